@@ -8,18 +8,18 @@ fn bench_disassemble(c: &mut Criterion) {
     c.bench_function("bench_disassemble_mega_bin", |b| {
         let input = fs::read("test-bin/mega.bin").unwrap();
         b.iter(|| {
-            std::hint::black_box(for _ in 1..=100 {
-                disassemble(&input);
-            });
+            for _ in 1..=100 {
+                std::hint::black_box(disassemble(&input));
+            }
         });
     });
 
     c.bench_function("bench_disassemble_giga_bin", |b| {
         let input = fs::read("test-bin/giga.bin").unwrap();
         b.iter(|| {
-            std::hint::black_box(for _ in 1..=10 {
-                disassemble(&input);
-            });
+            for _ in 1..=10 {
+                std::hint::black_box(disassemble(&input));
+            }
         });
     });
 }
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_image, AddressMode, Decoded, MemoryImage, Operation};
+
+/// Counts over a whole image
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct Statistics {
+    pub bytes: usize,
+    pub instructions: usize,
+    /// Bytes that don't decode into any instruction
+    pub unknown: usize,
+    pub illegal: usize,
+    /// Instructions cut short by the end of a segment
+    pub incomplete: usize,
+    pub operations: BTreeMap<String, usize>,
+    pub address_modes: BTreeMap<String, usize>,
+}
+
+pub fn statistics(image: &MemoryImage) -> Statistics {
+    statistics_of(&decode_image(image))
+}
+
+/// `statistics` of instructions that were already decoded
+pub(crate) fn statistics_of(decoded: &[Decoded]) -> Statistics {
+    let mut statistics = Statistics::default();
+
+    for decoded in decoded {
+        statistics.bytes += decoded.len();
+
+        if decoded.operation == Operation::Unknown {
+            statistics.unknown += 1;
+            continue;
+        }
+
+        statistics.instructions += 1;
+        if decoded.operation.is_illegal() {
+            statistics.illegal += 1;
+        }
+        if !decoded.is_satisfied() {
+            statistics.incomplete += 1;
+        }
+
+        *statistics
+            .operations
+            .entry(decoded.operation.to_string())
+            .or_default() += 1;
+        *statistics
+            .address_modes
+            .entry(format!("{:?}", decoded.address_mode))
+            .or_default() += 1;
+    }
+
+    statistics
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ReferenceKind {
+    Call,
+    Jump,
+    Branch,
+    Read,
+    Write,
+    /// Read-modify-write, like INC
+    Modify,
+}
+
+/// Instruction at `from` uses the address `to`
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CrossReference {
+    pub from: usize,
+    pub to: usize,
+    pub kind: ReferenceKind,
+}
+
+/// Every memory reference made by an instruction, sorted by the referenced
+/// address. Indirect jumps count as reads of their vector.
+pub fn cross_references(image: &MemoryImage) -> Vec<CrossReference> {
+    cross_references_of(&decode_image(image))
+}
+
+/// `cross_references` of instructions that were already decoded
+pub(crate) fn cross_references_of(decoded: &[Decoded]) -> Vec<CrossReference> {
+    let mut references: Vec<CrossReference> = decoded
+        .iter()
+        .filter_map(|decoded| {
+            let to = decoded.target()?;
+            let operation = decoded.operation;
+
+            let kind = match decoded.address_mode {
+                AddressMode::Indirect | AddressMode::AbsoluteXIndirect => ReferenceKind::Read,
+                _ if operation == Operation::JSR => ReferenceKind::Call,
+                _ if operation.is_jump() || operation == Operation::BRA => ReferenceKind::Jump,
+                _ if operation.is_branch() => ReferenceKind::Branch,
+                _ if operation.is_store() => ReferenceKind::Write,
+                _ if operation.is_modify() => ReferenceKind::Modify,
+                _ => ReferenceKind::Read,
+            };
+
+            Some(CrossReference {
+                from: decoded.offset,
+                to,
+                kind,
+            })
+        })
+        .collect();
+
+    references.sort_by_key(|reference| (reference.to, reference.from));
+    references
+}
+
+/// Straight line stretch of instructions that is only entered at the top
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// First address after the block
+    pub end: usize,
+    /// Blocks that execution can continue to. Subroutine calls continue after
+    /// the call, the callee is not a successor.
+    pub successors: Vec<usize>,
+}
+
+/// Splits the decoded image into basic blocks. Only targets that are inside
+/// the image show up as successors.
+pub fn control_flow(image: &MemoryImage) -> Vec<BasicBlock> {
+    control_flow_of(image, &decode_image(image))
+}
+
+/// `control_flow` of the instructions of the image, which were already
+/// decoded
+pub(crate) fn control_flow_of(image: &MemoryImage, decoded: &[Decoded]) -> Vec<BasicBlock> {
+    let starts: BTreeSet<usize> = decoded.iter().map(|decoded| decoded.offset).collect();
+
+    let mut leaders: BTreeSet<usize> = image
+        .entry_points()
+        .iter()
+        .map(|entry| entry.address)
+        .filter(|address| starts.contains(address))
+        .collect();
+
+    let mut previous_end = None;
+    for decoded in decoded {
+        if previous_end != Some(decoded.offset) {
+            leaders.insert(decoded.offset);
+        }
+        previous_end = Some(decoded.end());
+
+        if ends_block(decoded) {
+            leaders.insert(decoded.end());
+            leaders.extend(jump_target(decoded));
+        }
+    }
+    leaders.retain(|leader| starts.contains(leader));
+
+    let mut blocks: Vec<BasicBlock> = vec![];
+    for decoded in decoded {
+        match blocks.last_mut() {
+            Some(block) if block.end == decoded.offset && !leaders.contains(&decoded.offset) => {
+                block.end = decoded.end()
+            }
+            _ => blocks.push(BasicBlock {
+                start: decoded.offset,
+                end: decoded.end(),
+                successors: vec![],
+            }),
+        }
+
+        let block = blocks.last_mut().expect("a block was just pushed");
+        if ends_block(decoded) || leaders.contains(&decoded.end()) {
+            let mut successors = vec![];
+            if !decoded.operation.ends_flow() {
+                successors.push(decoded.end());
+            }
+            if decoded.operation != Operation::JSR {
+                successors.extend(jump_target(decoded));
+            }
+            successors.retain(|successor| starts.contains(successor));
+            successors.dedup();
+            block.successors = successors;
+        }
+    }
+
+    blocks
+}
+
+fn ends_block(decoded: &Decoded) -> bool {
+    let operation = decoded.operation;
+    operation.is_branch() || operation.is_jump() || operation.ends_flow() || operation.is_return()
+}
+
+fn jump_target(decoded: &Decoded) -> Option<usize> {
+    let is_transfer = decoded.operation.is_branch() || decoded.operation.is_jump();
+    let is_direct = matches!(
+        decoded.address_mode,
+        AddressMode::Absolute | AddressMode::Relative
+    );
+
+    (is_transfer && is_direct)
+        .then(|| decoded.target())
+        .flatten()
+}
+
+/// Graphviz rendering of the blocks, with the disassembly of each block as its
+/// node label
+pub fn control_flow_dot(image: &MemoryImage, blocks: &[BasicBlock]) -> String {
+    let instructions = crate::disassemble_image(image);
+    let mut lines = vec![
+        "digraph control_flow {".to_string(),
+        "    node [shape=box fontname=monospace];".to_string(),
+    ];
+
+    for block in blocks {
+        let body: String = instructions
+            .iter()
+            .filter(|instruction| (block.start..block.end).contains(&instruction.offset))
+            .map(|instruction| {
+                format!(
+                    "{:04X}  {} {}\\l",
+                    instruction.offset, instruction.operation, instruction.address
+                )
+                .replace('"', "\\\"")
+            })
+            .collect();
+
+        let title = match image.label(block.start) {
+            Some(label) => format!("{}:\\l", label.replace('"', "\\\"")),
+            None => String::new(),
+        };
+
+        lines.push(format!(
+            "    \"{:04X}\" [label=\"{}{}\"];",
+            block.start, title, body
+        ));
+        for successor in &block.successors {
+            lines.push(format!(
+                "    \"{:04X}\" -> \"{:04X}\";",
+                block.start, successor
+            ));
+        }
+    }
+
+    lines.push("}".to_string());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Segment;
+
+    use super::*;
+
+    fn image() -> MemoryImage {
+        MemoryImage::new().with_segment(Segment::new(
+            "main",
+            0x1000,
+            vec![
+                0xa2, 0x05, // 1000 LDX #$05
+                0xde, 0x00, 0x20, // 1002 DEC $2000,X
+                0x20, 0x10, 0x10, // 1005 JSR $1010
+                0xca, // 1008 DEX
+                0xd0, 0xf7, // 1009 BNE $1002
+                0x8d, 0x20, 0xd0, // 100B STA $D020
+                0x60, // 100E RTS
+                0x02, // 100F ???
+                0xad, 0x00, 0x20, // 1010 LDA $2000
+                0x4c, 0x0e, 0x10, // 1013 JMP $100E
+            ],
+        ))
+    }
+
+    #[test]
+    fn test_statistics() {
+        let statistics = statistics(&image());
+
+        assert_eq!(statistics.bytes, 22);
+        assert_eq!(statistics.instructions, 9);
+        assert_eq!(statistics.unknown, 1);
+        assert_eq!(statistics.operations["JSR"], 1);
+        assert_eq!(statistics.address_modes["Absolute"], 4);
+    }
+
+    #[test]
+    fn test_cross_references() {
+        let references = cross_references(&image());
+        let kinds: Vec<(usize, usize, ReferenceKind)> = references
+            .into_iter()
+            .map(|reference| (reference.from, reference.to, reference.kind))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                (0x1009, 0x1002, ReferenceKind::Branch),
+                (0x1013, 0x100e, ReferenceKind::Jump),
+                (0x1005, 0x1010, ReferenceKind::Call),
+                (0x1002, 0x2000, ReferenceKind::Modify),
+                (0x1010, 0x2000, ReferenceKind::Read),
+                (0x100b, 0xd020, ReferenceKind::Write),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let blocks: Vec<(usize, usize, Vec<usize>)> = control_flow(&image())
+            .into_iter()
+            .map(|block| (block.start, block.end, block.successors))
+            .collect();
+
+        assert_eq!(
+            blocks,
+            vec![
+                (0x1000, 0x1002, vec![0x1002]),
+                (0x1002, 0x1008, vec![0x1008]),
+                (0x1008, 0x100b, vec![0x100b, 0x1002]),
+                (0x100b, 0x100e, vec![0x100e]),
+                (0x100e, 0x100f, vec![]),
+                (0x100f, 0x1010, vec![0x1010]),
+                (0x1010, 0x1016, vec![0x100e]),
+            ]
+        );
+    }
+}
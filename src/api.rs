@@ -1,61 +1,1216 @@
-use crate::{disassemble, Instruction};
-use poem_openapi::{payload::Json, ApiResponse, Object, OpenApi};
+use crate::{
+    assembly_source, disassemble_image, disassemble_range, instruction_at, into_instructions,
+    load_as, load_at, parse_hex_text, Cpu, Dialect, Format, FormatError, Instruction, Job, JobKind,
+    Jobs, Limits, MemoryImage, Project, ProjectError, Property, QueueFull, Store,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use poem::{http::StatusCode, Body};
+use poem_openapi::{
+    param::{Path, Query},
+    payload::{Binary, EventStream, Json},
+    types::{multipart::Upload, Any},
+    ApiResponse, Enum, Multipart, Object, OpenApi,
+};
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+use tokio::{
+    io::AsyncWriteExt,
+    runtime::Handle,
+    sync::{oneshot, OwnedSemaphorePermit, Semaphore},
+};
 use tracing::{event, instrument, Level};
 
-#[derive(Debug, Serialize, Deserialize, Object)]
+/// Instructions serialised before a chunk of a stream is written
+const STREAM_CHUNK: usize = 256;
+
+/// File to disassemble, given as exactly one of `bytes`, `hex`, `base64` or
+/// the `id` of a stored binary
+#[derive(Debug, Default, Serialize, Deserialize, Object)]
 pub struct Input {
+    #[oai(default)]
+    #[serde(default)]
+    bytes: Vec<u8>,
+    /// Hex text such as "A9 BD", "$A9,$BD", a C array or `.byte` lines
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base64: Option<String>,
+    /// ID returned by `/json/binaries`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<Options>,
+}
+
+impl Input {
+    fn decode(&self, store: &Store) -> Result<Source, Failure> {
+        let given = [self.hex.is_some(), self.base64.is_some(), self.id.is_some()];
+        if given.iter().filter(|&&given| given).count() + usize::from(!self.bytes.is_empty()) > 1 {
+            return Err(Failure::BadRequest(
+                "give only one of bytes, hex, base64 and id".into(),
+            ));
+        }
+
+        if let Some(hex) = &self.hex {
+            parse_hex_text(hex)
+                .map(Source::from)
+                .map_err(|err| Failure::Unprocessable(err.to_string()))
+        } else if let Some(base64) = &self.base64 {
+            STANDARD
+                .decode(base64.trim())
+                .map(Source::from)
+                .map_err(|err| Failure::Unprocessable(format!("Invalid base64: {}", err)))
+        } else if let Some(id) = &self.id {
+            Ok(Source {
+                bytes: stored(store, id)?.to_vec(),
+                project: store.project(id),
+            })
+        } else {
+            Ok(self.bytes.clone().into())
+        }
+    }
+}
+
+/// Bytes to disassemble, with the project of the binary when it is a stored
+/// one
+#[derive(Debug, Default)]
+struct Source {
+    bytes: Vec<u8>,
+    project: Option<Project>,
+}
+
+impl From<Vec<u8>> for Source {
+    fn from(bytes: Vec<u8>) -> Self {
+        Source {
+            bytes,
+            project: None,
+        }
+    }
+}
+
+fn stored(store: &Store, id: &str) -> Result<Arc<Vec<u8>>, Failure> {
+    store.get(id).ok_or_else(|| no_binary(id))
+}
+
+fn no_binary(id: &str) -> Failure {
+    Failure::NotFound(format!("There is no binary with the ID {}", id))
+}
+
+/// Binary kept by the server
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Object, Clone)]
+pub struct StoredBinary {
+    /// SHA-256 of the bytes, the same bytes always get the same ID
+    pub id: String,
+    pub size: usize,
+}
+
+#[derive(Debug, PartialEq, ApiResponse)]
+#[oai(bad_request_handler = "bad_store_request")]
+pub enum StoreOutput {
+    #[oai(status = 200)]
+    Ok(Json<StoredBinary>),
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// The binary is larger than the server accepts
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_store_request(err: poem::Error) -> StoreOutput {
+    StoreOutput::BadRequest(Json(Failure::BadRequest(err.to_string()).body()))
+}
+
+/// Stored binary that has a project
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Object, Clone)]
+pub struct ProjectSummary {
+    /// ID of the binary
+    pub id: String,
+    pub labels: usize,
+    pub comments: usize,
+    pub data_ranges: usize,
+}
+
+#[derive(Debug, ApiResponse)]
+#[oai(bad_request_handler = "bad_project_request")]
+pub enum ProjectOutput {
+    #[oai(status = 200)]
+    Ok(Json<Any<Project>>),
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// There is no stored binary with the ID, or it has no project
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    /// The project is larger than the server accepts
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    /// The project was made for another binary
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_project_request(err: poem::Error) -> ProjectOutput {
+    Failure::BadRequest(err.to_string()).into()
+}
+
+impl From<Result<Project, Failure>> for ProjectOutput {
+    fn from(result: Result<Project, Failure>) -> Self {
+        match result {
+            Ok(project) => ProjectOutput::Ok(Json(Any(project))),
+            Err(failure) => failure.into(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, ApiResponse)]
+pub enum DeleteOutput {
+    #[oai(status = 204)]
+    Deleted,
+    /// There is no stored binary with the ID, or it has no project
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn no_project(id: &str) -> Failure {
+    Failure::NotFound(format!("The binary {} has no project", id))
+}
+
+/// Analysis to run in the background
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct JobInput {
+    kind: JobKind,
+    input: Input,
+    /// Input that `Diff` compares the first one to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    other: Option<Input>,
+}
+
+#[derive(Debug, PartialEq, ApiResponse)]
+#[oai(bad_request_handler = "bad_job_request")]
+pub enum JobOutput {
+    #[oai(status = 200)]
+    Ok(Json<Job>),
+    /// The job was queued, its progress can be followed by its ID
+    #[oai(status = 202)]
+    Accepted(Json<Job>),
+    /// The request could not be read, `other` is missing from a diff, or an
+    /// input looked like a known container format but its header is broken
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// There is no job, or no stored binary, with the ID
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    /// An input is larger than the server accepts, or the inputs of a diff
+    /// are together
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    /// The hex or base64 text is invalid
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<ErrorBody>),
+    /// As many jobs as the server queues already wait for a worker
+    #[oai(status = 429)]
+    TooManyRequests(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_job_request(err: poem::Error) -> JobOutput {
+    Failure::BadRequest(err.to_string()).into()
+}
+
+#[derive(ApiResponse)]
+pub enum JobEventsOutput {
+    /// Server-sent events with the state of the job each time it changes,
+    /// until it finishes
+    #[oai(status = 200)]
+    Ok(EventStream<BoxStream<'static, Job>>),
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+}
+
+fn no_job(id: &str) -> Failure {
+    Failure::NotFound(format!("There is no job with the ID {}", id))
+}
+
+/// Window of a listing
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Object, Clone)]
+pub struct InstructionPage {
+    pub instructions: Vec<Instruction>,
+    /// Pass as `cursor`, with the other parameters unchanged, for the next
+    /// page. Missing on the last page.
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+#[derive(Debug, PartialEq, ApiResponse)]
+#[oai(bad_request_handler = "bad_page_request")]
+pub enum InstructionPageOutput {
+    #[oai(status = 200)]
+    Ok(Json<InstructionPage>),
+    /// The cursor is invalid, or the binary looked like a known container
+    /// format but its header is broken
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// There is no stored binary with the ID
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_page_request(err: poem::Error) -> InstructionPageOutput {
+    Failure::BadRequest(err.to_string()).into()
+}
+
+#[derive(Debug, PartialEq, ApiResponse)]
+#[oai(bad_request_handler = "bad_lookup_request")]
+pub enum InstructionOutput {
+    #[oai(status = 200)]
+    Ok(Json<Instruction>),
+    /// The binary looked like a known container format but its header is
+    /// broken
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// There is no stored binary with the ID, or no instruction at the
+    /// address
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_lookup_request(err: poem::Error) -> InstructionOutput {
+    Failure::BadRequest(err.to_string()).into()
+}
+
+/// Instructions on a page when the client doesn't say
+const PAGE_SIZE: usize = 256;
+const MAX_PAGE_SIZE: usize = 10_000;
+
+/// Syntax of formatted output
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize, Enum)]
+pub enum OutputDialect {
+    /// Offsets, bytes and instructions
+    #[default]
+    Listing,
+    Ca65,
+    Acme,
+}
+
+/// How the input is loaded and written out
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct Options {
+    /// Load address of flat binaries, containers have their own
+    origin: Option<usize>,
+    cpu: Option<Cpu>,
+    /// Container format of the input, detected from its header by default
+    input_format: Option<Format>,
+    /// Only used by the formatted endpoints
+    dialect: Option<OutputDialect>,
+}
+
+impl Options {
+    fn image(&self, bytes: &[u8], project: Option<&Project>) -> Result<MemoryImage, FormatError> {
+        annotated_image(bytes, self.origin, self.cpu, self.input_format, project)
+    }
+}
+
+/// Image of the bytes with the annotations of the project, the origin and
+/// cpu win over the ones of the project when they are given
+pub(crate) fn annotated_image(
+    bytes: &[u8],
+    origin: Option<usize>,
+    cpu: Option<Cpu>,
+    input_format: Option<Format>,
+    project: Option<&Project>,
+) -> Result<MemoryImage, FormatError> {
+    let origin = origin
+        .or(project.map(|project| project.origin))
+        .unwrap_or(0);
+    let mut image = match input_format {
+        Some(format) => load_as(bytes, format, origin)?,
+        None => load_at(bytes, origin)?,
+    };
+    if let Some(project) = project {
+        project.apply(&mut image);
+    }
+    if let Some(cpu) = cpu {
+        image.set_cpu(cpu);
+    }
+    Ok(image)
+}
+
+#[derive(Debug, Multipart)]
+pub struct UploadInput {
+    file: Upload,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct NewProjectInput {
+    /// Path of the input, relative to where the project file will be saved
+    input: String,
     bytes: Vec<u8>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct ProjectInput {
+    bytes: Vec<u8>,
+    /// Project file as saved by the CLI or `/json/project/new`
+    project: Any<Project>,
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Object, Clone)]
+pub struct ErrorBody {
+    /// Stable identifier of the kind of error: `bad_request`, `not_found`,
+    /// `payload_too_large`, `unprocessable`, `internal_error` or `timeout`
+    pub code: String,
+    /// Explanation for people
+    pub message: String,
+}
+
+impl ErrorBody {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        ErrorBody {
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn too_large(max_payload: usize) -> Self {
+        Self::new(
+            "payload_too_large",
+            format!(
+                "The input is larger than the limit of {} bytes",
+                max_payload
+            ),
+        )
+    }
+}
+
+/// Why a request failed, before it is turned into a response of an endpoint
+#[derive(Debug)]
+pub(crate) enum Failure {
+    BadRequest(String),
+    NotFound(String),
+    TooLarge(usize),
+    Unprocessable(String),
+    Internal(String),
+}
+
+impl Failure {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Failure::BadRequest(_) => "bad_request",
+            Failure::NotFound(_) => "not_found",
+            Failure::TooLarge(_) => "payload_too_large",
+            Failure::Unprocessable(_) => "unprocessable",
+            Failure::Internal(_) => "internal_error",
+        }
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            Failure::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Failure::NotFound(_) => StatusCode::NOT_FOUND,
+            Failure::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Failure::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Failure::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub(crate) fn body(&self) -> ErrorBody {
+        match self {
+            Failure::TooLarge(max_payload) => ErrorBody::too_large(*max_payload),
+            Failure::BadRequest(message)
+            | Failure::NotFound(message)
+            | Failure::Unprocessable(message)
+            | Failure::Internal(message) => ErrorBody::new(self.code(), message.clone()),
+        }
+    }
+}
+
+/// Implements `From<Failure>` for responses that have a variant for every
+/// status a `Failure` can have, picking the variant by `Failure::status`
+macro_rules! from_failure {
+    ($($output:ident),+) => {$(
+        impl From<Failure> for $output {
+            fn from(failure: Failure) -> Self {
+                let body = Json(failure.body());
+                match failure.status() {
+                    StatusCode::NOT_FOUND => $output::NotFound(body),
+                    StatusCode::PAYLOAD_TOO_LARGE => $output::PayloadTooLarge(body),
+                    StatusCode::UNPROCESSABLE_ENTITY => $output::UnprocessableEntity(body),
+                    StatusCode::INTERNAL_SERVER_ERROR => $output::InternalServerError(body),
+                    _ => $output::BadRequest(body),
+                }
+            }
+        }
+    )+};
+}
+
+from_failure!(
+    ProjectOutput,
+    JobOutput,
+    InstructionPageOutput,
+    InstructionOutput,
+    StructuredOutput,
+    FormattedOutput,
+    StreamOutput
+);
+
+impl From<FormatError> for Failure {
+    fn from(err: FormatError) -> Self {
+        Failure::BadRequest(err.to_string())
+    }
+}
+
+impl From<ProjectError> for Failure {
+    fn from(err: ProjectError) -> Self {
+        match err {
+            ProjectError::Format(err) => err.into(),
+            err => Failure::Unprocessable(err.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, ApiResponse)]
+#[oai(bad_request_handler = "bad_structured_request")]
 pub enum StructuredOutput {
     #[oai(status = 200)]
     Ok(Json<StructuredDisassembly>),
+    /// The request could not be read, or the input looked like a known
+    /// container format but its header is broken
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// There is no stored binary with the ID
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    /// The input is larger than the server accepts
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    /// The hex or base64 text is invalid, or the input is not the one the
+    /// project was made for
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_structured_request(err: poem::Error) -> StructuredOutput {
+    Failure::BadRequest(err.to_string()).into()
+}
+
+impl From<Result<StructuredDisassembly, Failure>> for StructuredOutput {
+    fn from(result: Result<StructuredDisassembly, Failure>) -> Self {
+        match result {
+            Ok(disassembly) => StructuredOutput::Ok(Json(disassembly)),
+            Err(failure) => failure.into(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Object, Clone)]
 pub struct StructuredDisassembly {
-    instructions: Vec<Instruction>,
+    /// Header fields of container formats such as SID and NSF
+    #[serde(default)]
+    pub metadata: Vec<Property>,
+    pub instructions: Vec<Instruction>,
 }
 
 #[derive(Debug, PartialEq, ApiResponse)]
+#[oai(bad_request_handler = "bad_formatted_request")]
 pub enum FormattedOutput {
     #[oai(status = 200)]
     Ok(Json<FormattedDisassembly>),
+    /// The request could not be read, or the input looked like a known
+    /// container format but its header is broken
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// There is no stored binary with the ID
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    /// The input is larger than the server accepts
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    /// The hex or base64 text is invalid
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_formatted_request(err: poem::Error) -> FormattedOutput {
+    Failure::BadRequest(err.to_string()).into()
+}
+
+impl From<Result<FormattedDisassembly, Failure>> for FormattedOutput {
+    fn from(result: Result<FormattedDisassembly, Failure>) -> Self {
+        match result {
+            Ok(disassembly) => FormattedOutput::Ok(Json(disassembly)),
+            Err(failure) => failure.into(),
+        }
+    }
+}
+
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "bad_stream_request")]
+pub enum StreamOutput {
+    /// One JSON instruction per line, written while the input is being
+    /// disassembled
+    #[oai(status = 200, content_type = "application/x-ndjson")]
+    Ok(Binary<Body>),
+    /// The request could not be read, or the input looked like a known
+    /// container format but its header is broken
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// There is no stored binary with the ID
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    /// The input is larger than the server accepts
+    #[oai(status = 413)]
+    PayloadTooLarge(Json<ErrorBody>),
+    /// The hex or base64 text is invalid
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<ErrorBody>),
+    #[oai(status = 500)]
+    InternalServerError(Json<ErrorBody>),
+}
+
+fn bad_stream_request(err: poem::Error) -> StreamOutput {
+    Failure::BadRequest(err.to_string()).into()
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Object, Clone)]
 pub struct FormattedDisassembly {
+    /// Header fields of container formats such as SID and NSF
+    #[serde(default)]
+    metadata: Vec<Property>,
     instructions: Vec<String>,
 }
 
-#[derive(Debug)]
-pub struct Api;
+#[derive(Debug, Default)]
+pub struct Api {
+    limits: Limits,
+    store: Arc<Store>,
+    jobs: Jobs,
+}
+
+impl Api {
+    pub fn new(limits: Limits) -> Self {
+        Api {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Jobs that may run at once, one per core otherwise
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.jobs = Jobs::new(workers);
+        self
+    }
+
+    /// Shares the store with other endpoints, the API has its own otherwise
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = store;
+        self
+    }
+
+    fn check_size(&self, bytes: &[u8]) -> Result<(), Failure> {
+        match bytes.len() > self.limits.max_payload {
+            true => Err(Failure::TooLarge(self.limits.max_payload)),
+            false => Ok(()),
+        }
+    }
+
+    /// Images of the inputs of the job, checked against its limits
+    fn job_images(&self, payload: &JobInput) -> Result<Vec<MemoryImage>, Failure> {
+        let mut images = vec![self.job_image(&payload.input)?];
+        if payload.kind.inputs() > 1 {
+            let other = payload.other.as_ref().ok_or_else(|| {
+                Failure::BadRequest(format!("{:?} needs the other input", payload.kind))
+            })?;
+            images.push(self.job_image(other)?);
+        }
+        if let Some(max_bytes) = payload.kind.max_bytes() {
+            let bytes: usize = images
+                .iter()
+                .flat_map(|image| image.segments())
+                .map(|segment| segment.bytes.len())
+                .sum();
+            if bytes > max_bytes {
+                return Err(Failure::TooLarge(max_bytes));
+            }
+        }
+        Ok(images)
+    }
+
+    fn job_image(&self, input: &Input) -> Result<MemoryImage, Failure> {
+        let source = input.decode(&self.store)?;
+        self.check_size(&source.bytes)?;
+        let options = input.options.clone().unwrap_or_default();
+        Ok(options.image(&source.bytes, source.project.as_ref())?)
+    }
+
+    async fn structured(
+        &self,
+        source: Result<Source, Failure>,
+        options: Options,
+    ) -> StructuredOutput {
+        let result = async {
+            let source = source?;
+            self.check_size(&source.bytes)?;
+            blocking(move || {
+                let image = options.image(&source.bytes, source.project.as_ref())?;
+                Ok(StructuredDisassembly {
+                    metadata: image.metadata().to_vec(),
+                    instructions: disassemble_image(&image),
+                })
+            })
+            .await
+        };
+        result.await.into()
+    }
+
+    async fn formatted(
+        &self,
+        source: Result<Source, Failure>,
+        options: Options,
+    ) -> FormattedOutput {
+        let result = async {
+            let source = source?;
+            self.check_size(&source.bytes)?;
+            blocking(move || {
+                let image = options.image(&source.bytes, source.project.as_ref())?;
+                let instructions = match options.dialect.unwrap_or_default() {
+                    OutputDialect::Listing => disassemble_image(&image)
+                        .into_iter()
+                        .map(|instruction| instruction.to_string())
+                        .collect(),
+                    OutputDialect::Ca65 => source_lines(&image, Dialect::Ca65),
+                    OutputDialect::Acme => source_lines(&image, Dialect::Acme),
+                };
+                Ok(FormattedDisassembly {
+                    metadata: image.metadata().to_vec(),
+                    instructions,
+                })
+            })
+            .await
+        };
+        result.await.into()
+    }
+}
+
+/// Streams the instructions as NDJSON. The instructions are decoded on a
+/// blocking thread that waits whenever the client falls behind, and that
+/// holds a permit like `blocking` does until the stream ends. The image is
+/// loaded there before the body is handed out, so that broken input is
+/// still answered with an error status.
+async fn stream(source: Source, options: Options) -> Result<Body, Failure> {
+    let permit = permit().await;
+    let (mut writer, reader) = tokio::io::duplex(64 * 1024);
+    let (loaded, image_loaded) = oneshot::channel();
+    let runtime = Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let image = match options.image(&source.bytes, source.project.as_ref()) {
+            Ok(image) => {
+                let _ = loaded.send(Ok(()));
+                image
+            }
+            Err(failure) => {
+                let _ = loaded.send(Err(failure));
+                return;
+            }
+        };
+        let mut instructions = into_instructions(image).peekable();
+        while instructions.peek().is_some() {
+            let mut chunk = vec![];
+            for instruction in instructions.by_ref().take(STREAM_CHUNK) {
+                serde_json::to_writer(&mut chunk, &instruction)
+                    .expect("instructions always serialize");
+                chunk.push(b'\n');
+            }
+            // The client went away, nobody wants the rest
+            if runtime.block_on(writer.write_all(&chunk)).is_err() {
+                break;
+            }
+        }
+    });
+
+    image_loaded
+        .await
+        .map_err(|err| Failure::Internal(format!("Disassembling failed: {}", err)))??;
+    Ok(Body::from_async_read(reader))
+}
+
+/// Runs the work off the async threads, so that the request timeout can
+/// answer while it is still going. A panic becomes an internal error.
+///
+/// Work that the timeout gave up on can't be stopped, so it holds one of the
+/// permits, one per CPU, until it ends, and later requests wait for a permit
+/// instead of piling more work onto the blocking threads.
+pub(crate) async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, Failure> + Send + 'static,
+) -> Result<T, Failure> {
+    let permit = permit().await;
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        work()
+    })
+    .await
+    .map_err(|err| Failure::Internal(format!("Disassembling failed: {}", err)))?
+}
+
+/// One of the permits for work on the blocking threads, shared by everything
+/// that decodes
+async fn permit() -> OwnedSemaphorePermit {
+    static PERMITS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+    let permits = PERMITS.get_or_init(|| {
+        let cpus = std::thread::available_parallelism().map_or(1, usize::from);
+        Arc::new(Semaphore::new(cpus))
+    });
+    permits
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("the semaphore is never closed")
+}
+
+fn source_lines(image: &MemoryImage, dialect: Dialect) -> Vec<String> {
+    assembly_source(image, dialect)
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+async fn read_upload(upload: Upload, limits: &Limits) -> Result<Vec<u8>, Failure> {
+    if upload.size() > limits.max_payload {
+        return Err(Failure::TooLarge(limits.max_payload));
+    }
+    upload
+        .into_vec()
+        .await
+        .map_err(|err| Failure::BadRequest(err.to_string()))
+}
 
 #[OpenApi(prefix_path = "/json")]
 impl Api {
-    #[instrument]
+    #[instrument(skip(self, payload))]
     #[oai(path = "/structured", method = "post")]
     pub async fn structured_handler(&self, payload: Json<Input>) -> StructuredOutput {
         event!(Level::INFO, "Structured disassembling Json");
-        let instructions = disassemble(&payload.bytes);
-
-        StructuredOutput::Ok(Json(StructuredDisassembly { instructions }))
+        let options = payload.options.clone().unwrap_or_default();
+        self.structured(payload.decode(&self.store), options).await
     }
 
-    #[instrument]
+    #[instrument(skip(self, payload))]
     #[oai(path = "/formatted", method = "post")]
     pub async fn formatted_handler(&self, payload: Json<Input>) -> FormattedOutput {
         event!(Level::INFO, "Formatted disassembling Json");
-        let structured = disassemble(&payload.bytes);
+        let options = payload.options.clone().unwrap_or_default();
+        self.formatted(payload.decode(&self.store), options).await
+    }
+
+    /// Disassembles the request body as it is, without any JSON around it
+    #[instrument(skip_all)]
+    #[oai(path = "/structured/binary", method = "post")]
+    pub async fn structured_binary_handler(
+        &self,
+        payload: Binary<Vec<u8>>,
+        origin: Query<Option<usize>>,
+        cpu: Query<Option<Cpu>>,
+        input_format: Query<Option<Format>>,
+    ) -> StructuredOutput {
+        event!(Level::INFO, "Structured disassembling binary");
+        let options = Options {
+            origin: origin.0,
+            cpu: cpu.0,
+            input_format: input_format.0,
+            dialect: None,
+        };
+        self.structured(Ok(payload.0.into()), options).await
+    }
+
+    /// Disassembles the request body as it is, without any JSON around it
+    #[instrument(skip_all)]
+    #[oai(path = "/formatted/binary", method = "post")]
+    pub async fn formatted_binary_handler(
+        &self,
+        payload: Binary<Vec<u8>>,
+        origin: Query<Option<usize>>,
+        cpu: Query<Option<Cpu>>,
+        input_format: Query<Option<Format>>,
+        dialect: Query<Option<OutputDialect>>,
+    ) -> FormattedOutput {
+        event!(Level::INFO, "Formatted disassembling binary");
+        let options = Options {
+            origin: origin.0,
+            cpu: cpu.0,
+            input_format: input_format.0,
+            dialect: dialect.0,
+        };
+        self.formatted(Ok(payload.0.into()), options).await
+    }
+
+    /// Disassembles a file uploaded from a form
+    #[instrument(skip_all)]
+    #[oai(path = "/structured/upload", method = "post")]
+    pub async fn structured_upload_handler(
+        &self,
+        payload: UploadInput,
+        origin: Query<Option<usize>>,
+        cpu: Query<Option<Cpu>>,
+        input_format: Query<Option<Format>>,
+    ) -> StructuredOutput {
+        event!(Level::INFO, "Structured disassembling upload");
+        let options = Options {
+            origin: origin.0,
+            cpu: cpu.0,
+            input_format: input_format.0,
+            dialect: None,
+        };
+        let bytes = read_upload(payload.file, &self.limits).await;
+        self.structured(bytes.map(Source::from), options).await
+    }
+
+    /// Disassembles a file uploaded from a form
+    #[instrument(skip_all)]
+    #[oai(path = "/formatted/upload", method = "post")]
+    pub async fn formatted_upload_handler(
+        &self,
+        payload: UploadInput,
+        origin: Query<Option<usize>>,
+        cpu: Query<Option<Cpu>>,
+        input_format: Query<Option<Format>>,
+        dialect: Query<Option<OutputDialect>>,
+    ) -> FormattedOutput {
+        event!(Level::INFO, "Formatted disassembling upload");
+        let options = Options {
+            origin: origin.0,
+            cpu: cpu.0,
+            input_format: input_format.0,
+            dialect: dialect.0,
+        };
+        let bytes = read_upload(payload.file, &self.limits).await;
+        self.formatted(bytes.map(Source::from), options).await
+    }
+
+    /// Same as `/structured`, but the instructions are sent as newline
+    /// delimited JSON while the input is being disassembled, which keeps
+    /// memory use flat for big inputs
+    #[instrument(skip(self, payload))]
+    #[oai(path = "/structured/stream", method = "post")]
+    pub async fn structured_stream_handler(&self, payload: Json<Input>) -> StreamOutput {
+        event!(Level::INFO, "Streaming structured disassembly");
+        let options = payload.options.clone().unwrap_or_default();
+        let result = async {
+            let source = payload.decode(&self.store)?;
+            self.check_size(&source.bytes)?;
+            stream(source, options).await
+        };
+
+        match result.await {
+            Ok(body) => StreamOutput::Ok(Binary(body)),
+            Err(failure) => failure.into(),
+        }
+    }
 
-        FormattedOutput::Ok(Json(FormattedDisassembly {
-            instructions: structured
+    /// Keeps the request body, so that later requests can refer to it by ID
+    #[instrument(skip_all)]
+    #[oai(path = "/binaries", method = "post")]
+    pub async fn store_handler(&self, payload: Binary<Vec<u8>>) -> StoreOutput {
+        event!(Level::INFO, "Storing binary");
+        if let Err(failure) = self.check_size(&payload) {
+            return StoreOutput::PayloadTooLarge(Json(failure.body()));
+        }
+
+        let size = payload.len();
+        match self.store.insert(payload.0) {
+            Ok(id) => StoreOutput::Ok(Json(StoredBinary { id, size })),
+            Err(err) => StoreOutput::InternalServerError(Json(
+                Failure::Internal(format!("Storing failed: {}", err)).body(),
+            )),
+        }
+    }
+
+    /// Removes the binary along with its project, permalinks to it stop
+    /// working
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/binaries/:id", method = "delete")]
+    pub async fn delete_binary_handler(&self, id: Path<String>) -> DeleteOutput {
+        event!(Level::INFO, "Deleting binary");
+        match self.store.remove(&id) {
+            Ok(true) => DeleteOutput::Deleted,
+            Ok(false) => DeleteOutput::NotFound(Json(no_binary(&id).body())),
+            Err(err) => DeleteOutput::InternalServerError(Json(
+                Failure::Internal(format!("Deleting the binary failed: {}", err)).body(),
+            )),
+        }
+    }
+
+    /// Instructions of a stored binary that start between `start` and `end`,
+    /// at most `count` of them. Instruction boundaries are the same as in the
+    /// full listing.
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/binaries/:id/instructions", method = "get")]
+    pub async fn page_handler(
+        &self,
+        id: Path<String>,
+        start: Query<Option<usize>>,
+        end: Query<Option<usize>>,
+        count: Query<Option<usize>>,
+        cursor: Query<Option<String>>,
+        origin: Query<Option<usize>>,
+        cpu: Query<Option<Cpu>>,
+        input_format: Query<Option<Format>>,
+    ) -> InstructionPageOutput {
+        event!(Level::INFO, "Paging instructions");
+        let options = Options {
+            origin: origin.0,
+            cpu: cpu.0,
+            input_format: input_format.0,
+            dialect: None,
+        };
+        let result = async {
+            let bytes = stored(&self.store, &id)?;
+            let project = self.store.project(&id);
+            let start = match cursor.0 {
+                Some(cursor) => usize::from_str_radix(&cursor, 16)
+                    .map_err(|_| Failure::BadRequest(format!("Invalid cursor {}", cursor)))?,
+                None => start.unwrap_or(0),
+            };
+            let range = start..end.unwrap_or(usize::MAX);
+            let count = count.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+            blocking(move || {
+                let image = options.image(&bytes, project.as_ref())?;
+                // One more than asked for tells whether there is a next page
+                let mut instructions: Vec<Instruction> =
+                    disassemble_range(&image, range).take(count + 1).collect();
+                let next = match instructions.len() > count {
+                    true => instructions.pop().map(|next| format!("{:x}", next.offset)),
+                    false => None,
+                };
+                Ok(InstructionPage { instructions, next })
+            })
+            .await
+        };
+
+        match result.await {
+            Ok(page) => InstructionPageOutput::Ok(Json(page)),
+            Err(failure) => failure.into(),
+        }
+    }
+
+    /// Instruction of a stored binary that the address is a part of
+    #[instrument(skip_all, fields(id = %id.0, address = address.0))]
+    #[oai(path = "/binaries/:id/at/:address", method = "get")]
+    pub async fn lookup_handler(
+        &self,
+        id: Path<String>,
+        address: Path<usize>,
+        origin: Query<Option<usize>>,
+        cpu: Query<Option<Cpu>>,
+        input_format: Query<Option<Format>>,
+    ) -> InstructionOutput {
+        event!(Level::INFO, "Looking up instruction");
+        let options = Options {
+            origin: origin.0,
+            cpu: cpu.0,
+            input_format: input_format.0,
+            dialect: None,
+        };
+        let address = address.0;
+        let result = async {
+            let bytes = stored(&self.store, &id)?;
+            let project = self.store.project(&id);
+            blocking(move || {
+                let image = options.image(&bytes, project.as_ref())?;
+                instruction_at(&image, address).ok_or_else(|| {
+                    Failure::NotFound(format!("There is no instruction at {:04X}", address))
+                })
+            })
+            .await
+        };
+
+        match result.await {
+            Ok(instruction) => InstructionOutput::Ok(Json(instruction)),
+            Err(failure) => failure.into(),
+        }
+    }
+
+    /// Stored binaries that have a project
+    #[instrument(skip_all)]
+    #[oai(path = "/projects", method = "get")]
+    pub async fn projects_handler(&self) -> Json<Vec<ProjectSummary>> {
+        event!(Level::INFO, "Listing projects");
+        let projects = self.store.projects();
+        Json(
+            projects
                 .into_iter()
-                .map(|instruction| instruction.to_string())
+                .map(|(id, project)| ProjectSummary {
+                    id,
+                    labels: project.labels.len(),
+                    comments: project.comments.len(),
+                    data_ranges: project.data_ranges.len(),
+                })
                 .collect(),
-        }))
+        )
+    }
+
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/projects/:id", method = "get")]
+    pub async fn project_handler(&self, id: Path<String>) -> ProjectOutput {
+        event!(Level::INFO, "Fetching project");
+        self.store
+            .project(&id)
+            .ok_or_else(|| no_project(&id))
+            .into()
+    }
+
+    /// Attaches the project to the stored binary, replacing the one it had.
+    /// Disassembling the binary by its ID applies the project from then on.
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/projects/:id", method = "put")]
+    pub async fn update_project_handler(
+        &self,
+        id: Path<String>,
+        payload: Json<Any<Project>>,
+    ) -> ProjectOutput {
+        event!(Level::INFO, "Updating project");
+        let Json(Any(project)) = payload;
+        let result = stored(&self.store, &id).and_then(|bytes| {
+            project.check(&project.input, &bytes)?;
+            self.store
+                .set_project(&id, project.clone())
+                .map_err(|err| Failure::Internal(format!("Saving the project failed: {}", err)))?;
+            Ok(project)
+        });
+        result.into()
+    }
+
+    /// Removes the project, the binary itself stays
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/projects/:id", method = "delete")]
+    pub async fn delete_project_handler(&self, id: Path<String>) -> DeleteOutput {
+        event!(Level::INFO, "Deleting project");
+        match self.store.remove_project(&id) {
+            Ok(true) => DeleteOutput::Deleted,
+            Ok(false) => DeleteOutput::NotFound(Json(no_project(&id).body())),
+            Err(err) => DeleteOutput::InternalServerError(Json(
+                Failure::Internal(format!("Deleting the project failed: {}", err)).body(),
+            )),
+        }
+    }
+
+    /// Queues an analysis that may take longer than a request is allowed to
+    #[instrument(skip_all)]
+    #[oai(path = "/jobs", method = "post")]
+    pub async fn submit_job_handler(&self, payload: Json<JobInput>) -> JobOutput {
+        event!(Level::INFO, "Submitting {:?} job", payload.kind);
+        let images = match self.job_images(&payload) {
+            Ok(images) => images,
+            Err(failure) => return failure.into(),
+        };
+        match self.jobs.submit(payload.kind, images) {
+            Ok(job) => JobOutput::Accepted(Json(job)),
+            Err(QueueFull) => JobOutput::TooManyRequests(Json(ErrorBody::new(
+                "too_many_requests",
+                "Too many jobs are waiting for a worker, try again later",
+            ))),
+        }
+    }
+
+    /// State of the job, with the result once it is done
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/jobs/:id", method = "get")]
+    pub async fn job_handler(&self, id: Path<String>) -> JobOutput {
+        match self.jobs.get(&id) {
+            Some(job) => JobOutput::Ok(Json(job)),
+            None => no_job(&id).into(),
+        }
+    }
+
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/jobs/:id/events", method = "get")]
+    pub async fn job_events_handler(&self, id: Path<String>) -> JobEventsOutput {
+        event!(Level::INFO, "Subscribing to job");
+        let Some(receiver) = self.jobs.subscribe(&id) else {
+            return JobEventsOutput::NotFound(Json(no_job(&id).body()));
+        };
+
+        // The current state goes out first, then every change after it
+        let events = stream::unfold((Some(receiver), true), |(receiver, first)| async move {
+            let mut receiver = receiver?;
+            if !first && receiver.changed().await.is_err() {
+                return None;
+            }
+            let job = receiver.borrow_and_update().clone();
+            let receiver = (!job.status.is_finished()).then_some(receiver);
+            Some((job, (receiver, false)))
+        });
+        JobEventsOutput::Ok(EventStream::new(events.boxed()))
+    }
+
+    /// Cancels the job unless it has finished already
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/jobs/:id", method = "delete")]
+    pub async fn cancel_job_handler(&self, id: Path<String>) -> JobOutput {
+        event!(Level::INFO, "Cancelling job");
+        match self.jobs.cancel(&id) {
+            Some(job) => JobOutput::Ok(Json(job)),
+            None => no_job(&id).into(),
+        }
+    }
+
+    /// Starts a project for the input, with the hash of the bytes recorded
+    #[instrument(skip(self, payload))]
+    #[oai(path = "/project/new", method = "post")]
+    pub async fn new_project_handler(&self, payload: Json<NewProjectInput>) -> Json<Any<Project>> {
+        event!(Level::INFO, "Creating project");
+        Json(Any(Project::for_input(&payload.input, &payload.bytes)))
+    }
+
+    /// Disassembles the input with the annotations of the project. Symbol
+    /// files of the project are not read, their labels have to be in the
+    /// project itself.
+    #[instrument(skip(self, payload))]
+    #[oai(path = "/project/structured", method = "post")]
+    pub async fn project_structured_handler(
+        &self,
+        payload: Json<ProjectInput>,
+    ) -> StructuredOutput {
+        event!(Level::INFO, "Structured disassembling with a project");
+        let Json(ProjectInput { bytes, project }) = payload;
+        let result = async {
+            self.check_size(&bytes)?;
+            blocking(move || {
+                let image = project.0.image(&bytes)?;
+                Ok(StructuredDisassembly {
+                    metadata: image.metadata().to_vec(),
+                    instructions: disassemble_image(&image),
+                })
+            })
+            .await
+        };
+        result.await.into()
     }
 }
 
@@ -69,6 +1224,7 @@ mod tests {
 
         let payload = Input {
             bytes: vec![0xa9, 0xbd, 0xa0, 0xbd, 0x20, 0x28, 0xba],
+            ..Input::default()
         };
 
         let output = client
@@ -88,18 +1244,24 @@ mod tests {
                 bytes: "A9 BD".into(),
                 operation: "LDA".into(),
                 address: "#$BD".into(),
+                label: None,
+                comment: None,
             },
             Instruction {
                 offset: 2,
                 bytes: "A0 BD".into(),
                 operation: "LDY".into(),
                 address: "#$BD".into(),
+                label: None,
+                comment: None,
             },
             Instruction {
                 offset: 4,
                 bytes: "20 28 BA".into(),
                 operation: "JSR".into(),
                 address: "$BA28".into(),
+                label: None,
+                comment: None,
             },
         ];
 
@@ -112,6 +1274,7 @@ mod tests {
 
         let payload = Input {
             bytes: vec![0xa9, 0xbd, 0xa0, 0xbd, 0x20, 0x28, 0xba],
+            ..Input::default()
         };
 
         let lines = client
@@ -136,4 +1299,574 @@ mod tests {
 
         assert_eq!(expected, lines);
     }
+
+    #[tokio::test]
+    async fn test_sid_metadata() {
+        let client = reqwest::Client::builder().build().unwrap();
+
+        let mut bytes = vec![0; 0x76];
+        bytes[0..4].copy_from_slice(b"PSID");
+        bytes[0x05] = 1;
+        bytes[0x07] = 0x76;
+        bytes[0x08..0x0c].copy_from_slice(&[0x10, 0x00, 0x10, 0x00]);
+        bytes[0x16..0x1b].copy_from_slice(b"Tune!");
+        bytes.push(0x60);
+
+        let output = client
+            .post("http://localhost:9999/json/structured")
+            .json(&Input {
+                bytes,
+                ..Input::default()
+            })
+            .send()
+            .await
+            .unwrap()
+            .json::<StructuredDisassembly>()
+            .await
+            .unwrap();
+
+        assert!(output.metadata.contains(&Property {
+            name: "title".into(),
+            value: "Tune!".into()
+        }));
+        assert_eq!(output.instructions[0].offset, 0x1000);
+        assert_eq!(output.instructions[0].label, Some("init".into()));
+    }
+
+    #[tokio::test]
+    async fn test_broken_container() {
+        let client = reqwest::Client::builder().build().unwrap();
+
+        let response = client
+            .post("http://localhost:9999/json/formatted")
+            .json(&Input {
+                bytes: b"NESM\x1a".to_vec(),
+                ..Input::default()
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        // Unless the client says that it is no container
+        let output = client
+            .post("http://localhost:9999/json/structured")
+            .json(&Input {
+                bytes: b"NESM\x1a".to_vec(),
+                options: Some(Options {
+                    input_format: Some(Format::Raw),
+                    ..Options::default()
+                }),
+                ..Input::default()
+            })
+            .send()
+            .await
+            .unwrap()
+            .json::<StructuredDisassembly>()
+            .await
+            .unwrap();
+        assert_eq!(output.instructions[0].operation, "LSR");
+    }
+
+    #[tokio::test]
+    async fn test_project() {
+        let client = reqwest::Client::builder().build().unwrap();
+        let bytes = vec![0x20, 0xd2, 0xff, 0x60];
+
+        let mut project = client
+            .post("http://localhost:9999/json/project/new")
+            .json(&NewProjectInput {
+                input: "hello.bin".into(),
+                bytes: bytes.clone(),
+            })
+            .send()
+            .await
+            .unwrap()
+            .json::<Project>()
+            .await
+            .unwrap();
+        assert!(project.hashes.contains_key("hello.bin"));
+
+        project.origin = 0xc000;
+        project.labels.insert(0xc003, "done".into());
+        let output = client
+            .post("http://localhost:9999/json/project/structured")
+            .json(&ProjectInput {
+                bytes: bytes.clone(),
+                project: Any(project.clone()),
+            })
+            .send()
+            .await
+            .unwrap()
+            .json::<StructuredDisassembly>()
+            .await
+            .unwrap();
+        assert_eq!(output.instructions[1].offset, 0xc003);
+        assert_eq!(output.instructions[1].label, Some("done".into()));
+
+        let response = client
+            .post("http://localhost:9999/json/project/structured")
+            .json(&ProjectInput {
+                bytes: vec![0xea],
+                project: Any(project),
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_text_inputs() {
+        let client = reqwest::Client::builder().build().unwrap();
+
+        for payload in [
+            Input {
+                hex: Some("A9 BD 60".into()),
+                ..Input::default()
+            },
+            Input {
+                base64: Some("qb1g".into()),
+                ..Input::default()
+            },
+        ] {
+            let lines = client
+                .post("http://localhost:9999/json/formatted")
+                .json(&Input {
+                    options: Some(Options {
+                        origin: Some(0xc000),
+                        ..Options::default()
+                    }),
+                    ..payload
+                })
+                .send()
+                .await
+                .unwrap()
+                .json::<FormattedDisassembly>()
+                .await
+                .unwrap()
+                .instructions;
+            assert_eq!(
+                lines,
+                ["C000   A9 BD         LDA #$BD", "C002   60            RTS"]
+            );
+        }
+
+        let response = client
+            .post("http://localhost:9999/json/structured")
+            .json(&Input {
+                bytes: vec![0xea],
+                hex: Some("EA".into()),
+                ..Input::default()
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_binary_body() {
+        let client = reqwest::Client::builder().build().unwrap();
+
+        let output = client
+            .post("http://localhost:9999/json/structured/binary?origin=4096&cpu=Cmos")
+            .header("Content-Type", "application/octet-stream")
+            .body(vec![0x80, 0xfe])
+            .send()
+            .await
+            .unwrap()
+            .json::<StructuredDisassembly>()
+            .await
+            .unwrap();
+
+        assert_eq!(output.instructions[0].offset, 0x1000);
+        assert_eq!(output.instructions[0].operation, "BRA");
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload() {
+        let client = reqwest::Client::builder().build().unwrap();
+
+        let part = reqwest::multipart::Part::bytes(vec![0xa9, 0xbd, 0x60]).file_name("hello.bin");
+        let lines = client
+            .post("http://localhost:9999/json/formatted/upload?dialect=Acme")
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap()
+            .json::<FormattedDisassembly>()
+            .await
+            .unwrap()
+            .instructions;
+
+        assert_eq!(lines[0].trim(), "!cpu 6502");
+        assert_eq!(lines[3].trim(), "LDA #$BD");
+    }
+
+    #[tokio::test]
+    async fn test_error_bodies() {
+        let client = reqwest::Client::builder().build().unwrap();
+
+        let response = client
+            .post("http://localhost:9999/json/structured")
+            .json(&Input {
+                hex: Some("A9 ZZ".into()),
+                ..Input::default()
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 422);
+        assert_eq!(
+            response.json::<ErrorBody>().await.unwrap().code,
+            "unprocessable"
+        );
+
+        let response = client
+            .post("http://localhost:9999/json/formatted")
+            .header("Content-Type", "application/json")
+            .body("{\"bytes\": [")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            response.json::<ErrorBody>().await.unwrap().code,
+            "bad_request"
+        );
+    }
+
+    #[test]
+    fn test_failure_statuses() {
+        let body = |failure: &Failure| Json(failure.body());
+        let failure = Failure::TooLarge(4);
+        assert_eq!(
+            InstructionOutput::from(Failure::TooLarge(4)),
+            InstructionOutput::PayloadTooLarge(body(&failure))
+        );
+        let failure = Failure::Unprocessable("other binary".into());
+        assert_eq!(
+            InstructionPageOutput::from(Failure::Unprocessable("other binary".into())),
+            InstructionPageOutput::UnprocessableEntity(body(&failure))
+        );
+        let failure = Failure::Internal("disk full".into());
+        assert_eq!(
+            JobOutput::from(Failure::Internal("disk full".into())),
+            JobOutput::InternalServerError(body(&failure))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_payload_limit() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Only the headers are sent, a too large body is turned down before
+        // any of it is read
+        let mut stream = tokio::net::TcpStream::connect("localhost:9999")
+            .await
+            .unwrap();
+        let request = format!(
+            "POST /json/structured/binary HTTP/1.1\r\n\
+             Host: localhost\r\n\
+             Content-Type: application/octet-stream\r\n\
+             Content-Length: {}\r\n\r\n",
+            Limits::default().max_payload + 1
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = vec![0; 1024];
+        let read = stream.read(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response[..read]);
+
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        assert!(response.contains("payload_too_large"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let client = reqwest::Client::builder().build().unwrap();
+        let payload = || Input {
+            bytes: (0..=255).collect(),
+            ..Input::default()
+        };
+
+        let expected = client
+            .post("http://localhost:9999/json/structured")
+            .json(&payload())
+            .send()
+            .await
+            .unwrap()
+            .json::<StructuredDisassembly>()
+            .await
+            .unwrap()
+            .instructions;
+
+        let response = client
+            .post("http://localhost:9999/json/structured/stream")
+            .json(&payload())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+        let streamed: Vec<Instruction> = response
+            .text()
+            .await
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(streamed, expected);
+    }
+
+    async fn store(client: &reqwest::Client, bytes: Vec<u8>) -> StoredBinary {
+        client
+            .post("http://localhost:9999/json/binaries")
+            .header("Content-Type", "application/octet-stream")
+            .body(bytes)
+            .send()
+            .await
+            .unwrap()
+            .json::<StoredBinary>()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pages() {
+        let client = reqwest::Client::builder().build().unwrap();
+        // LDA $1234 and NOP, over and over
+        let bytes: Vec<u8> = [0xad, 0x34, 0x12, 0xea].repeat(100);
+        let stored = store(&client, bytes.clone()).await;
+        assert_eq!(stored.size, 400);
+
+        let mut offsets = vec![];
+        let mut cursor = None;
+        loop {
+            let mut url = format!(
+                "http://localhost:9999/json/binaries/{}/instructions?origin=4096&start=4097&count=64",
+                stored.id
+            );
+            if let Some(cursor) = &cursor {
+                url.push_str(&format!("&cursor={}", cursor));
+            }
+            let page = client
+                .get(url)
+                .send()
+                .await
+                .unwrap()
+                .json::<InstructionPage>()
+                .await
+                .unwrap();
+            assert!(page.instructions.len() <= 64);
+            offsets.extend(
+                page.instructions
+                    .iter()
+                    .map(|instruction| instruction.offset),
+            );
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        // The first instruction starts before `start`, so it is left out
+        assert_eq!(offsets.len(), 199);
+        assert_eq!(offsets[0], 0x1003);
+
+        let output = client
+            .post("http://localhost:9999/json/structured")
+            .json(&Input {
+                id: Some(stored.id),
+                ..Input::default()
+            })
+            .send()
+            .await
+            .unwrap()
+            .json::<StructuredDisassembly>()
+            .await
+            .unwrap();
+        assert_eq!(output.instructions.len(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_lookup() {
+        let client = reqwest::Client::builder().build().unwrap();
+        let stored = store(&client, vec![0xea, 0xad, 0x34, 0x12]).await;
+
+        let instruction = client
+            .get(format!(
+                "http://localhost:9999/json/binaries/{}/at/3",
+                stored.id
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json::<Instruction>()
+            .await
+            .unwrap();
+        assert_eq!(instruction.offset, 1);
+        assert_eq!(instruction.operation, "LDA");
+
+        for url in [
+            format!("http://localhost:9999/json/binaries/{}/at/4", stored.id),
+            "http://localhost:9999/json/binaries/unknown/instructions".to_string(),
+        ] {
+            let response = client.get(url).send().await.unwrap();
+            assert_eq!(response.status(), 404);
+            assert_eq!(
+                response.json::<ErrorBody>().await.unwrap().code,
+                "not_found"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jobs() {
+        let client = reqwest::Client::builder().build().unwrap();
+        let submit = |input: JobInput| {
+            client
+                .post("http://localhost:9999/json/jobs")
+                .json(&input)
+                .send()
+        };
+
+        let response = submit(JobInput {
+            kind: JobKind::Statistics,
+            input: Input {
+                bytes: vec![0xa9, 0xbd, 0x60],
+                ..Input::default()
+            },
+            other: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 202);
+        let job = response.json::<Job>().await.unwrap();
+        let url = format!("http://localhost:9999/json/jobs/{}", job.id);
+
+        let job = loop {
+            let job = client
+                .get(&url)
+                .send()
+                .await
+                .unwrap()
+                .json::<Job>()
+                .await
+                .unwrap();
+            if job.status.is_finished() {
+                break job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(job.status, crate::JobStatus::Done);
+        assert_eq!(job.result.unwrap()["instructions"], 2);
+
+        // A finished job sends its last state and ends the stream
+        let events = client
+            .get(format!("{}/events", url))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(events.starts_with("data: "), "{}", events);
+        assert!(events.contains("\"Done\""), "{}", events);
+
+        let response = submit(JobInput {
+            kind: JobKind::Diff,
+            input: Input::default(),
+            other: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 400);
+
+        let large = || Input {
+            hex: Some("EA".repeat(300 * 1024)),
+            ..Input::default()
+        };
+        let response = submit(JobInput {
+            kind: JobKind::Diff,
+            input: large(),
+            other: Some(large()),
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 413);
+
+        let response = client
+            .delete("http://localhost:9999/json/jobs/unknown")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_stored_project() {
+        let client = reqwest::Client::builder().build().unwrap();
+        let bytes = vec![0x20, 0xd2, 0xff, 0xea, 0x60];
+        let stored = store(&client, bytes.clone()).await;
+        let url = format!("http://localhost:9999/json/projects/{}", stored.id);
+
+        let mut project = Project::for_input("hello.bin", &bytes);
+        project.origin = 0xc000;
+        project.labels.insert(0xc004, "done".into());
+        let response = client.put(&url).json(&project).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let fetched = client
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .json::<Project>()
+            .await
+            .unwrap();
+        assert_eq!(fetched, project);
+        let projects = client
+            .get("http://localhost:9999/json/projects")
+            .send()
+            .await
+            .unwrap()
+            .json::<Vec<ProjectSummary>>()
+            .await
+            .unwrap();
+        assert!(projects.contains(&ProjectSummary {
+            id: stored.id.clone(),
+            labels: 1,
+            comments: 0,
+            data_ranges: 0,
+        }));
+
+        let output = client
+            .post("http://localhost:9999/json/structured")
+            .json(&Input {
+                id: Some(stored.id.clone()),
+                ..Input::default()
+            })
+            .send()
+            .await
+            .unwrap()
+            .json::<StructuredDisassembly>()
+            .await
+            .unwrap();
+        assert_eq!(output.instructions[2].offset, 0xc004);
+        assert_eq!(output.instructions[2].label, Some("done".into()));
+
+        let other = Project::for_input("hello.bin", &[0xea]);
+        let response = client.put(&url).json(&other).send().await.unwrap();
+        assert_eq!(response.status(), 422);
+
+        assert_eq!(client.delete(&url).send().await.unwrap().status(), 204);
+        for response in [
+            client.delete(&url).send().await.unwrap(),
+            client.get(&url).send().await.unwrap(),
+        ] {
+            assert_eq!(response.status(), 404);
+        }
+    }
 }
@@ -0,0 +1,639 @@
+use std::{collections::BTreeMap, error::Error, fmt::Display};
+
+use crate::{opcodes::encode_opcode, AddressMode, Cpu, Operation};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AssembleError {
+    /// One-indexed line of the source
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+/// Machine code produced from a source file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Assembled {
+    pub origin: usize,
+    pub bytes: Vec<u8>,
+    pub labels: BTreeMap<String, usize>,
+}
+
+/// Parses `$FF`, `0xFF`, `%11111111` and `255` style numbers
+pub fn parse_number(text: &str) -> Option<usize> {
+    let text = text.trim();
+
+    if let Some(hex) = text.strip_prefix('$') {
+        usize::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        usize::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix('%') {
+        usize::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Assembles a single instruction that will be placed at the address. Labels
+/// are not available, all operands have to be numbers.
+pub fn assemble_instruction(text: &str, address: usize, cpu: Cpu) -> Result<Vec<u8>, String> {
+    let (mnemonic, operand) = split_mnemonic(text.trim());
+    let (operation, is_absolute) =
+        parse_mnemonic(mnemonic).ok_or_else(|| format!("unknown mnemonic '{}'", mnemonic))?;
+
+    let context = Context {
+        labels: &BTreeMap::new(),
+        pc: address,
+    };
+    let (address_mode, expression) = address_mode(operation, is_absolute, operand, cpu, &context)?;
+    let value = match expression {
+        Some(expression) => Some(
+            context
+                .evaluate(expression)?
+                .ok_or_else(|| format!("cannot evaluate '{}'", expression))?,
+        ),
+        None => None,
+    };
+
+    encode(operation, address_mode, value, address, cpu)
+}
+
+/// Two pass assembler for the kind of source the disassembler writes. Supports
+/// labels, `name = value` constants, `.org`/`* =`, `.byte`/`!byte`,
+/// `.word`/`!word` and `.setcpu`/`!cpu`.
+pub fn assemble(source: &str, cpu: Cpu) -> Result<Assembled, AssembleError> {
+    let mut cpu = cpu;
+    let mut labels = BTreeMap::new();
+    let mut statements = vec![];
+    let mut origin = None;
+    let mut pc = 0;
+
+    // First pass figures out sizes, which means deciding address modes
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| AssembleError {
+            line: index + 1,
+            message,
+        };
+
+        let mut text = strip_comment(line).trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some((name, rest)) = split_label(text) {
+            define(&mut labels, name, pc).map_err(error)?;
+            text = rest;
+            if text.is_empty() {
+                continue;
+            }
+        }
+
+        let context = Context {
+            labels: &labels,
+            pc,
+        };
+        let (keyword, rest) = split_mnemonic(text);
+        let directive = keyword.to_ascii_lowercase();
+
+        if let Some(value) = text
+            .strip_prefix('*')
+            .and_then(|rest| rest.trim_start().strip_prefix('='))
+            .or_else(|| (directive == ".org").then_some(rest))
+        {
+            let address = context
+                .evaluate(value)
+                .map_err(error)?
+                .ok_or_else(|| error(format!("cannot evaluate '{}'", value.trim())))?;
+
+            match origin {
+                None => origin = Some(address),
+                Some(_) if address < pc => {
+                    return Err(error(format!("origin ${:04X} moves backwards", address)))
+                }
+                Some(_) => statements.push((index, Statement::Fill(address - pc))),
+            }
+            pc = address;
+        } else if let Some((name, value)) = split_constant(text) {
+            let value = context
+                .evaluate(value)
+                .map_err(error)?
+                .ok_or_else(|| error(format!("cannot evaluate '{}'", value.trim())))?;
+            define(&mut labels, name, value).map_err(error)?;
+        } else if directive == ".setcpu" || directive == "!cpu" {
+            cpu = match rest.trim_matches('"').to_ascii_lowercase().as_str() {
+                "6502" => Cpu::Nmos,
+                "6502x" | "6510" => Cpu::NmosIllegal,
+                "65c02" => Cpu::Cmos,
+                other => return Err(error(format!("unsupported cpu '{}'", other))),
+            };
+        } else if let Some(width) = data_width(&directive) {
+            let items = split_items(rest);
+            let size = items
+                .iter()
+                .map(|item| match item.strip_prefix('"') {
+                    Some(text) => text.trim_end_matches('"').len(),
+                    None => width,
+                })
+                .sum::<usize>();
+
+            origin.get_or_insert(pc);
+            statements.push((index, Statement::Data { width, items, pc }));
+            pc += size;
+        } else {
+            let (operation, is_absolute) = parse_mnemonic(keyword)
+                .ok_or_else(|| error(format!("unknown mnemonic '{}'", keyword)))?;
+            let (address_mode, expression) =
+                address_mode(operation, is_absolute, rest, cpu, &context).map_err(error)?;
+
+            origin.get_or_insert(pc);
+            statements.push((
+                index,
+                Statement::Instruction {
+                    operation,
+                    address_mode,
+                    expression,
+                    pc,
+                    cpu,
+                },
+            ));
+            pc += address_mode.length();
+        }
+    }
+
+    // Second pass emits the bytes now that all labels are known
+    let mut bytes = vec![];
+    for (index, statement) in statements {
+        let error = |message: String| AssembleError {
+            line: index + 1,
+            message,
+        };
+
+        match statement {
+            Statement::Fill(length) => bytes.extend(vec![0; length]),
+            Statement::Data { width, items, pc } => {
+                for item in items {
+                    if let Some(text) = item.strip_prefix('"') {
+                        bytes.extend(text.trim_end_matches('"').bytes());
+                        continue;
+                    }
+
+                    let context = Context {
+                        labels: &labels,
+                        pc,
+                    };
+                    let value = context
+                        .evaluate(item)
+                        .map_err(error)?
+                        .ok_or_else(|| error(format!("unknown label in '{}'", item)))?;
+
+                    if width == 1 {
+                        bytes.push(byte(value).map_err(error)?);
+                    } else {
+                        bytes.extend((value as u16).to_le_bytes());
+                    }
+                }
+            }
+            Statement::Instruction {
+                operation,
+                address_mode,
+                expression,
+                pc,
+                cpu,
+            } => {
+                let context = Context {
+                    labels: &labels,
+                    pc,
+                };
+                let value = match expression {
+                    Some(expression) => Some(
+                        context
+                            .evaluate(expression)
+                            .map_err(error)?
+                            .ok_or_else(|| error(format!("unknown label in '{}'", expression)))?,
+                    ),
+                    None => None,
+                };
+
+                bytes.extend(encode(operation, address_mode, value, pc, cpu).map_err(error)?);
+            }
+        }
+    }
+
+    Ok(Assembled {
+        origin: origin.unwrap_or(0),
+        bytes,
+        labels,
+    })
+}
+
+enum Statement<'a> {
+    Fill(usize),
+    Data {
+        width: usize,
+        items: Vec<&'a str>,
+        pc: usize,
+    },
+    Instruction {
+        operation: Operation,
+        address_mode: AddressMode,
+        expression: Option<&'a str>,
+        pc: usize,
+        cpu: Cpu,
+    },
+}
+
+struct Context<'a> {
+    labels: &'a BTreeMap<String, usize>,
+    pc: usize,
+}
+
+impl Context<'_> {
+    /// Evaluates `<`/`>` prefixed sums and differences of numbers, labels and
+    /// `*`. Ok(None) means that a label is not defined yet.
+    fn evaluate(&self, expression: &str) -> Result<Option<usize>, String> {
+        let expression = expression.trim();
+        let (selector, expression) = match expression.chars().next() {
+            Some('<') => (Some('<'), &expression[1..]),
+            Some('>') => (Some('>'), &expression[1..]),
+            _ => (None, expression),
+        };
+
+        let mut total: isize = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+
+        for character in expression.chars().chain(std::iter::once('+')) {
+            // A leading sign or the * for the program counter are not operators
+            let is_operator = (character == '+' || character == '-') && !term.trim().is_empty();
+            if !is_operator {
+                term.push(character);
+                continue;
+            }
+
+            let value = match term.trim() {
+                "*" => self.pc,
+                name if name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                    match self.labels.get(name) {
+                        Some(&value) => value,
+                        None => return Ok(None),
+                    }
+                }
+                number => parse_number(number)
+                    .ok_or_else(|| format!("cannot parse '{}' as a number", number))?,
+            };
+
+            total += sign * value as isize;
+            sign = if character == '-' { -1 } else { 1 };
+            term.clear();
+        }
+
+        let total = total as usize;
+        Ok(Some(match selector {
+            Some('<') => total & 0xff,
+            Some('>') => (total >> 8) & 0xff,
+            _ => total,
+        }))
+    }
+}
+
+fn define(labels: &mut BTreeMap<String, usize>, name: &str, value: usize) -> Result<(), String> {
+    if labels.insert(name.to_string(), value).is_some() {
+        Err(format!("'{}' is defined twice", name))
+    } else {
+        Ok(())
+    }
+}
+
+/// Mnemonic and whether it forces an absolute address, which ACME writes as
+/// `LDA+2`
+fn parse_mnemonic(keyword: &str) -> Option<(Operation, bool)> {
+    match keyword.strip_suffix("+2") {
+        Some(mnemonic) => Operation::from_mnemonic(mnemonic).map(|operation| (operation, true)),
+        None => Operation::from_mnemonic(keyword).map(|operation| (operation, false)),
+    }
+}
+
+/// Picks the address mode from the operand syntax. Zero page forms are used
+/// when the value is already known to fit, forward references get the
+/// absolute form so that sizes don't change between passes. ca65 style `a:`
+/// prefixes force the absolute form as well.
+fn address_mode<'a>(
+    operation: Operation,
+    is_absolute: bool,
+    operand: &'a str,
+    cpu: Cpu,
+    context: &Context,
+) -> Result<(AddressMode, Option<&'a str>), String> {
+    let operand = operand.trim();
+    let (is_absolute, operand) = match operand
+        .strip_prefix("a:")
+        .or_else(|| operand.strip_prefix("A:"))
+    {
+        Some(rest) => (true, rest),
+        None => (is_absolute, operand),
+    };
+    let upper = operand.to_ascii_uppercase();
+    let supports = |mode: AddressMode| encode_opcode(operation, mode, cpu).is_some();
+    let unsupported = || format!("{} does not support the operand '{}'", operation, operand);
+
+    if operand.is_empty() {
+        return [AddressMode::Implied, AddressMode::Accumulator]
+            .into_iter()
+            .find(|&mode| supports(mode))
+            .map(|mode| (mode, None))
+            .ok_or_else(unsupported);
+    }
+
+    if upper == "A" && supports(AddressMode::Accumulator) {
+        return Ok((AddressMode::Accumulator, None));
+    }
+
+    if let Some(value) = operand.strip_prefix('#') {
+        return supports(AddressMode::Immediate)
+            .then_some((AddressMode::Immediate, Some(value)))
+            .ok_or_else(unsupported);
+    }
+
+    if operation.is_branch() {
+        return supports(AddressMode::Relative)
+            .then_some((AddressMode::Relative, Some(operand)))
+            .ok_or_else(unsupported);
+    }
+
+    let strip = |suffix: &str| {
+        upper
+            .ends_with(suffix)
+            .then(|| operand[..operand.len() - suffix.len()].trim())
+    };
+
+    let (zeropage, absolute, expression) = if operand.starts_with('(') {
+        if let Some(inner) = strip(",X)") {
+            (
+                Some(AddressMode::XIndirect),
+                Some(AddressMode::AbsoluteXIndirect),
+                &inner[1..],
+            )
+        } else if let Some(inner) = strip("),Y") {
+            (Some(AddressMode::IndirectY), None, &inner[1..])
+        } else if let Some(inner) = strip(")") {
+            (
+                Some(AddressMode::ZeropageIndirect),
+                Some(AddressMode::Indirect),
+                &inner[1..],
+            )
+        } else {
+            return Err(format!("unbalanced parenthesis in '{}'", operand));
+        }
+    } else if let Some(inner) = strip(",X") {
+        (
+            Some(AddressMode::ZeropageX),
+            Some(AddressMode::AbsoluteX),
+            inner,
+        )
+    } else if let Some(inner) = strip(",Y") {
+        (
+            Some(AddressMode::ZeropageY),
+            Some(AddressMode::AbsoluteY),
+            inner,
+        )
+    } else {
+        (
+            Some(AddressMode::Zeropage),
+            Some(AddressMode::Absolute),
+            operand,
+        )
+    };
+
+    let zeropage = zeropage.filter(|&mode| supports(mode));
+    let absolute = absolute.filter(|&mode| supports(mode));
+    let fits =
+        !is_absolute && matches!(context.evaluate(expression), Ok(Some(value)) if value < 0x100);
+
+    match (zeropage, absolute) {
+        (Some(zeropage), Some(_)) if fits => Ok((zeropage, Some(expression))),
+        (_, Some(absolute)) => Ok((absolute, Some(expression))),
+        (Some(zeropage), None) => Ok((zeropage, Some(expression))),
+        (None, None) => Err(unsupported()),
+    }
+}
+
+fn encode(
+    operation: Operation,
+    address_mode: AddressMode,
+    value: Option<usize>,
+    pc: usize,
+    cpu: Cpu,
+) -> Result<Vec<u8>, String> {
+    let opcode = encode_opcode(operation, address_mode, cpu)
+        .ok_or_else(|| format!("{} does not support {:?}", operation, address_mode))?;
+
+    let mut bytes = vec![opcode];
+    match (address_mode.length(), value) {
+        (1, _) => {}
+        (_, None) => return Err(format!("{} needs an operand", operation)),
+        (2, Some(target)) if address_mode == AddressMode::Relative => {
+            // Branches wrap around the 64K address space
+            let displacement = target.wrapping_sub(pc + 2) as u16 as i16;
+            if !(-128..=127).contains(&displacement) {
+                return Err(format!("branch to ${:04X} is out of range", target));
+            }
+            bytes.push(displacement as i8 as u8);
+        }
+        (2, Some(value)) => bytes.push(byte(value)?),
+        (_, Some(value)) => {
+            if value > 0xffff {
+                return Err(format!("${:X} does not fit in a word", value));
+            }
+            bytes.extend((value as u16).to_le_bytes());
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn byte(value: usize) -> Result<u8, String> {
+    u8::try_from(value).map_err(|_| format!("${:X} does not fit in a byte", value))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn split_mnemonic(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+/// Splits `name:` labels, and bare labels on their own line or in front of an
+/// instruction the way ACME writes them
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (first, rest) = split_mnemonic(text);
+
+    if let Some(name) = first.strip_suffix(':') {
+        return is_identifier(name).then_some((name, rest));
+    }
+
+    let is_keyword = parse_mnemonic(first).is_some()
+        || first.starts_with('.')
+        || first.starts_with('!')
+        || first.starts_with('*');
+    let is_constant = rest.starts_with('=') || rest.to_ascii_lowercase().starts_with("equ ");
+
+    (!is_keyword && !is_constant && is_identifier(first)).then_some((first, rest))
+}
+
+fn split_constant(text: &str) -> Option<(&str, &str)> {
+    let (name, value) = text
+        .split_once('=')
+        .or_else(|| text.split_once(" equ "))
+        .or_else(|| text.split_once(" EQU "))?;
+    let name = name.trim();
+    is_identifier(name).then_some((name, value))
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn data_width(directive: &str) -> Option<usize> {
+    match directive {
+        ".byte" | ".db" | "!byte" | "!by" | "!08" => Some(1),
+        ".word" | ".dw" | "!word" | "!wo" | "!16" => Some(2),
+        _ => None,
+    }
+}
+
+fn split_items(text: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut in_string = false;
+    let mut start = 0;
+
+    for (index, character) in text.char_indices() {
+        match character {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(text[start..].trim());
+
+    items.into_iter().filter(|item| !item.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::disassemble;
+
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(parse_number("$C000"), Some(0xc000));
+        assert_eq!(parse_number("0xff"), Some(0xff));
+        assert_eq!(parse_number("%101"), Some(5));
+        assert_eq!(parse_number("49152"), Some(49152));
+        assert_eq!(parse_number("$"), None);
+    }
+
+    #[test]
+    fn test_single_instruction() {
+        let assemble = |text| assemble_instruction(text, 0x1000, Cpu::Nmos);
+
+        assert_eq!(assemble("LDA #$BD"), Ok(vec![0xa9, 0xbd]));
+        assert_eq!(assemble("lda $10"), Ok(vec![0xa5, 0x10]));
+        assert_eq!(assemble("LDA $0010"), Ok(vec![0xa5, 0x10]));
+        assert_eq!(assemble("LDA a:$10"), Ok(vec![0xad, 0x10, 0x00]));
+        assert_eq!(assemble("LDA+2 $10"), Ok(vec![0xad, 0x10, 0x00]));
+        assert_eq!(assemble("LDA $1234,X"), Ok(vec![0xbd, 0x34, 0x12]));
+        assert_eq!(assemble("STA ($FB),Y"), Ok(vec![0x91, 0xfb]));
+        assert_eq!(assemble("JMP ($FFFC)"), Ok(vec![0x6c, 0xfc, 0xff]));
+        assert_eq!(assemble("ASL"), Ok(vec![0x0a]));
+        assert_eq!(assemble("ROR A"), Ok(vec![0x6a]));
+        assert_eq!(assemble("BNE $0FF0"), Ok(vec![0xd0, 0xee]));
+        assert!(assemble("BNE $2000").is_err());
+        assert_eq!(
+            assemble_instruction("BEQ $FFF0", 0x0002, Cpu::Nmos),
+            Ok(vec![0xf0, 0xec])
+        );
+        assert!(assemble("STZ $10").is_err());
+        assert_eq!(
+            assemble_instruction("STZ $10", 0, Cpu::Cmos),
+            Ok(vec![0x64, 0x10])
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let source = r#"
+            ; Comments are skipped
+            .setcpu "6502"
+            .org $C000
+            screen = $0400
+            start:
+                LDX #0
+            loop: LDA message,X
+                BEQ done
+                STA screen,X
+                INX
+                BNE loop
+            done
+                RTS
+            message:
+                .byte "HI", 0
+                .word start
+        "#;
+
+        let assembled = assemble(source, Cpu::Nmos).unwrap();
+
+        assert_eq!(assembled.origin, 0xc000);
+        assert_eq!(assembled.labels["done"], 0xc00d);
+        assert_eq!(assembled.labels["message"], 0xc00e);
+
+        let lines: Vec<String> = disassemble(&assembled.bytes[..0x0e])
+            .into_iter()
+            .map(|instruction| format!("{} {}", instruction.operation, instruction.address))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "LDX #$00",
+                "LDA $C00E,X",
+                "BEQ $000D",
+                "STA $0400,X",
+                "INX ",
+                "BNE $0002",
+                "RTS ",
+            ]
+        );
+        assert_eq!(assembled.bytes[0x0e..], [b'H', b'I', 0, 0x00, 0xc0]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = assemble("LDA #$00\nFOO $10", Cpu::Nmos).unwrap_err();
+        assert_eq!(error.line, 2);
+
+        let error = assemble("JMP nowhere", Cpu::Nmos).unwrap_err();
+        assert_eq!(error.message, "unknown label in 'nowhere'");
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use mos_6502_disassembler::{statistics, Charset, ListingFormat};
+use rayon::prelude::*;
+
+use crate::{load_file, print_disassembly, BatchArgs, OutputDialect};
+
+/// Input file and where its listing goes, relative to the output directory
+#[derive(Debug, PartialEq, Eq)]
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+struct Outcome {
+    bytes: usize,
+    instructions: usize,
+    unknown: usize,
+    time: Duration,
+}
+
+/// Disassembles every input into its own file in the output directory, one
+/// file per core at a time, and writes a summary next to the listings.
+/// Returns whether every file succeeded.
+pub fn run(args: &BatchArgs, out: &mut dyn Write) -> Result<bool, Box<dyn Error>> {
+    let output = Path::new(&args.output);
+    let jobs = expand(&args.inputs, output, args.dialect.extension())?;
+    fs::create_dir_all(output)?;
+
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = args.jobs {
+        pool = pool.num_threads(threads);
+    }
+
+    let started = Instant::now();
+    let outcomes: Vec<Result<Outcome, String>> = pool.build()?.install(|| {
+        jobs.par_iter()
+            .map(|job| process(job, args, output).map_err(|error| error.to_string()))
+            .collect()
+    });
+
+    let summary = summary(&jobs, &outcomes, started.elapsed());
+    fs::write(output.join("summary.txt"), &summary)?;
+    write!(out, "{}", summary)?;
+
+    Ok(outcomes.iter().all(Result::is_ok))
+}
+
+fn process(job: &Job, args: &BatchArgs, output: &Path) -> Result<Outcome, Box<dyn Error>> {
+    let started = Instant::now();
+    let input = job.input.to_str().ok_or("path is not valid UTF-8")?;
+    let image = load_file(input, &args.options)?;
+
+    let mut listing = vec![];
+    print_disassembly(
+        &image,
+        args.dialect,
+        Charset::Ascii,
+        &ListingFormat::default(),
+        &mut listing,
+    )?;
+
+    let path = output.join(&job.output);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, listing)?;
+
+    let statistics = statistics(&image);
+    Ok(Outcome {
+        bytes: statistics.bytes,
+        instructions: statistics.instructions,
+        unknown: statistics.unknown,
+        time: started.elapsed(),
+    })
+}
+
+/// Turns files, directories and glob patterns into jobs. Directories are
+/// searched recursively and keep their layout in the output, other files
+/// are written by their name.
+fn expand(inputs: &[String], output: &Path, extension: &str) -> Result<Vec<Job>, Box<dyn Error>> {
+    let mut files: Vec<(PathBuf, PathBuf)> = vec![];
+
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            for file in walk(path, output)? {
+                let relative = file.strip_prefix(path)?.to_path_buf();
+                files.push((file, relative));
+            }
+        } else if input.contains(['*', '?', '[']) {
+            for file in glob::glob(input)? {
+                let file = file?;
+                if file.is_file() {
+                    files.push((file.clone(), file_name(&file)));
+                }
+            }
+        } else {
+            // Missing files are reported in the summary with the rest
+            files.push((path.to_path_buf(), file_name(path)));
+        }
+    }
+
+    let mut taken: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut jobs = vec![];
+    for (input, relative) in files {
+        let mut name = relative.into_os_string();
+        name.push(".");
+        name.push(extension);
+        let output = PathBuf::from(name);
+
+        if let Some(other) = taken.insert(output.clone(), input.clone()) {
+            return Err(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                input.display(),
+                output.display()
+            )
+            .into());
+        }
+        jobs.push(Job { input, output });
+    }
+
+    Ok(jobs)
+}
+
+/// Files under the directory in a stable order, skipping the output
+/// directory so that a second run doesn't disassemble the listings
+fn walk(directory: &Path, output: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let output = fs::canonicalize(output).ok();
+    let mut files = vec![];
+    let mut pending = vec![directory.to_path_buf()];
+
+    while let Some(directory) = pending.pop() {
+        if output.is_some() && fs::canonicalize(&directory).ok() == output {
+            continue;
+        }
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> PathBuf {
+    path.file_name()
+        .map_or_else(|| path.to_path_buf(), PathBuf::from)
+}
+
+fn summary(jobs: &[Job], outcomes: &[Result<Outcome, String>], time: Duration) -> String {
+    let width = jobs
+        .iter()
+        .map(|job| job.input.display().to_string().len())
+        .max()
+        .unwrap_or_default()
+        .max(4);
+
+    let mut text = format!(
+        "{: <width$}  {: >8}  {: >12}  {: >8}  {: >8}  status\n",
+        "file", "bytes", "instructions", "unknown", "time"
+    );
+    for (job, outcome) in jobs.iter().zip(outcomes) {
+        let input = job.input.display();
+        text.push_str(&match outcome {
+            Ok(outcome) => format!(
+                "{: <width$}  {: >8}  {: >12}  {: >8}  {: >6}ms  ok\n",
+                input,
+                outcome.bytes,
+                outcome.instructions,
+                outcome.unknown,
+                outcome.time.as_millis()
+            ),
+            Err(error) => format!(
+                "{: <width$}  {: >8}  {: >12}  {: >8}  {: >8}  error: {}\n",
+                input, "-", "-", "-", "-", error
+            ),
+        });
+    }
+
+    let failed = outcomes.iter().filter(|outcome| outcome.is_err()).count();
+    text.push_str(&format!(
+        "\n{} files, {} failed, {}ms\n",
+        jobs.len(),
+        failed,
+        time.as_millis()
+    ));
+    text
+}
+
+impl OutputDialect {
+    /// Extension of listings written in the dialect, without the input's
+    fn extension(self) -> &'static str {
+        match self {
+            OutputDialect::Listing | OutputDialect::Hex => "txt",
+            OutputDialect::Ca65 | OutputDialect::Acme => "s",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use crate::{Cli, Command};
+
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("batch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("roms/nested")).unwrap();
+        fs::write(directory.join("roms/a.bin"), [0xa9, 0xbd, 0x60]).unwrap();
+        fs::write(directory.join("roms/nested/b.bin"), [0xea]).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_expand() {
+        let directory = scratch("expand");
+        let roms = directory.join("roms").display().to_string();
+        let output = directory.join("roms/out");
+
+        let jobs = expand(std::slice::from_ref(&roms), &output, "txt").unwrap();
+        let outputs: Vec<PathBuf> = jobs.into_iter().map(|job| job.output).collect();
+        assert_eq!(
+            outputs,
+            [
+                PathBuf::from("a.bin.txt"),
+                PathBuf::from("nested/b.bin.txt")
+            ]
+        );
+
+        let jobs = expand(&[format!("{}/*/*.bin", roms)], &output, "s").unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].output, PathBuf::from("b.bin.s"));
+
+        let twice = expand(
+            &[format!("{}/a.bin", roms), format!("{}/*.bin", roms)],
+            &output,
+            "txt",
+        );
+        assert!(twice.is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_run() {
+        let directory = scratch("run");
+        let roms = directory.join("roms").display().to_string();
+        let output = directory.join("out");
+        let missing = directory.join("missing.bin").display().to_string();
+        let cli = Cli::try_parse_from([
+            "cli",
+            "batch",
+            "--jobs",
+            "2",
+            "-o",
+            &output.display().to_string(),
+            &roms,
+            &missing,
+        ])
+        .unwrap();
+        let Some(Command::Batch(args)) = cli.command else {
+            panic!("expected the batch command");
+        };
+
+        let mut summary = vec![];
+        assert!(!run(&args, &mut summary).unwrap());
+
+        let listing = fs::read_to_string(output.join("nested/b.bin.txt")).unwrap();
+        assert_eq!(listing, "0000   EA            NOP\n");
+        let summary = String::from_utf8(summary).unwrap();
+        assert!(summary.contains("3 files, 1 failed"), "{}", summary);
+        assert_eq!(
+            fs::read_to_string(output.join("summary.txt")).unwrap(),
+            summary
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
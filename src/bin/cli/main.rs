@@ -0,0 +1,922 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, BufRead, ErrorKind, IsTerminal, Read, Write},
+    ops::Range,
+    path::Path,
+    process::ExitCode,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use mos_6502_disassembler::{
+    assemble, assembly_source, control_flow, control_flow_dot, cross_references, csv_field,
+    disassemble_image, disassemble_image_iter, hex_listing, hexdump, instruction_changes, load_as,
+    load_at, parse_hex_text, parse_number, parse_pattern, parse_symbols, search_bytes,
+    search_instructions, statistics, Change, Charset, Cpu, Dialect, EntryPoint, Format, HexStyle,
+    Instruction, ListingFormat, MemoryImage, Platform, Project, ProjectError,
+    StructuredDisassembly,
+};
+use serde::Serialize;
+
+mod batch;
+mod monitor;
+mod project;
+mod tui;
+mod watch;
+
+use monitor::Monitor;
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+/// Disassembler for the MOS 6502 family. Without a subcommand the files are
+/// disassembled, same as with `disasm`.
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    disasm: DisasmArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Disassemble files as a listing or as assembler source
+    Disasm(DisasmArgs),
+    /// Count instructions, address modes and undecodable bytes
+    Stats(InputArgs),
+    /// List the instructions that reference each address
+    Xref(InputArgs),
+    /// Print the control flow graph in Graphviz DOT format
+    Cfg(InputArgs),
+    /// Hex and text dump in the style of xxd
+    Hexdump(HexdumpArgs),
+    /// Disassemble many files in parallel into an output directory
+    Batch(BatchArgs),
+    /// Find byte patterns or instructions
+    Search(SearchArgs),
+    /// Compare the disassembly of two files
+    Diff(DiffArgs),
+    /// Assemble a source file into a binary
+    Assemble(AssembleArgs),
+    /// Browse a file interactively, annotations are kept in a project file
+    Tui(TuiArgs),
+    /// Machine language monitor that reads commands from stdin
+    Monitor(MonitorArgs),
+    /// Create and check project files that keep annotations next to a binary
+    Project(ProjectArgs),
+}
+
+#[derive(Debug, Clone, Args)]
+struct LoadOptions {
+    /// Load address of flat binaries, containers have their own
+    #[arg(long, value_parser = number)]
+    origin: Option<usize>,
+    /// First address to include
+    #[arg(long, value_parser = number)]
+    start: Option<usize>,
+    /// First address after the range to include
+    #[arg(long, value_parser = number)]
+    end: Option<usize>,
+    #[arg(long, value_enum)]
+    cpu: Option<CpuArg>,
+    /// VICE label file or `name = $1234` style symbol file, can be repeated
+    #[arg(long = "symbols", value_name = "FILE")]
+    symbols: Vec<String>,
+    /// Inputs are hex text, like "A9 BD", "$A9,$BD", C arrays, .byte lines or
+    /// base64, instead of binary
+    #[arg(long)]
+    hex: bool,
+    /// Project file whose annotations are applied, the options given here
+    /// take precedence over it. The tui defaults to the input file with a
+    /// .project.json extension.
+    #[arg(long, value_name = "FILE")]
+    project: Option<String>,
+    /// Names the hardware registers and ROM routines of the machine
+    #[arg(long, value_enum)]
+    platform: Option<PlatformArg>,
+    /// Container format of the inputs, detected from their header by default
+    #[arg(long, value_enum)]
+    input_format: Option<FormatArg>,
+}
+
+#[derive(Debug, Clone, Args)]
+struct InputArgs {
+    /// Use - to read from stdin
+    files: Vec<String>,
+    #[arg(short, long)]
+    verbose: bool,
+    #[command(flatten)]
+    options: LoadOptions,
+}
+
+#[derive(Debug, Args)]
+struct DisasmArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Syntax of the text format
+    #[arg(long, value_enum, default_value_t = OutputDialect::Listing)]
+    dialect: OutputDialect,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    #[command(flatten)]
+    listing: ListingArgs,
+    /// Text column of the hex dialect
+    #[arg(long, value_enum, default_value_t = CharsetArg::Ascii)]
+    charset: CharsetArg,
+    /// Keep running and disassemble again when an input or symbol file
+    /// changes
+    #[arg(long)]
+    watch: bool,
+    /// With --watch, only print the instructions that changed since the
+    /// previous run
+    #[arg(long, requires = "watch")]
+    diff: bool,
+}
+
+#[derive(Debug, Args)]
+struct BatchArgs {
+    /// Files, directories that are searched recursively, or glob patterns
+    /// like "roms/*.bin"
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Directory for the listings and summary.txt
+    #[arg(short, long)]
+    output: String,
+    #[arg(long, value_enum, default_value_t = OutputDialect::Listing)]
+    dialect: OutputDialect,
+    /// Files processed at the same time, defaults to one per core
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    options: LoadOptions,
+}
+
+#[derive(Debug, Args)]
+struct HexdumpArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Bytes per row
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u16).range(1..))]
+    columns: u16,
+    #[arg(long, value_enum, default_value_t = CharsetArg::Ascii)]
+    charset: CharsetArg,
+}
+
+/// Layout of the listing dialect
+#[derive(Debug, Args)]
+struct ListingArgs {
+    /// Lowercase mnemonics and hex digits
+    #[arg(long)]
+    lowercase: bool,
+    #[arg(long, value_enum, default_value_t = HexStyleArg::Dollar)]
+    hex_style: HexStyleArg,
+    #[arg(long)]
+    no_offsets: bool,
+    #[arg(long)]
+    no_bytes: bool,
+    /// Show branches as `*+5` instead of the target address
+    #[arg(long)]
+    relative_branches: bool,
+    /// Width of the raw bytes column
+    #[arg(long, default_value_t = 8)]
+    bytes_width: usize,
+    /// Column where comments start
+    #[arg(long, default_value_t = 40)]
+    comment_column: usize,
+    /// Colour instructions by class, auto colours when writing to a terminal
+    #[arg(long, value_enum, default_value_t = ColorArg::Auto)]
+    color: ColorArg,
+}
+
+impl ListingArgs {
+    fn format(&self) -> ListingFormat {
+        ListingFormat {
+            lowercase: self.lowercase,
+            hex_style: match self.hex_style {
+                HexStyleArg::Dollar => HexStyle::Dollar,
+                HexStyleArg::C => HexStyle::C,
+                HexStyleArg::Intel => HexStyle::Intel,
+            },
+            show_offsets: !self.no_offsets,
+            show_bytes: !self.no_bytes,
+            relative_branches: self.relative_branches,
+            bytes_width: self.bytes_width,
+            comment_column: self.comment_column,
+            color: match self.color {
+                ColorArg::Always => true,
+                ColorArg::Never => false,
+                // https://no-color.org
+                ColorArg::Auto => {
+                    io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+                }
+            },
+            ..ListingFormat::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum HexStyleArg {
+    /// $FF
+    Dollar,
+    /// 0xFF
+    C,
+    /// 0FFh
+    Intel,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Args)]
+#[group(id = "pattern", required = true, multiple = false, args = ["bytes", "instruction"])]
+struct SearchArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Hex bytes where ?? matches anything, like "A9 ?? 8D"
+    #[arg(long)]
+    bytes: Option<String>,
+    /// Instruction glob, like "STA $D0??"
+    #[arg(long)]
+    instruction: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct DiffArgs {
+    old: String,
+    new: String,
+    #[command(flatten)]
+    options: LoadOptions,
+}
+
+#[derive(Debug, Args)]
+struct AssembleArgs {
+    file: String,
+    /// Defaults to the source file with a .bin extension
+    #[arg(short, long)]
+    output: Option<String>,
+    #[arg(long, value_enum, default_value_t = CpuArg::Nmos)]
+    cpu: CpuArg,
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(Debug, Args)]
+struct TuiArgs {
+    file: String,
+    #[command(flatten)]
+    options: LoadOptions,
+}
+
+#[derive(Debug, Args)]
+struct MonitorArgs {
+    /// Starts with empty memory when left out
+    file: Option<String>,
+    #[command(flatten)]
+    options: LoadOptions,
+}
+
+#[derive(Debug, Args)]
+struct ProjectArgs {
+    #[command(subcommand)]
+    command: ProjectCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ProjectCommand {
+    /// Start a project for a file, recording its hash and how it is loaded
+    New(NewProjectArgs),
+    /// Check that the files of a project are unchanged and summarise it
+    Check { project: String },
+}
+
+#[derive(Debug, Args)]
+struct NewProjectArgs {
+    file: String,
+    /// Defaults to the input file with a .project.json extension
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Load address of flat binaries, containers have their own
+    #[arg(long, value_parser = number)]
+    origin: Option<usize>,
+    #[arg(long, value_enum)]
+    cpu: Option<CpuArg>,
+    /// Names the hardware registers and ROM routines of the machine
+    #[arg(long, value_enum)]
+    platform: Option<PlatformArg>,
+    /// Symbol file to read every time the project is opened, can be repeated
+    #[arg(long = "symbols", value_name = "FILE")]
+    symbols: Vec<String>,
+    /// Where execution starts, as NAME=ADDRESS, can be repeated
+    #[arg(long = "entry", value_name = "NAME=ADDRESS", value_parser = project::entry_point)]
+    entry_points: Vec<EntryPoint>,
+    /// Replace an existing project file
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CpuArg {
+    #[value(name = "6502")]
+    Nmos,
+    #[value(name = "6502x")]
+    NmosIllegal,
+    #[value(name = "65c02")]
+    Cmos,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PlatformArg {
+    C64,
+    Nes,
+    #[value(name = "apple2")]
+    AppleII,
+    #[value(name = "atari8")]
+    Atari8,
+}
+
+impl From<PlatformArg> for Platform {
+    fn from(value: PlatformArg) -> Self {
+        match value {
+            PlatformArg::C64 => Platform::C64,
+            PlatformArg::Nes => Platform::Nes,
+            PlatformArg::AppleII => Platform::AppleII,
+            PlatformArg::Atari8 => Platform::Atari8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FormatArg {
+    /// Flat binary, whatever its header looks like
+    Raw,
+    Sid,
+    Nsf,
+    Xex,
+    Elf,
+    Crt,
+    O65,
+}
+
+impl From<FormatArg> for Format {
+    fn from(value: FormatArg) -> Self {
+        match value {
+            FormatArg::Raw => Format::Raw,
+            FormatArg::Sid => Format::Sid,
+            FormatArg::Nsf => Format::Nsf,
+            FormatArg::Xex => Format::Xex,
+            FormatArg::Elf => Format::Elf,
+            FormatArg::Crt => Format::Crt,
+            FormatArg::O65 => Format::O65,
+        }
+    }
+}
+
+impl From<CpuArg> for Cpu {
+    fn from(value: CpuArg) -> Self {
+        match value {
+            CpuArg::Nmos => Cpu::Nmos,
+            CpuArg::NmosIllegal => Cpu::NmosIllegal,
+            CpuArg::Cmos => Cpu::Cmos,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    /// Array with one /json/structured style object per file
+    Json,
+    /// One instruction object per line, tagged with its file
+    Jsonl,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputDialect {
+    Listing,
+    Ca65,
+    Acme,
+    /// Listing with the hex and text of every instruction next to it
+    Hex,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CharsetArg {
+    Ascii,
+    Petscii,
+}
+
+impl From<CharsetArg> for Charset {
+    fn from(value: CharsetArg) -> Self {
+        match value {
+            CharsetArg::Ascii => Charset::Ascii,
+            CharsetArg::Petscii => Charset::Petscii,
+        }
+    }
+}
+
+fn number(text: &str) -> Result<usize, String> {
+    parse_number(text).ok_or_else(|| format!("'{}' is not a number", text))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let out = &mut io::stdout().lock();
+
+    let succeeded = match cli.command.unwrap_or(Command::Disasm(cli.disasm)) {
+        Command::Disasm(args) => disassemble_files(&args, out),
+        Command::Stats(args) => {
+            for_each_file(&args, out, |_, image, out| print_statistics(image, out))
+        }
+        Command::Xref(args) => for_each_file(&args, out, |_, image, out| {
+            print_cross_references(image, out)
+        }),
+        Command::Cfg(args) => for_each_file(&args, out, |_, image, out| {
+            writeln!(out, "{}", control_flow_dot(image, &control_flow(image)))?;
+            Ok(())
+        }),
+        Command::Hexdump(args) => for_each_file(&args.input, out, |_, image, out| {
+            let dump = hexdump(image, args.columns.into(), args.charset.into());
+            write!(out, "{}", dump)?;
+            Ok(())
+        }),
+        Command::Batch(args) => match batch::run(&args, out) {
+            Ok(succeeded) => succeeded,
+            Err(error) => report(&args.output, Err(error)),
+        },
+        Command::Search(args) => {
+            for_each_file(&args.input, out, |_, image, out| search(image, &args, out))
+        }
+        Command::Diff(args) => report(&args.new, print_diff(&args, out)),
+        Command::Assemble(args) => report(&args.file, assemble_file(&args, out)),
+        Command::Tui(args) => report(&args.file, browse(&args)),
+        Command::Monitor(args) => report(
+            args.file.as_deref().unwrap_or("monitor"),
+            run_monitor(&args, out),
+        ),
+        Command::Project(args) => report("project", project::run(&args, out)),
+    };
+
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Runs the command on every file. A file that fails is reported and the
+/// rest of the batch still runs.
+fn for_each_file(
+    args: &InputArgs,
+    out: &mut dyn Write,
+    mut command: impl FnMut(&str, &MemoryImage, &mut dyn Write) -> CliResult,
+) -> bool {
+    let mut succeeded = true;
+
+    for file in &args.files {
+        let result = (|| {
+            if args.verbose {
+                writeln!(out, "Disassembly of {}:", file)?;
+            }
+            command(file, &load_file(file, &args.options)?, out)?;
+            if args.verbose {
+                writeln!(out)?;
+            }
+            Ok(())
+        })();
+        succeeded &= report(file, result);
+    }
+
+    succeeded
+}
+
+fn report(file: &str, result: CliResult) -> bool {
+    match result {
+        Ok(()) => true,
+        // Output piped into something like head, which is not a failure
+        Err(error)
+            if error
+                .downcast_ref::<io::Error>()
+                .is_some_and(|error| error.kind() == ErrorKind::BrokenPipe) =>
+        {
+            true
+        }
+        Err(error) => {
+            eprintln!("error: {}: {}", file, error);
+            false
+        }
+    }
+}
+
+fn load_file(file: &str, options: &LoadOptions) -> Result<MemoryImage, Box<dyn Error>> {
+    let project = options.project.as_deref().map(Project::load).transpose()?;
+    let mut input = if file == "-" {
+        let mut input = vec![];
+        io::stdin().read_to_end(&mut input)?;
+        input
+    } else {
+        fs::read(file)?
+    };
+    if let Some(project) = &project {
+        project.check(&project.input, &input)?;
+    }
+    if options.hex {
+        input = parse_hex_text(&String::from_utf8(input)?)?;
+    }
+
+    let origin = options
+        .origin
+        .or(project.as_ref().map(|project| project.origin))
+        .unwrap_or(0);
+    let mut image = match options.input_format {
+        Some(format) => load_as(&input, format.into(), origin)?,
+        None => load_at(&input, origin)?,
+    };
+
+    for path in &options.symbols {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        let symbols = parse_symbols(&text).map_err(|error| format!("{}: {}", path, error))?;
+        for (address, name) in symbols {
+            image.add_label(address, name);
+        }
+    }
+    if let Some(platform) = options.platform {
+        Platform::from(platform).apply(&mut image);
+    }
+    if let (Some(project), Some(path)) = (&project, &options.project) {
+        project.add_symbols(project_directory(path), &mut image)?;
+        project.apply(&mut image);
+    }
+    if let Some(cpu) = options.cpu {
+        image.set_cpu(cpu.into());
+    }
+
+    if options.start.is_some() || options.end.is_some() {
+        let range: Range<usize> = options.start.unwrap_or(0)..options.end.unwrap_or(usize::MAX);
+        image = image.crop(range);
+    }
+
+    Ok(image)
+}
+
+/// Paths in a project are relative to the directory it is in
+fn project_directory(path: &str) -> &Path {
+    Path::new(path).parent().unwrap_or(Path::new(""))
+}
+
+/// Opens the browser. The project file remembers the origin, cpu and
+/// platform, so they only have to be given the first time.
+fn browse(args: &TuiArgs) -> CliResult {
+    let project_path = args
+        .options
+        .project
+        .clone()
+        .unwrap_or_else(|| format!("{}.project.json", args.file));
+    let bytes = fs::read(&args.file)?;
+    let mut project = match Project::load(&project_path) {
+        Ok(project) => project,
+        Err(ProjectError::Io { error, .. }) if error.kind() == ErrorKind::NotFound => {
+            Project::new(project::relative_path(&args.file, &project_path))
+        }
+        Err(error) => return Err(error.into()),
+    };
+    project.check(&project.input, &bytes)?;
+    if !project.hashes.contains_key(&project.input) {
+        project.record(&project.input.clone(), &bytes);
+    }
+
+    let mut options = args.options.clone();
+    // The project is applied by the browser, where it can change
+    options.project = None;
+    match options.origin {
+        Some(origin) => project.origin = origin,
+        None => options.origin = Some(project.origin),
+    }
+    if let Some(cpu) = options.cpu {
+        project.cpu = Some(cpu.into());
+    }
+    if let Some(platform) = options.platform.take() {
+        project.platform = Some(platform.into());
+    }
+
+    let mut image = load_file(&args.file, &options)?;
+    project.add_symbols(project_directory(&project_path), &mut image)?;
+    tui::run(tui::App::new(image, project, project_path))?;
+    Ok(())
+}
+
+fn run_monitor(args: &MonitorArgs, out: &mut dyn Write) -> CliResult {
+    let mut image = match &args.file {
+        Some(file) => load_file(file, &args.options)?,
+        None => MemoryImage::new(),
+    };
+    if let Some(cpu) = args.options.cpu {
+        image.set_cpu(cpu.into());
+    }
+
+    let mut monitor = Monitor::new(image);
+    // Prompts would only clutter the output of a script
+    let interactive = io::stdin().is_terminal();
+    let mut lines = io::stdin().lock().lines();
+
+    while !monitor.is_done() {
+        if interactive {
+            write!(out, "{}", monitor.prompt())?;
+            out.flush()?;
+        }
+        match lines.next() {
+            Some(line) => monitor.execute(&line?, out)?,
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn disassemble_files(args: &DisasmArgs, out: &mut dyn Write) -> bool {
+    if args.watch {
+        return watch::run(args, out);
+    }
+    print_files(args, out)
+}
+
+fn print_files(args: &DisasmArgs, out: &mut dyn Write) -> bool {
+    // Headers from --verbose would break the structured formats
+    let input = InputArgs {
+        verbose: args.input.verbose && args.format == OutputFormat::Text,
+        ..args.input.clone()
+    };
+    let format = args.listing.format();
+
+    match args.format {
+        OutputFormat::Text => for_each_file(&input, out, |_, image, out| {
+            print_disassembly(image, args.dialect, args.charset.into(), &format, out)
+        }),
+        OutputFormat::Json => {
+            let mut separator = "";
+            let succeeded = report("stdout", write!(out, "[").map_err(Into::into))
+                && for_each_file(&input, out, |file, image, out| {
+                    write!(out, "{}", separator)?;
+                    separator = ",";
+
+                    let disassembly = StructuredDisassembly {
+                        metadata: image.metadata().to_vec(),
+                        instructions: disassemble_image(image),
+                    };
+                    serde_json::to_writer(&mut *out, &FileDisassembly { file, disassembly })?;
+                    Ok(())
+                });
+            succeeded & report("stdout", writeln!(out, "]").map_err(Into::into))
+        }
+        // One instruction per line, written as soon as it is decoded
+        OutputFormat::Jsonl => for_each_file(&input, out, |file, image, out| {
+            for instruction in disassemble_image_iter(image) {
+                serde_json::to_writer(&mut *out, &FileInstruction { file, instruction })?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }),
+        OutputFormat::Csv => {
+            report(
+                "stdout",
+                writeln!(out, "file,offset,bytes,operation,address,label,comment")
+                    .map_err(Into::into),
+            ) && for_each_file(&input, out, |file, image, out| {
+                for instruction in disassemble_image_iter(image) {
+                    let fields = [
+                        file.to_string(),
+                        instruction.offset.to_string(),
+                        instruction.bytes,
+                        instruction.operation,
+                        instruction.address,
+                        instruction.label.unwrap_or_default(),
+                        instruction.comment.unwrap_or_default(),
+                    ];
+                    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                    writeln!(out, "{}", fields.join(","))?;
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+/// Same structure as the /json/structured endpoint, with the file it came from
+#[derive(Serialize)]
+struct FileDisassembly<'a> {
+    file: &'a str,
+    #[serde(flatten)]
+    disassembly: StructuredDisassembly,
+}
+
+#[derive(Serialize)]
+struct FileInstruction<'a> {
+    file: &'a str,
+    #[serde(flatten)]
+    instruction: Instruction,
+}
+
+fn print_disassembly(
+    image: &MemoryImage,
+    dialect: OutputDialect,
+    charset: Charset,
+    format: &ListingFormat,
+    out: &mut dyn Write,
+) -> CliResult {
+    let dialect = match dialect {
+        OutputDialect::Listing => return print_listing(image, format, out),
+        OutputDialect::Hex => return print_hex_listing(image, charset, format, out),
+        OutputDialect::Ca65 => Dialect::Ca65,
+        OutputDialect::Acme => Dialect::Acme,
+    };
+
+    write!(out, "{}", assembly_source(image, dialect))?;
+    Ok(())
+}
+
+fn print_listing(image: &MemoryImage, format: &ListingFormat, out: &mut dyn Write) -> CliResult {
+    for property in image.metadata() {
+        writeln!(out, "; {}: {}", property.name, property.value)?;
+    }
+
+    for line in disassemble_image(image) {
+        print_instruction(&line, "", format, out)?;
+    }
+
+    Ok(())
+}
+
+fn print_hex_listing(
+    image: &MemoryImage,
+    charset: Charset,
+    format: &ListingFormat,
+    out: &mut dyn Write,
+) -> CliResult {
+    const COLUMNS: usize = 8;
+    // Offsets and bytes are already in the hex columns
+    let format = ListingFormat {
+        show_offsets: false,
+        show_bytes: false,
+        ..format.clone()
+    };
+
+    for property in image.metadata() {
+        writeln!(out, "; {}: {}", property.name, property.value)?;
+    }
+
+    for line in hex_listing(image, COLUMNS, charset) {
+        if let Some(label) = &line.label {
+            writeln!(out, "{}:", label)?;
+        }
+        let code = line
+            .instruction
+            .as_ref()
+            .map(|instruction| format.format_commented(instruction))
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "{:04X}  {: <hex$}  {: <text$}  {}",
+                line.offset,
+                line.bytes,
+                line.text,
+                code,
+                hex = COLUMNS * 3 - 1,
+                text = COLUMNS
+            )
+            .trim_end()
+        )?;
+    }
+
+    Ok(())
+}
+
+fn print_instruction(
+    line: &Instruction,
+    prefix: &str,
+    format: &ListingFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if let Some(label) = &line.label {
+        writeln!(out, "{}{}:", prefix, label)?;
+    }
+    writeln!(out, "{}{}", prefix, format.format_commented(line))
+}
+
+fn print_statistics(image: &MemoryImage, out: &mut dyn Write) -> CliResult {
+    let statistics = statistics(image);
+
+    writeln!(out, "bytes:        {}", statistics.bytes)?;
+    writeln!(out, "instructions: {}", statistics.instructions)?;
+    writeln!(out, "unknown:      {}", statistics.unknown)?;
+    writeln!(out, "illegal:      {}", statistics.illegal)?;
+    writeln!(out, "incomplete:   {}", statistics.incomplete)?;
+
+    writeln!(out, "\noperations:")?;
+    for (operation, count) in &statistics.operations {
+        writeln!(out, "  {: <4} {}", operation, count)?;
+    }
+
+    writeln!(out, "\naddress modes:")?;
+    for (address_mode, count) in &statistics.address_modes {
+        writeln!(out, "  {: <18} {}", address_mode, count)?;
+    }
+
+    Ok(())
+}
+
+fn print_cross_references(image: &MemoryImage, out: &mut dyn Write) -> CliResult {
+    let mut previous = None;
+
+    for reference in cross_references(image) {
+        if previous != Some(reference.to) {
+            match image.label(reference.to) {
+                Some(label) => writeln!(out, "{:04X} {}", reference.to, label)?,
+                None => writeln!(out, "{:04X}", reference.to)?,
+            }
+            previous = Some(reference.to);
+        }
+        writeln!(out, "    {:04X} {:?}", reference.from, reference.kind)?;
+    }
+
+    Ok(())
+}
+
+fn search(image: &MemoryImage, args: &SearchArgs, out: &mut dyn Write) -> CliResult {
+    if let Some(bytes) = &args.bytes {
+        for address in search_bytes(image, &parse_pattern(bytes)?) {
+            writeln!(out, "{:04X}", address)?;
+        }
+    }
+
+    if let Some(pattern) = &args.instruction {
+        for line in search_instructions(image, pattern) {
+            writeln!(out, "{}", line)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn print_diff(args: &DiffArgs, out: &mut dyn Write) -> CliResult {
+    let old = disassemble_image(&load_file(&args.old, &args.options)?);
+    let new = disassemble_image(&load_file(&args.new, &args.options)?);
+
+    writeln!(out, "--- {}", args.old)?;
+    writeln!(out, "+++ {}", args.new)?;
+    print_changes(&old, &new, &ListingFormat::default(), out)?;
+
+    Ok(())
+}
+
+/// Prints the instructions that were removed or added, returns how many
+fn print_changes(
+    old: &[Instruction],
+    new: &[Instruction],
+    format: &ListingFormat,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let changes = instruction_changes(old, new);
+    for change in &changes {
+        match change {
+            Change::Delete(line) => print_instruction(line, "-", format, out)?,
+            Change::Insert(line) => print_instruction(line, "+", format, out)?,
+            Change::Equal(_) => {}
+        }
+    }
+
+    Ok(changes.len())
+}
+
+fn assemble_file(args: &AssembleArgs, out: &mut dyn Write) -> CliResult {
+    let source = fs::read_to_string(&args.file)?;
+    let assembled = assemble(&source, args.cpu.into())?;
+
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => Path::new(&args.file)
+            .with_extension("bin")
+            .to_string_lossy()
+            .to_string(),
+    };
+    fs::write(&output, &assembled.bytes)?;
+
+    if args.verbose {
+        writeln!(
+            out,
+            "Wrote {} bytes from ${:04X} to {}",
+            assembled.bytes.len(),
+            assembled.origin,
+            output
+        )?;
+    }
+
+    Ok(())
+}
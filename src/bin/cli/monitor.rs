@@ -0,0 +1,360 @@
+use std::{error::Error, io::Write};
+
+use mos_6502_disassembler::{
+    assemble_instruction, disassemble_image_iter, parse_number, parse_pattern, search_bytes,
+    Charset, MemoryImage,
+};
+
+const HELP: &str = "\
+d [start [end]]        disassemble
+m [start [end]]        memory dump
+a address [instr]      assemble, an empty line ends
+f start end bytes      fill with a repeating pattern
+h start end bytes      hunt for bytes, ?? matches anything
+> address bytes        write bytes
+g [address]            run
+x                      exit
+Numbers are hex, prefix decimal with + and binary with %";
+
+/// Lines shown by `d` and `m` when no end address is given
+const PAGE_LINES: usize = 20;
+const DUMP_COLUMNS: usize = 16;
+
+type MonitorResult = Result<(), Box<dyn Error>>;
+
+/// Machine language monitor in the style of the VICE and Apple II ones,
+/// working on a memory image instead of a running machine
+pub struct Monitor {
+    image: MemoryImage,
+    /// Where `d` continues when no address is given
+    next_disassembly: usize,
+    /// Where `m` continues when no address is given
+    next_dump: usize,
+    /// Address of the next instruction while in assembly mode
+    assembling: Option<usize>,
+    done: bool,
+}
+
+impl Monitor {
+    pub fn new(image: MemoryImage) -> Self {
+        let start = image
+            .entry_points()
+            .first()
+            .map(|entry| entry.address)
+            .or_else(|| {
+                image
+                    .segments()
+                    .iter()
+                    .map(|segment| segment.load_address)
+                    .min()
+            })
+            .unwrap_or_default();
+
+        Monitor {
+            image,
+            next_disassembly: start,
+            next_dump: start,
+            assembling: None,
+            done: false,
+        }
+    }
+
+    pub fn prompt(&self) -> String {
+        match self.assembling {
+            Some(address) => format!("a {:04x} ", address),
+            None => format!("(C:${:04x}) ", self.next_disassembly),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Runs one line of input. Mistakes are reported in the output, like
+    /// they are on a real monitor, and only writing the output can fail.
+    pub fn execute(&mut self, line: &str, out: &mut dyn Write) -> std::io::Result<()> {
+        let result = match self.assembling {
+            Some(address) => self.assemble_line(address, line, out),
+            None => self.command(line, out),
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(error) => match error.downcast::<std::io::Error>() {
+                Ok(error) => Err(*error),
+                Err(error) => writeln!(out, "error: {}", error),
+            },
+        }
+    }
+
+    fn command(&mut self, line: &str, out: &mut dyn Write) -> MonitorResult {
+        let line = line.trim();
+        // `>c000 a9` needs no space after the command
+        let (command, rest) = match line.strip_prefix('>') {
+            Some(rest) => (">", rest.trim()),
+            None => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+        };
+        let rest = rest.trim();
+        let args: Vec<&str> = rest
+            .split([' ', ','])
+            .filter(|arg| !arg.is_empty())
+            .collect();
+
+        match command.to_ascii_lowercase().as_str() {
+            "" => Ok(()),
+            "d" => self.disassemble(&args, out),
+            "m" => self.dump(&args, out),
+            "a" => {
+                let (address, instruction) =
+                    rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let address = number(address)?;
+                self.assembling = Some(address);
+                self.assemble_line(address, instruction, out)
+            }
+            "f" => {
+                let [start, end, bytes @ ..] = &args[..] else {
+                    return Err("usage: f start end bytes".into());
+                };
+                let bytes = parse_bytes(bytes)?;
+                if bytes.is_empty() {
+                    return Err("nothing to fill with".into());
+                }
+                for (address, &byte) in (number(start)?..=number(end)?).zip(bytes.iter().cycle()) {
+                    self.image.write(address, byte);
+                }
+                Ok(())
+            }
+            "h" => {
+                let [start, end, pattern @ ..] = &args[..] else {
+                    return Err("usage: h start end bytes".into());
+                };
+                let range = number(start)?..number(end)? + 1;
+                let pattern = parse_pattern(&pattern.join(" "))?;
+                let found: Vec<String> = search_bytes(&self.image.crop(range), &pattern)
+                    .into_iter()
+                    .map(|address| format!("{:04x}", address))
+                    .collect();
+                writeln!(out, "{}", found.join(" "))?;
+                Ok(())
+            }
+            ">" => {
+                let [address, bytes @ ..] = &args[..] else {
+                    return Err("usage: > address bytes".into());
+                };
+                let address = number(address)?;
+                for (offset, byte) in parse_bytes(bytes)?.into_iter().enumerate() {
+                    self.image.write(address + offset, byte);
+                }
+                Ok(())
+            }
+            "g" => Err("emulation is not available, g can't run code yet".into()),
+            "x" | "q" | "exit" | "quit" => {
+                self.done = true;
+                Ok(())
+            }
+            "?" | "help" => {
+                writeln!(out, "{}", HELP)?;
+                Ok(())
+            }
+            command => Err(format!("unknown command '{}', ? lists the commands", command).into()),
+        }
+    }
+
+    /// Assembles the line at the address and moves on to the next one. An
+    /// empty line leaves assembly mode.
+    fn assemble_line(&mut self, address: usize, line: &str, out: &mut dyn Write) -> MonitorResult {
+        let line = line.trim();
+        if line.is_empty() {
+            self.assembling = None;
+            return Ok(());
+        }
+
+        let bytes = assemble_instruction(line, address, self.image.cpu())?;
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.image.write(address + offset, byte);
+        }
+
+        let end = address + bytes.len();
+        self.print_disassembly(address..end, usize::MAX, out)?;
+        self.assembling = Some(end);
+        self.next_disassembly = end;
+        Ok(())
+    }
+
+    fn disassemble(&mut self, args: &[&str], out: &mut dyn Write) -> MonitorResult {
+        let start = args
+            .first()
+            .map_or(Ok(self.next_disassembly), |arg| number(arg))?;
+        let (end, lines) = match args.get(1) {
+            Some(end) => (number(end)? + 1, usize::MAX),
+            // Longest instructions are three bytes
+            None => (start + PAGE_LINES * 3, PAGE_LINES),
+        };
+
+        self.next_disassembly = self.print_disassembly(start..end, lines, out)?;
+        Ok(())
+    }
+
+    /// Prints the instructions that start inside the range and returns the
+    /// address after the last one
+    fn print_disassembly(
+        &self,
+        range: std::ops::Range<usize>,
+        lines: usize,
+        out: &mut dyn Write,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut next = range.start;
+        // The last instruction may continue past the end of the range
+        let cropped = self.image.crop(range.start..range.end + 2);
+
+        for instruction in disassemble_image_iter(&cropped)
+            .take_while(|instruction| instruction.offset < range.end)
+            .take(lines)
+        {
+            writeln!(out, "{}", instruction)?;
+            next = instruction.offset + instruction.bytes.split_whitespace().count();
+        }
+
+        Ok(next)
+    }
+
+    fn dump(&mut self, args: &[&str], out: &mut dyn Write) -> MonitorResult {
+        let start = args.first().map_or(Ok(self.next_dump), |arg| number(arg))?;
+        let end = match args.get(1) {
+            Some(end) => number(end)? + 1,
+            None => start + PAGE_LINES / 2 * DUMP_COLUMNS,
+        };
+
+        for row in (start..end).step_by(DUMP_COLUMNS) {
+            let addresses = row..(row + DUMP_COLUMNS).min(end);
+            let bytes: Vec<Option<u8>> =
+                addresses.map(|address| self.image.read(address)).collect();
+
+            let hex: Vec<String> = bytes
+                .iter()
+                .map(|byte| byte.map_or("--".into(), |byte| format!("{:02x}", byte)))
+                .collect();
+            let text: String = bytes
+                .iter()
+                .map(|byte| byte.map_or(' ', |byte| Charset::Ascii.render(byte)))
+                .collect();
+
+            writeln!(
+                out,
+                ">C:{:04x}  {:<width$}  {}",
+                row,
+                hex.join(" "),
+                text,
+                width = DUMP_COLUMNS * 3 - 1
+            )?;
+        }
+
+        self.next_dump = end;
+        Ok(())
+    }
+}
+
+/// Monitor numbers are hex unless they say otherwise: `+` for decimal and
+/// `%` for binary
+fn number(text: &str) -> Result<usize, String> {
+    let error = || format!("'{}' is not a number", text);
+
+    if let Some(decimal) = text.strip_prefix('+') {
+        decimal.parse().map_err(|_| error())
+    } else if text.starts_with('%') {
+        parse_number(text).ok_or_else(error)
+    } else {
+        let hex = text
+            .strip_prefix('$')
+            .or_else(|| text.strip_prefix("0x"))
+            .unwrap_or(text);
+        usize::from_str_radix(hex, 16).map_err(|_| error())
+    }
+}
+
+fn parse_bytes(args: &[&str]) -> Result<Vec<u8>, String> {
+    args.iter()
+        .map(|arg| {
+            number(arg).and_then(|value| {
+                u8::try_from(value).map_err(|_| format!("'{}' does not fit in a byte", arg))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use mos_6502_disassembler::Segment;
+
+    use super::*;
+
+    fn run(monitor: &mut Monitor, lines: &[&str]) -> String {
+        let mut out = vec![];
+        for line in lines {
+            monitor.execute(line, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn monitor() -> Monitor {
+        let image =
+            MemoryImage::new().with_segment(Segment::new("code", 0xc000, vec![0xa9, 0xbd, 0x60]));
+        Monitor::new(image)
+    }
+
+    #[test]
+    fn test_poke_and_disassemble() {
+        let mut monitor = monitor();
+
+        let output = run(&mut monitor, &[">c001 00", "d c000 c002"]);
+
+        assert_eq!(
+            output,
+            "C000   A9 00         LDA #$00\nC002   60            RTS\n"
+        );
+        assert_eq!(monitor.prompt(), "(C:$c003) ");
+    }
+
+    #[test]
+    fn test_assemble_mode() {
+        let mut monitor = monitor();
+
+        let output = run(&mut monitor, &["a c000 ldx #$01", "inx", "", "d c000 c002"]);
+
+        assert!(output.ends_with("C000   A2 01         LDX #$01\nC002   E8            INX\n"));
+        assert_eq!(monitor.prompt(), "(C:$c003) ");
+    }
+
+    #[test]
+    fn test_fill_and_hunt() {
+        let mut monitor = monitor();
+
+        let output = run(&mut monitor, &["f c000 c005 ea 60", "h c000 c005 ea ??"]);
+
+        assert_eq!(output, "c000 c002 c004\n");
+        assert_eq!(monitor.image.read(0xc005), Some(0x60));
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut monitor = monitor();
+
+        let output = run(&mut monitor, &["m c000 c003"]);
+
+        assert_eq!(
+            output,
+            format!(">C:c000  a9 bd 60 --{}  ..` \n", " ".repeat(36))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut monitor = monitor();
+
+        let output = run(&mut monitor, &["g", "> c000 100", "zz", "x"]);
+
+        assert_eq!(output.lines().count(), 3);
+        assert!(output.lines().all(|line| line.starts_with("error: ")));
+        assert!(monitor.is_done());
+    }
+}
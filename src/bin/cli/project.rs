@@ -0,0 +1,176 @@
+use std::{error::Error, fs, io::Write, path::Path};
+
+use mos_6502_disassembler::{parse_number, EntryPoint, Project};
+
+use crate::{project_directory, CliResult, NewProjectArgs, ProjectArgs, ProjectCommand};
+
+pub fn run(args: &ProjectArgs, out: &mut dyn Write) -> CliResult {
+    match &args.command {
+        ProjectCommand::New(args) => create(args, out),
+        ProjectCommand::Check { project } => check(project, out),
+    }
+}
+
+fn create(args: &NewProjectArgs, out: &mut dyn Write) -> CliResult {
+    let path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| format!("{}.project.json", args.file));
+    if !args.force && Path::new(&path).exists() {
+        return Err(format!("{} already exists, use --force to replace it", path).into());
+    }
+
+    let mut project = Project::for_input(relative_path(&args.file, &path), &fs::read(&args.file)?);
+    project.origin = args.origin.unwrap_or(0);
+    project.cpu = args.cpu.map(Into::into);
+    project.platform = args.platform.map(Into::into);
+    project.entry_points = args.entry_points.clone();
+    for symbols in &args.symbols {
+        let name = relative_path(symbols, &path);
+        project.record(&name, &fs::read(symbols)?);
+        project.symbols.push(name);
+    }
+
+    project.save(&path)?;
+    writeln!(out, "Created {}", path)?;
+    Ok(())
+}
+
+/// Checks every file of the project against its hash
+fn check(path: &str, out: &mut dyn Write) -> CliResult {
+    let project = Project::load(path)?;
+    let directory = project_directory(path);
+    let mut changed = 0;
+
+    for file in std::iter::once(&project.input).chain(&project.symbols) {
+        let status = match fs::read(directory.join(file)) {
+            Err(error) => {
+                changed += 1;
+                error.to_string()
+            }
+            Ok(_) if !project.hashes.contains_key(file) => "no hash recorded".into(),
+            Ok(bytes) => match project.check(file, &bytes) {
+                Ok(()) => "ok".into(),
+                Err(_) => {
+                    changed += 1;
+                    "changed".into()
+                }
+            },
+        };
+        writeln!(out, "{}: {}", file, status)?;
+    }
+
+    writeln!(
+        out,
+        "{} labels, {} comments, {} data ranges, {} entry points",
+        project.labels.len(),
+        project.comments.len(),
+        project.data_ranges.len(),
+        project.entry_points.len()
+    )?;
+
+    match changed {
+        0 => Ok(()),
+        _ => Err(format!("{} files are missing or changed", changed).into()),
+    }
+}
+
+/// Path of a file as seen from the directory of the project, so that the
+/// two can move together
+pub fn relative_path(file: &str, project: &str) -> String {
+    let directory = project_directory(project);
+    match (
+        fs::canonicalize(file),
+        fs::canonicalize(directory_or_current(directory)),
+    ) {
+        (Ok(file), Ok(directory)) => file
+            .strip_prefix(&directory)
+            .map(|relative| relative.display().to_string())
+            .unwrap_or_else(|_| file.display().to_string()),
+        _ => file.to_string(),
+    }
+}
+
+fn directory_or_current(directory: &Path) -> &Path {
+    if directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        directory
+    }
+}
+
+pub fn entry_point(text: &str) -> Result<EntryPoint, Box<dyn Error + Send + Sync>> {
+    let (name, address) = text.split_once('=').ok_or("expected NAME=ADDRESS")?;
+    let address = parse_number(address.trim())
+        .ok_or_else(|| format!("'{}' is not a number", address.trim()))?;
+    Ok(EntryPoint {
+        name: name.trim().to_string(),
+        address,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use crate::{load_file, Cli, Command};
+
+    use super::*;
+
+    fn parse(arguments: &[&str]) -> ProjectArgs {
+        let cli = Cli::try_parse_from(arguments).unwrap();
+        let Some(Command::Project(args)) = cli.command else {
+            panic!("expected the project command");
+        };
+        args
+    }
+
+    #[test]
+    fn test_new_and_check() {
+        let directory = std::env::temp_dir().join(format!("cli-project-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("game.bin").display().to_string();
+        let project = directory.join("game.json").display().to_string();
+        fs::write(&file, [0x20, 0xd2, 0xff, 0x60]).unwrap();
+
+        let args = parse(&[
+            "cli",
+            "project",
+            "new",
+            &file,
+            "-o",
+            &project,
+            "--origin",
+            "$c000",
+            "--platform",
+            "c64",
+            "--entry",
+            "start=$c000",
+        ]);
+        run(&args, &mut vec![]).unwrap();
+        assert!(run(&args, &mut vec![]).is_err(), "replaced without --force");
+
+        let saved = Project::load(&project).unwrap();
+        assert_eq!(saved.input, "game.bin");
+        assert_eq!(saved.entry_points[0].address, 0xc000);
+
+        let cli = Cli::try_parse_from(["cli", "--project", &project, &file]).unwrap();
+        let options = &cli.disasm.input.options;
+        let image = load_file(&file, options).unwrap();
+        assert_eq!(image.label(0xc000), Some("start"));
+        assert_eq!(image.label(0xffd2), Some("CHROUT"));
+
+        let mut out = vec![];
+        run(&parse(&["cli", "project", "check", &project]), &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("game.bin: ok\n"));
+
+        fs::write(&file, [0xea]).unwrap();
+        assert!(load_file(&file, options).is_err());
+        assert!(run(&parse(&["cli", "project", "check", &project]), &mut vec![]).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
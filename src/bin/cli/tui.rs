@@ -0,0 +1,622 @@
+use std::{fs, io, ops::Range};
+
+use mos_6502_disassembler::{
+    decode_image, disassemble_image, parse_number, Charset, Decoded, Instruction, MemoryImage,
+    Operation, Project,
+};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    Frame,
+};
+
+const HELP: &str = "↑↓ move  g jump  ⏎ follow  ⌫ back  n label  ; comment  v select  d data  c code  s save  q quit";
+const HEX_COLUMNS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptKind {
+    Jump,
+    Label,
+    Comment,
+}
+
+impl PromptKind {
+    fn title(self) -> &'static str {
+        match self {
+            PromptKind::Jump => "Jump to",
+            PromptKind::Label => "Label",
+            PromptKind::Comment => "Comment",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+/// State of the browser. Keys are handled here and drawing only reads it, so
+/// the behaviour can be tested without a terminal.
+pub struct App {
+    /// Image as it was loaded, the project is applied on top of it
+    base: MemoryImage,
+    image: MemoryImage,
+    project: Project,
+    project_path: String,
+    decoded: Vec<Decoded>,
+    lines: Vec<Instruction>,
+    cursor: usize,
+    scroll: usize,
+    /// Addresses to return to after following targets
+    back: Vec<usize>,
+    /// Line where the selection started
+    mark: Option<usize>,
+    prompt: Option<Prompt>,
+    message: String,
+    dirty: bool,
+    quit: bool,
+}
+
+impl App {
+    pub fn new(base: MemoryImage, project: Project, project_path: impl Into<String>) -> Self {
+        let mut app = App {
+            image: base.clone(),
+            base,
+            project,
+            project_path: project_path.into(),
+            decoded: vec![],
+            lines: vec![],
+            cursor: 0,
+            scroll: 0,
+            back: vec![],
+            mark: None,
+            prompt: None,
+            message: HELP.into(),
+            dirty: false,
+            quit: false,
+        };
+        app.refresh();
+        if let Some(entry) = app.image.entry_points().first() {
+            let address = entry.address;
+            app.go_to(address);
+        }
+        app
+    }
+
+    /// Decodes the image again after the project changed, keeping the cursor
+    /// on the same address
+    fn refresh(&mut self) {
+        let address = self.address();
+
+        self.image = self.base.clone();
+        self.project.apply(&mut self.image);
+        self.decoded = decode_image(&self.image);
+        self.lines = disassemble_image(&self.image);
+
+        if let Some(address) = address {
+            self.go_to(address);
+        }
+    }
+
+    fn address(&self) -> Option<usize> {
+        self.decoded.get(self.cursor).map(|decoded| decoded.offset)
+    }
+
+    fn line_at(&self, address: usize) -> Option<usize> {
+        self.decoded
+            .iter()
+            .position(|decoded| decoded.offset <= address && address < decoded.end())
+    }
+
+    fn go_to(&mut self, address: usize) -> bool {
+        match self.line_at(address) {
+            Some(line) => {
+                self.cursor = line;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let last = self.lines.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+
+    /// Follows the branch, jump or operand address of the current line
+    fn follow(&mut self) {
+        let Some(decoded) = self.decoded.get(self.cursor) else {
+            return;
+        };
+        let from = decoded.offset;
+
+        match decoded.target() {
+            Some(target) if self.go_to(target) => {
+                self.back.push(from);
+                self.message = format!("${:04X} -> ${:04X}", from, target);
+            }
+            Some(target) => self.message = format!("${:04X} is not loaded", target),
+            None => self.message = "No target on this line".into(),
+        }
+    }
+
+    fn go_back(&mut self) {
+        match self.back.pop() {
+            Some(address) => {
+                self.go_to(address);
+            }
+            None => self.message = "Nothing to go back to".into(),
+        }
+    }
+
+    /// Addresses covered by the selection, or by the current line
+    fn selection(&self) -> Option<Range<usize>> {
+        let anchor = self.mark.unwrap_or(self.cursor);
+        let first = self.decoded.get(anchor.min(self.cursor))?;
+        let last = self.decoded.get(anchor.max(self.cursor))?;
+        Some(first.offset..last.end())
+    }
+
+    fn mark_data(&mut self) {
+        let Some(range) = self.selection() else {
+            return;
+        };
+
+        self.project.data_ranges.push(range.clone());
+        self.project.data_ranges = merge(&self.project.data_ranges);
+        self.project
+            .code_starts
+            .retain(|start| !range.contains(start));
+        self.message = format!("${:04X}-${:04X} marked as data", range.start, range.end - 1);
+        self.changed();
+    }
+
+    fn mark_code(&mut self) {
+        let Some(range) = self.selection() else {
+            return;
+        };
+
+        self.project.data_ranges = subtract(&self.project.data_ranges, &range);
+        self.project.code_starts.insert(range.start);
+        self.message = format!("${:04X}-${:04X} marked as code", range.start, range.end - 1);
+        self.changed();
+    }
+
+    fn set_label(&mut self, name: &str) {
+        let Some(address) = self.address() else {
+            return;
+        };
+
+        let name = name.trim();
+        if name.is_empty() {
+            self.project.labels.remove(&address);
+        } else if name.chars().any(char::is_whitespace) {
+            self.message = format!("'{}' is not a valid label", name);
+            return;
+        } else {
+            self.project.labels.insert(address, name.to_string());
+        }
+        self.changed();
+    }
+
+    fn set_comment(&mut self, comment: &str) {
+        let Some(address) = self.address() else {
+            return;
+        };
+
+        let comment = comment.trim();
+        if comment.is_empty() {
+            self.project.comments.remove(&address);
+        } else {
+            self.project.comments.insert(address, comment.to_string());
+        }
+        self.changed();
+    }
+
+    /// Takes the name of a label, or an address with or without a `$`
+    fn jump(&mut self, target: &str) {
+        let target = target.trim();
+        // Labels first, a label such as `beef` is also valid hex
+        let address = self
+            .image
+            .labels()
+            .iter()
+            .find(|(_, name)| name.as_str() == target)
+            .map(|(&address, _)| address)
+            .or_else(|| parse_number(target))
+            .or_else(|| usize::from_str_radix(target, 16).ok());
+
+        match address {
+            Some(address) => {
+                let from = self.address();
+                if self.go_to(address) {
+                    self.back.extend(from);
+                } else {
+                    self.message = format!("${:04X} is not loaded", address);
+                }
+            }
+            None => self.message = format!("'{}' is not an address or a label", target),
+        }
+    }
+
+    fn changed(&mut self) {
+        self.mark = None;
+        self.dirty = true;
+        self.refresh();
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        fs::write(&self.project_path, self.project.to_json())?;
+        self.dirty = false;
+        self.message = format!("Saved {}", self.project_path);
+        Ok(())
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if let Some(prompt) = &mut self.prompt {
+            match key.code {
+                KeyCode::Char(c) => prompt.input.push(c),
+                KeyCode::Backspace => {
+                    prompt.input.pop();
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Enter => {
+                    let Prompt { kind, input } = self.prompt.take().expect("prompt is open");
+                    self.message = HELP.into();
+                    match kind {
+                        PromptKind::Jump => self.jump(&input),
+                        PromptKind::Label => self.set_label(&input),
+                        PromptKind::Comment => self.set_comment(&input),
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        self.message = HELP.into();
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-20),
+            KeyCode::PageDown => self.move_by(20),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.lines.len().saturating_sub(1),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.follow(),
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => self.go_back(),
+            KeyCode::Char('g') => self.open_prompt(PromptKind::Jump, String::new()),
+            KeyCode::Char('n') => {
+                let current = self
+                    .address()
+                    .and_then(|address| self.image.label(address))
+                    .unwrap_or_default()
+                    .to_string();
+                self.open_prompt(PromptKind::Label, current);
+            }
+            KeyCode::Char(';') => {
+                let current = self
+                    .address()
+                    .and_then(|address| self.image.comment(address))
+                    .unwrap_or_default()
+                    .to_string();
+                self.open_prompt(PromptKind::Comment, current);
+            }
+            KeyCode::Char('v') => {
+                self.mark = match self.mark {
+                    Some(_) => None,
+                    None => Some(self.cursor),
+                }
+            }
+            KeyCode::Esc => self.mark = None,
+            KeyCode::Char('d') => self.mark_data(),
+            KeyCode::Char('c') => self.mark_code(),
+            KeyCode::Char('s') => {
+                if let Err(error) = self.save() {
+                    self.message = format!("Could not save {}: {}", self.project_path, error);
+                }
+            }
+            KeyCode::Char('q') if self.dirty => {
+                // A second q quits without saving
+                self.dirty = false;
+                self.message = "Unsaved changes, press s to save or q again to quit".into();
+            }
+            KeyCode::Char('q') => self.quit = true,
+            _ => {}
+        }
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind, input: String) {
+        self.prompt = Some(Prompt { kind, input });
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [body, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [listing, hex] = Layout::horizontal([
+            Constraint::Min(40),
+            Constraint::Length((HEX_COLUMNS * 4 + 8) as u16),
+        ])
+        .areas(body);
+
+        self.draw_listing(frame, listing);
+        self.draw_hex(frame, hex);
+
+        let status_line = match &self.prompt {
+            Some(prompt) => format!("{}: {}_", prompt.kind.title(), prompt.input),
+            None => self.message.clone(),
+        };
+        frame.render_widget(
+            Paragraph::new(status_line).style(Style::new().add_modifier(Modifier::REVERSED)),
+            status,
+        );
+    }
+
+    fn draw_listing(&mut self, frame: &mut Frame, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if height > 0 && self.cursor >= self.scroll + height {
+            self.scroll = self.cursor + 1 - height;
+        }
+
+        let selected = self
+            .mark
+            .map(|mark| mark.min(self.cursor)..=mark.max(self.cursor));
+
+        let rows: Vec<Line> = self
+            .lines
+            .iter()
+            .zip(&self.decoded)
+            .enumerate()
+            .skip(self.scroll)
+            .take(height)
+            .map(|(index, (line, decoded))| {
+                let mut style = if self.image.is_data(decoded.offset) {
+                    Style::new().fg(Color::Yellow)
+                } else if decoded.operation == Operation::Unknown {
+                    Style::new().add_modifier(Modifier::DIM)
+                } else {
+                    Style::new()
+                };
+                if selected
+                    .as_ref()
+                    .is_some_and(|range| range.contains(&index))
+                {
+                    style = style.bg(Color::DarkGray);
+                }
+                if index == self.cursor {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+
+                let mut spans = vec![
+                    Span::raw(format!("{:04X}  {:<9} ", line.offset, line.bytes)),
+                    Span::styled(
+                        format!("{:<16}", truncate(line.label.as_deref().unwrap_or(""), 15)),
+                        Style::new().fg(Color::Cyan),
+                    ),
+                    Span::raw(format!("{} {}", line.operation, line.address)),
+                ];
+                if let Some(comment) = &line.comment {
+                    spans.push(Span::styled(
+                        format!("  ; {}", comment),
+                        Style::new().fg(Color::Green),
+                    ));
+                }
+                Line::from(spans).style(style)
+            })
+            .collect();
+
+        let title = format!(
+            " {}{} ",
+            self.project.input,
+            if self.dirty { " *" } else { "" }
+        );
+        frame.render_widget(
+            Paragraph::new(rows).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+
+    fn draw_hex(&self, frame: &mut Frame, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let current = self
+            .decoded
+            .get(self.cursor)
+            .map_or(0..0, |decoded| decoded.offset..decoded.end());
+        let first = (current.start - current.start % HEX_COLUMNS)
+            .saturating_sub(HEX_COLUMNS * (height / 2));
+
+        let rows: Vec<Line> = (0..height)
+            .map(|row| {
+                let start = first + row * HEX_COLUMNS;
+                let mut spans = vec![Span::raw(format!("{:04X} ", start))];
+                let mut text = String::new();
+                for address in start..start + HEX_COLUMNS {
+                    let byte = self.image.read(address);
+                    let style = if current.contains(&address) {
+                        Style::new().add_modifier(Modifier::REVERSED)
+                    } else {
+                        Style::new()
+                    };
+                    let hex = byte.map_or("--".into(), |byte| format!("{:02X}", byte));
+                    spans.push(Span::raw(" "));
+                    spans.push(Span::styled(hex, style));
+                    text.push(byte.map_or(' ', |byte| Charset::Ascii.render(byte)));
+                }
+                spans.push(Span::raw(format!("  {}", text)));
+                Line::from(spans)
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(rows).block(Block::bordered().title(" Hex ")),
+            area,
+        );
+    }
+}
+
+/// Runs the browser until it is quit, restoring the terminal afterwards
+pub fn run(mut app: App) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+
+    let result = (|| {
+        while !app.quit {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key);
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    ratatui::restore();
+    result
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Sorts the ranges and joins the ones that overlap or touch
+fn merge(ranges: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<usize>> = vec![];
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+fn subtract(ranges: &[Range<usize>], cut: &Range<usize>) -> Vec<Range<usize>> {
+    ranges
+        .iter()
+        .flat_map(|range| {
+            [
+                range.start..range.end.min(cut.start),
+                range.start.max(cut.end)..range.end,
+            ]
+        })
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use mos_6502_disassembler::Segment;
+    use ratatui::{backend::TestBackend, crossterm::event::KeyModifiers, Terminal};
+
+    use super::*;
+
+    fn app() -> App {
+        // JSR $C006, BNE $C000, RTS, then the subroutine: LDA #$00, RTS
+        let bytes = vec![0x20, 0x06, 0xc0, 0xd0, 0xfb, 0x60, 0xa9, 0x00, 0x60];
+        let image = MemoryImage::new().with_segment(Segment::new("code", 0xc000, bytes));
+        App::new(image, Project::new("test.bin"), "test.bin.project.json")
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            press(app, KeyCode::Char(c));
+        }
+        press(app, KeyCode::Enter);
+    }
+
+    #[test]
+    fn test_follow_and_back() {
+        let mut app = app();
+
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.address(), Some(0xc006));
+
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(app.address(), Some(0xc000));
+
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.address(), Some(0xc000));
+        assert_eq!(app.back, vec![0xc003]);
+    }
+
+    #[test]
+    fn test_jump_and_label() {
+        let mut app = app();
+
+        press(&mut app, KeyCode::Char('g'));
+        type_text(&mut app, "$C006");
+        assert_eq!(app.address(), Some(0xc006));
+
+        press(&mut app, KeyCode::Char('n'));
+        type_text(&mut app, "clear");
+        assert_eq!(app.project.labels.get(&0xc006), Some(&"clear".to_string()));
+        assert_eq!(app.lines[3].label.as_deref(), Some("clear"));
+        assert!(app.dirty);
+
+        press(&mut app, KeyCode::Home);
+        press(&mut app, KeyCode::Char('g'));
+        type_text(&mut app, "clear");
+        assert_eq!(app.address(), Some(0xc006));
+    }
+
+    #[test]
+    fn test_mark_data_and_code() {
+        let mut app = app();
+
+        press(&mut app, KeyCode::Char('g'));
+        type_text(&mut app, "c006");
+        press(&mut app, KeyCode::Char('v'));
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Char('d'));
+        assert_eq!(app.project.data_ranges, vec![0xc006..0xc009]);
+        assert_eq!(app.lines.len(), 6);
+
+        press(&mut app, KeyCode::Char('c'));
+        assert_eq!(app.project.data_ranges, vec![0xc006..0xc008]);
+        assert!(app.project.code_starts.contains(&0xc008));
+        assert_eq!(app.lines[5].operation, "RTS");
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut app = app();
+        let mut terminal = Terminal::new(TestBackend::new(100, 12)).unwrap();
+
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("C000  20 06 C0"));
+        assert!(screen.contains("C000  20 06 C0 D0 FB 60 A9 00"));
+    }
+
+    #[test]
+    fn test_quit_asks_about_unsaved_changes() {
+        let mut app = app();
+
+        press(&mut app, KeyCode::Char(';'));
+        type_text(&mut app, "entry");
+        press(&mut app, KeyCode::Char('q'));
+        assert!(!app.quit);
+        press(&mut app, KeyCode::Char('q'));
+        assert!(app.quit);
+    }
+}
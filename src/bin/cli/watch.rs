@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use mos_6502_disassembler::{disassemble_image, Instruction, ListingFormat};
+
+use crate::{load_file, print_changes, print_files, report, DisasmArgs};
+
+/// How often the files are checked for changes
+const INTERVAL: Duration = Duration::from_millis(300);
+
+/// Modification time and size, which together catch both editors that
+/// write in place and ones that replace the file
+type Stamp = Option<(SystemTime, u64)>;
+
+/// Disassembles the files, then again every time one of them, a symbol file
+/// or the project changes. Runs until the process is stopped.
+pub fn run(args: &DisasmArgs, out: &mut dyn Write) -> bool {
+    let mut paths: Vec<&str> = args
+        .input
+        .files
+        .iter()
+        .map(String::as_str)
+        .filter(|file| *file != "-")
+        .collect();
+    paths.extend(args.input.options.symbols.iter().map(String::as_str));
+    paths.extend(args.input.options.project.as_deref());
+
+    if paths.is_empty() {
+        return report("stdin", Err("can't be watched, --watch needs files".into()));
+    }
+
+    let format = args.listing.format();
+    let mut previous: HashMap<&str, Vec<Instruction>> = HashMap::new();
+    let mut stamps = stamps(&paths);
+
+    loop {
+        let succeeded = if args.diff && !previous.is_empty() {
+            print_file_changes(args, &mut previous, &format, out)
+        } else {
+            let succeeded = print_files(args, out);
+            if args.diff {
+                remember(args, &mut previous);
+            }
+            succeeded
+        };
+        // Nothing reads the output any more, like after `| head`
+        if let Err(error) = out.flush() {
+            return succeeded & report("stdout", Err(error.into()));
+        }
+
+        eprintln!("watching {} files, press Ctrl-C to stop", paths.len());
+        wait_for_change(&paths, &mut stamps);
+    }
+}
+
+fn print_file_changes<'a>(
+    args: &'a DisasmArgs,
+    previous: &mut HashMap<&'a str, Vec<Instruction>>,
+    format: &ListingFormat,
+    out: &mut dyn Write,
+) -> bool {
+    let mut succeeded = true;
+
+    for file in &args.input.files {
+        let result = (|| {
+            let lines = disassemble_image(&load_file(file, &args.input.options)?);
+            let old = previous.get(file.as_str()).map_or(&[][..], Vec::as_slice);
+
+            writeln!(out, "=== {}", file)?;
+            if print_changes(old, &lines, format, out)? == 0 {
+                writeln!(out, "no changes")?;
+            }
+            previous.insert(file, lines);
+            Ok(())
+        })();
+        succeeded &= report(file, result);
+    }
+
+    succeeded
+}
+
+/// Keeps the listings of the files that loaded, to compare the next run to
+fn remember<'a>(args: &'a DisasmArgs, previous: &mut HashMap<&'a str, Vec<Instruction>>) {
+    for file in &args.input.files {
+        if let Ok(image) = load_file(file, &args.input.options) {
+            previous.insert(file, disassemble_image(&image));
+        }
+    }
+}
+
+fn stamps(paths: &[&str]) -> Vec<Stamp> {
+    paths
+        .iter()
+        .map(|path| {
+            let metadata = fs::metadata(path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        })
+        .collect()
+}
+
+fn wait_for_change(paths: &[&str], stamps: &mut Vec<Stamp>) {
+    loop {
+        thread::sleep(INTERVAL);
+        let current = self::stamps(paths);
+        if current != *stamps {
+            // Give whatever is writing the file a moment to finish
+            thread::sleep(INTERVAL);
+            *stamps = self::stamps(paths);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use crate::{Cli, Command};
+
+    use super::*;
+
+    #[test]
+    fn test_stamps_notice_changes() {
+        let path = std::env::temp_dir().join(format!("watch-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let missing = stamps(&[path]);
+        assert_eq!(missing, [None]);
+
+        fs::write(path, [0xea]).unwrap();
+        let written = stamps(&[path]);
+        assert!(written[0].is_some());
+
+        fs::write(path, [0xea, 0x60]).unwrap();
+        assert_ne!(stamps(&[path]), written);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_only_changes_are_printed() {
+        let path = std::env::temp_dir().join(format!("watch-diff-{}.bin", std::process::id()));
+        let file = path.to_str().unwrap();
+        fs::write(file, [0xa9, 0x01, 0x60]).unwrap();
+
+        let cli = Cli::try_parse_from(["cli", "disasm", "--watch", "--diff", file]).unwrap();
+        let Some(Command::Disasm(args)) = cli.command else {
+            panic!("expected the disasm command");
+        };
+        let mut previous = HashMap::new();
+        remember(&args, &mut previous);
+
+        fs::write(file, [0xa9, 0x02, 0x60]).unwrap();
+        let mut out = vec![];
+        assert!(print_file_changes(
+            &args,
+            &mut previous,
+            &ListingFormat::default(),
+            &mut out
+        ));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "=== {}\n-0000   A9 01         LDA #$01\n+0000   A9 02         LDA #$02\n",
+                file
+            )
+        );
+
+        let mut out = vec![];
+        print_file_changes(&args, &mut previous, &ListingFormat::default(), &mut out);
+        assert!(String::from_utf8(out).unwrap().ends_with("no changes\n"));
+
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -1,13 +1,41 @@
+use std::{path::PathBuf, process::exit, sync::Arc, time::Duration};
+
 use clap::Parser;
-use poem::{listener::TcpListener, Route, Server};
+use poem::{endpoint::make_sync, listener::TcpListener, EndpointExt, Route, Server};
 use poem_openapi::OpenApiService;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{event, Level};
 
-use mos_6502_disassembler::{Api, Frontend};
+use mos_6502_disassembler::{
+    Api, DisassembleEndpoint, Frontend, Health, Limits, Metrics, Sessions, Store,
+};
 
 #[derive(Debug, Parser)]
 struct Args {
     #[arg(short, long)]
     bind_address: String,
+    /// Largest request body in bytes
+    #[arg(long, default_value_t = Limits::default().max_payload)]
+    max_payload: usize,
+    /// Seconds a request may take before it is answered with 503
+    #[arg(long, default_value_t = Limits::default().timeout.as_secs())]
+    timeout: u64,
+    /// Bytes of uploaded binaries kept for reference by ID, the oldest are
+    /// dropped first. With `--store-dir` this only bounds the cache.
+    #[arg(long, default_value_t = 256 * 1024 * 1024)]
+    store_size: usize,
+    /// Directory where uploaded binaries and their projects are kept across
+    /// restarts, otherwise they are only kept in memory
+    #[arg(long)]
+    store_dir: Option<PathBuf>,
+    /// Background jobs that may run at once, one per core by default
+    #[arg(long)]
+    workers: Option<usize>,
+    /// Seconds to go on serving after SIGTERM while `/readyz` answers 503,
+    /// so that load balancers stop sending traffic before the server stops
+    /// accepting it
+    #[arg(long, default_value_t = 5)]
+    drain: u64,
 }
 
 #[tokio::main]
@@ -15,11 +43,77 @@ async fn main() {
     let args = Args::parse();
     tracing_subscriber::fmt().init();
 
-    let endpoints = OpenApiService::new((Api, Frontend), "Api", "1.0");
+    let limits = Limits::new()
+        .with_max_payload(args.max_payload)
+        .with_timeout(Duration::from_secs(args.timeout));
+    let store = match &args.store_dir {
+        Some(directory) => Store::open(directory, args.store_size).unwrap_or_else(|err| {
+            eprintln!("Cannot open the store in {}: {}", directory.display(), err);
+            exit(1)
+        }),
+        None => Store::new(args.store_size),
+    };
+    let store = Arc::new(store);
+    let mut api = Api::new(limits).with_store(store.clone());
+    if let Some(workers) = args.workers {
+        api = api.with_workers(workers);
+    }
+    let frontend = Frontend::new(limits).with_store(store.clone());
+    let sessions = Sessions::new(limits).with_store(store.clone());
+    let disassemble = DisassembleEndpoint::new(limits).with_store(store.clone());
+    let health = Health::new().with_store(store);
+    let metrics = Metrics::new();
+    let endpoints = OpenApiService::new((api, frontend, disassemble), "Api", "1.0");
 
     let ui = endpoints.swagger_ui();
 
-    let _ = Server::new(TcpListener::bind(args.bind_address))
-        .run(Route::new().nest("/", endpoints).nest("/swagger", ui))
+    let app = Route::new()
+        .at("/healthz", make_sync(|_| "ok"))
+        .at("/readyz", health.clone())
+        .at("/metrics", metrics.clone())
+        .at("/json/session", sessions.clone())
+        .nest("/", endpoints)
+        .nest("/swagger", ui)
+        .with(limits)
+        .with(metrics);
+
+    // No request takes longer than the timeout, so waiting for that long lets
+    // every one in flight finish
+    let drain = Duration::from_secs(args.drain);
+    let result = Server::new(TcpListener::bind(args.bind_address))
+        .run_with_graceful_shutdown(
+            app,
+            shutdown(health, sessions, drain, limits.timeout),
+            Some(limits.timeout),
+        )
         .await;
+    if let Err(err) = result {
+        eprintln!("The server stopped: {}", err);
+        exit(1);
+    }
+}
+
+/// Turns readiness off on SIGTERM or Ctrl+C, and resolves once the drain
+/// period is over and the live sessions are closed, or have had the timeout
+/// to close
+async fn shutdown(health: Health, sessions: Sessions, drain: Duration, timeout: Duration) {
+    let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM can be listened to");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    health.set_ready(false);
+    event!(
+        Level::INFO,
+        "Shutting down in {} seconds, after the requests in flight",
+        drain.as_secs()
+    );
+
+    tokio::time::sleep(drain).await;
+    if tokio::time::timeout(timeout, sessions.close())
+        .await
+        .is_err()
+    {
+        event!(Level::WARN, "Sessions were still open after the timeout");
+    }
 }
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Instruction;
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum Change<T> {
+    Equal(T),
+    Delete(T),
+    Insert(T),
+}
+
+/// Edits searched for from each end of a differing part before giving up on
+/// it, which bounds the time taken by inputs that have little in common
+const MAX_SEARCH: isize = 4096;
+
+/// Shortest edit script that turns `old` into `new`, using the linear space
+/// variant of Myers' algorithm, so that memory stays proportional to the
+/// inputs however different they are. Parts that need more than twice
+/// `MAX_SEARCH` edits are reported as deleted and inserted whole, so the
+/// script is only the shortest up to that.
+pub fn diff<T: PartialEq + Clone>(old: &[T], new: &[T]) -> Vec<Change<T>> {
+    let size = (old.len() + new.len()).div_ceil(2).min(MAX_SEARCH as usize) + 2;
+    let mut forward = vec![0; 2 * size + 1];
+    let mut backward = vec![0; 2 * size + 1];
+    let mut changes = Vec::with_capacity(old.len().max(new.len()));
+    compare(old, new, &mut forward, &mut backward, &mut changes);
+
+    // Deletions come first in each changed block, as diff tools show them
+    for block in changes.split_mut(|change| matches!(change, Change::Equal(_))) {
+        block.sort_by_key(|change| matches!(change, Change::Insert(_)));
+    }
+    changes
+}
+
+/// Instructions that were removed from `old` or added in `new`. They are
+/// compared by operation and operand without their offsets, so that code
+/// that moved is not reported as changed.
+pub fn instruction_changes<'a>(
+    old: &'a [Instruction],
+    new: &'a [Instruction],
+) -> Vec<Change<&'a Instruction>> {
+    // Lines are numbered so that the diff compares integers, not strings
+    let mut numbers = HashMap::new();
+    let mut number = |line: &Instruction| {
+        let next = numbers.len();
+        *numbers
+            .entry((line.operation.clone(), line.address.clone()))
+            .or_insert(next)
+    };
+    let old_keys: Vec<usize> = old.iter().map(&mut number).collect();
+    let new_keys: Vec<usize> = new.iter().map(&mut number).collect();
+
+    let (mut old, mut new) = (old.iter(), new.iter());
+    diff(&old_keys, &new_keys)
+        .into_iter()
+        .filter_map(|change| match change {
+            Change::Equal(_) => {
+                old.next();
+                new.next();
+                None
+            }
+            Change::Delete(_) => old.next().map(Change::Delete),
+            Change::Insert(_) => new.next().map(Change::Insert),
+        })
+        .collect()
+}
+
+/// Splits the comparison at the middle of an optimal path and compares both
+/// halves. The common prefix and suffix are split off first, which keeps
+/// comparing two builds of the same program cheap.
+fn compare<T: PartialEq + Clone>(
+    old: &[T],
+    new: &[T],
+    forward: &mut [isize],
+    backward: &mut [isize],
+    changes: &mut Vec<Change<T>>,
+) {
+    let prefix = common_prefix(old, new);
+    changes.extend(old[..prefix].iter().cloned().map(Change::Equal));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = common_suffix(old, new);
+    let (old, new, equal) = (
+        &old[..old.len() - suffix],
+        &new[..new.len() - suffix],
+        &old[old.len() - suffix..],
+    );
+
+    if old.is_empty() {
+        changes.extend(new.iter().cloned().map(Change::Insert));
+    } else if new.is_empty() {
+        changes.extend(old.iter().cloned().map(Change::Delete));
+    } else if let Some((x, y)) = middle_snake(old, new, forward, backward) {
+        compare(&old[..x], &new[..y], forward, backward, changes);
+        compare(&old[x..], &new[y..], forward, backward, changes);
+    } else {
+        changes.extend(old.iter().cloned().map(Change::Delete));
+        changes.extend(new.iter().cloned().map(Change::Insert));
+    }
+
+    changes.extend(equal.iter().cloned().map(Change::Equal));
+}
+
+/// Point on a shortest path that splits its edits in half, found by searching
+/// from both ends until the searches meet, or nothing when they don't within
+/// `MAX_SEARCH` edits. Needs both inputs to differ at their first and last
+/// elements.
+fn middle_snake<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    forward: &mut [isize],
+    backward: &mut [isize],
+) -> Option<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    // Furthest x reached on each diagonal k = x - y
+    let offset = (forward.len() / 2) as isize;
+    let at = |k: isize| (k + offset) as usize;
+    forward[at(1)] = 0;
+    backward[at(1)] = 0;
+
+    for d in 0..=((n + m + 1) / 2).min(MAX_SEARCH) {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+
+            // Backward diagonals are counted from the end, so diagonal k
+            // meets backward diagonal delta - k
+            if odd && (k - delta).abs() < d && x + backward[at(delta - k)] >= n {
+                return Some((x as usize, y as usize));
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[at(k)] = x;
+
+            if !odd && (k - delta).abs() <= d && x + forward[at(delta - k)] >= n {
+                return Some(((n - x) as usize, (m - y) as usize));
+            }
+        }
+    }
+
+    None
+}
+
+fn common_prefix<T: PartialEq>(old: &[T], new: &[T]) -> usize {
+    old.iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count()
+}
+
+fn common_suffix<T: PartialEq>(old: &[T], new: &[T]) -> usize {
+    old.iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edits(old: &str, new: &str) -> String {
+        let old: Vec<char> = old.chars().collect();
+        let new: Vec<char> = new.chars().collect();
+
+        diff(&old, &new)
+            .into_iter()
+            .map(|change| match change {
+                Change::Equal(c) => c.to_string(),
+                Change::Delete(c) => format!("-{}", c),
+                Change::Insert(c) => format!("+{}", c),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        // One of the shortest scripts, the example of Myers' paper has others
+        assert_eq!(edits("abcabba", "cbabac"), "-a+cb-cab-ba+c");
+        assert_eq!(edits("same", "same"), "same");
+        assert_eq!(edits("xyz", "abz"), "-x-y+a+bz");
+        assert_eq!(edits("", "new"), "+n+e+w");
+        assert_eq!(edits("old", ""), "-o-l-d");
+        assert_eq!(
+            edits("start-end", "start-middle-end"),
+            "start-+m+i+d+d+l+e+-end"
+        );
+    }
+
+    /// Every edit script has to turn the old into the new, with as few
+    /// edits as the longest common subsequence allows
+    #[test]
+    fn test_shortest() {
+        let strings: Vec<Vec<u8>> = (0..=7)
+            .flat_map(|length| {
+                (0..1 << length).map(move |bits| (0..length).map(|bit| bits >> bit & 1).collect())
+            })
+            .collect();
+
+        for old in &strings {
+            for new in &strings {
+                let changes = diff(old, new);
+                let (mut from, mut to, mut edits) = (vec![], vec![], 0);
+                for change in changes {
+                    match change {
+                        Change::Equal(c) => {
+                            from.push(c);
+                            to.push(c);
+                        }
+                        Change::Delete(c) => {
+                            from.push(c);
+                            edits += 1;
+                        }
+                        Change::Insert(c) => {
+                            to.push(c);
+                            edits += 1;
+                        }
+                    }
+                }
+                assert_eq!((&from, &to), (old, new));
+
+                let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+                for i in 0..old.len() {
+                    for j in 0..new.len() {
+                        common[i + 1][j + 1] = match old[i] == new[j] {
+                            true => common[i][j] + 1,
+                            false => common[i][j + 1].max(common[i + 1][j]),
+                        };
+                    }
+                }
+                let shortest = old.len() + new.len() - 2 * common[old.len()][new.len()];
+                assert_eq!(edits, shortest, "{:?} {:?}", old, new);
+            }
+        }
+    }
+}
@@ -1,347 +1,82 @@
-use std::fmt::Display;
+use std::{borrow::Cow, collections::BTreeSet, fmt::Display, ops::Range};
 
 use poem_openapi::Object;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
-#[allow(clippy::upper_case_acronyms)]
-enum Operation {
-    ADC,
-    AND,
-    ASL,
-    BCC,
-    BCS,
-    BEQ,
-    BIT,
-    BMI,
-    BNE,
-    BPL,
-    BRK,
-    BVC,
-    BVS,
-    CLC,
-    CLD,
-    CLI,
-    CLV,
-    CMP,
-    CPX,
-    CPY,
-    DEC,
-    DEX,
-    DEY,
-    EOR,
-    INC,
-    INX,
-    INY,
-    JMP,
-    JSR,
-    LDA,
-    LDX,
-    LDY,
-    LSR,
-    NOP,
-    ORA,
-    PHA,
-    PHP,
-    PLA,
-    PLP,
-    ROL,
-    ROR,
-    RTI,
-    RTS,
-    SBC,
-    SEC,
-    SED,
-    SEI,
-    STA,
-    STX,
-    STY,
-    TAX,
-    TAY,
-    TSX,
-    TXA,
-    TXS,
-    TYA,
-    Unknown,
+use crate::{
+    opcodes::{decode_opcode, AddressMode, Cpu, Operation},
+    ListingFormat, MemoryImage,
+};
+
+/// Instruction as it was decoded, before any formatting
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct Decoded {
+    pub operation: Operation,
+    pub address_mode: AddressMode,
+    pub raw_bytes: Vec<u8>,
+    pub offset: usize,
 }
-use Operation::*;
 
-impl Display for Operation {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if *self == Operation::Unknown {
-            write!(f, "???")
-        } else {
-            write!(f, "{:?}", self)
-        }
-    }
-}
+impl Decoded {
+    fn new(offset: usize, token: u8, cpu: Cpu) -> Self {
+        let (operation, address_mode) = decode_opcode(token, cpu);
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
-enum AddressMode {
-    Accumulator,
-    Absolute,
-    AbsoluteX,
-    AbsoluteY,
-    Immediate,
-    Implied,
-    Indirect,
-    XIndirect,
-    IndirectY,
-    Relative,
-    Zeropage,
-    ZeropageX,
-    ZeropageY,
-    Unknown,
-}
-use AddressMode::*;
-impl AddressMode {
-    fn format(&self, formatted: &[String], raw: &[u8], offset: usize) -> String {
-        if formatted.len() != self.length() {
-            // Input is faulty and missing bytes
-            return String::from("*Missing operands*");
+        Decoded {
+            offset,
+            operation,
+            address_mode,
+            raw_bytes: vec![token],
         }
+    }
 
-        match self {
-            Accumulator => String::from("A"),
-            Absolute => format!("${}{}", formatted[2], formatted[1]),
-            AbsoluteX => format!("${}{},X", formatted[2], formatted[1]),
-            AbsoluteY => format!("${}{},Y", formatted[2], formatted[1]),
-            Immediate => format!("#${}", formatted[1]),
-            Implied => String::new(),
-            Indirect => format!("(${}{})", formatted[2], formatted[1]),
-            XIndirect => format!("(${},X)", formatted[1]),
-            IndirectY => format!("(${}),Y", formatted[1]),
-            Relative => {
-                // MOS-6502 can only handle addresses up to 2^16
-                // For the sake of convenience, this disassembler handles bigger binaries
-                let addr = (offset + 2) as isize + (raw[1] as i8) as isize;
-
-                format!("${:04X}", addr as u16)
-            }
-            Zeropage => format!("${}", formatted[1]),
-            ZeropageX => format!("${},X", formatted[1]),
-            ZeropageY => format!("${},Y", formatted[1]),
-            AddressMode::Unknown => {
-                let is_ascii_symbol = (32..126).contains(&raw[0]);
-                if is_ascii_symbol {
-                    format!(";%{:0>8b} '{}'", raw[0], raw[0] as char)
-                } else {
-                    format!(";%{:0>8b}", raw[0])
-                }
-            }
-        }
+    fn add(&mut self, token: u8) {
+        self.raw_bytes.push(token);
     }
 
-    fn length(&self) -> usize {
-        match self {
-            Accumulator | Implied | AddressMode::Unknown => 1,
-            Immediate | Relative | Zeropage | ZeropageX | ZeropageY | XIndirect | IndirectY => 2,
-            Absolute | AbsoluteX | AbsoluteY | Indirect => 3,
-        }
+    /// All operand bytes are present
+    pub fn is_satisfied(&self) -> bool {
+        self.address_mode.length() == self.raw_bytes.len()
     }
-}
 
-fn decode_opcode(value: u8) -> (Operation, AddressMode) {
-    match value {
-        // From https://www.masswerk.at/6502/6502_instruction_set.html
-        // Validated with a binary that contains one of each byte as an instruction
-        0x00 => (BRK, Implied),
-        0x01 => (ORA, XIndirect),
-        0x05 => (ORA, Zeropage),
-        0x06 => (ASL, Zeropage),
-        0x08 => (PHP, Implied),
-        0x09 => (ORA, Immediate),
-        0x0a => (ASL, Accumulator),
-        0x0d => (ORA, Absolute),
-        0x0e => (ASL, Absolute),
-
-        0x10 => (BPL, Relative),
-        0x11 => (ORA, IndirectY),
-        0x15 => (ORA, ZeropageX),
-        0x16 => (ASL, ZeropageX),
-        0x18 => (CLC, Implied),
-        0x19 => (ORA, AbsoluteY),
-        0x1d => (ORA, AbsoluteX),
-        0x1e => (ASL, AbsoluteX),
-
-        0x20 => (JSR, Absolute),
-        0x21 => (AND, XIndirect),
-        0x24 => (BIT, Zeropage),
-        0x25 => (AND, Zeropage),
-        0x26 => (ROL, Zeropage),
-        0x28 => (PLP, Implied),
-        0x29 => (AND, Immediate),
-        0x2a => (ROL, Accumulator),
-        0x2c => (BIT, Absolute),
-        0x2d => (AND, Absolute),
-        0x2e => (ROL, Absolute),
-
-        0x30 => (BMI, Relative),
-        0x31 => (AND, IndirectY),
-        0x35 => (AND, ZeropageX),
-        0x36 => (ROL, ZeropageX),
-        0x38 => (SEC, Implied),
-        0x39 => (AND, AbsoluteY),
-        0x3d => (AND, AbsoluteX),
-        0x3e => (ROL, AbsoluteX),
-
-        0x40 => (RTI, Implied),
-        0x41 => (EOR, XIndirect),
-        0x45 => (EOR, Zeropage),
-        0x46 => (LSR, Zeropage),
-        0x48 => (PHA, Implied),
-        0x49 => (EOR, Immediate),
-        0x4a => (LSR, Accumulator),
-        0x4c => (JMP, Absolute),
-        0x4d => (EOR, Absolute),
-        0x4e => (LSR, Absolute),
-
-        0x50 => (BVC, Relative),
-        0x51 => (EOR, IndirectY),
-        0x55 => (EOR, ZeropageX),
-        0x56 => (LSR, ZeropageX),
-        0x58 => (CLI, Implied),
-        0x59 => (EOR, AbsoluteY),
-        0x5d => (EOR, AbsoluteX),
-        0x5e => (LSR, AbsoluteX),
-
-        0x60 => (RTS, Implied),
-        0x61 => (ADC, XIndirect),
-        0x65 => (ADC, Zeropage),
-        0x66 => (ROR, Zeropage),
-        0x68 => (PLA, Implied),
-        0x69 => (ADC, Immediate),
-        0x6a => (ROR, Accumulator),
-        0x6c => (JMP, Indirect),
-        0x6d => (ADC, Absolute),
-        0x6e => (ROR, Absolute),
-
-        0x70 => (BVS, Relative),
-        0x71 => (ADC, IndirectY),
-        0x75 => (ADC, ZeropageX),
-        0x76 => (ROR, ZeropageX),
-        0x78 => (SEI, Implied),
-        0x79 => (ADC, AbsoluteY),
-        0x7d => (ADC, AbsoluteX),
-        0x7e => (ROR, AbsoluteX),
-
-        0x81 => (STA, XIndirect),
-        0x84 => (STY, Zeropage),
-        0x85 => (STA, Zeropage),
-        0x86 => (STX, Zeropage),
-        0x88 => (DEY, Implied),
-        0x8a => (TXA, Implied),
-        0x8c => (STY, Absolute),
-        0x8d => (STA, Absolute),
-        0x8e => (STX, Absolute),
-
-        0x90 => (BCC, Relative),
-        0x91 => (STA, IndirectY),
-        0x94 => (STY, ZeropageX),
-        0x95 => (STA, ZeropageX),
-        0x96 => (STX, ZeropageY),
-        0x98 => (TYA, Implied),
-        0x99 => (STA, AbsoluteY),
-        0x9a => (TXS, Implied),
-        0x9d => (STA, AbsoluteX),
-
-        0xa0 => (LDY, Immediate),
-        0xa1 => (LDA, XIndirect),
-        0xa2 => (LDX, Immediate),
-        0xa4 => (LDY, Zeropage),
-        0xa5 => (LDA, Zeropage),
-        0xa6 => (LDX, Zeropage),
-        0xa8 => (TAY, Implied),
-        0xa9 => (LDA, Immediate),
-        0xaa => (TAX, Implied),
-        0xac => (LDY, Absolute),
-        0xad => (LDA, Absolute),
-        0xae => (LDX, Absolute),
-
-        0xb0 => (BCS, Relative),
-        0xb1 => (LDA, IndirectY),
-        0xb4 => (LDY, ZeropageX),
-        0xb5 => (LDA, ZeropageX),
-        0xb6 => (LDX, ZeropageY),
-        0xb8 => (CLV, Implied),
-        0xb9 => (LDA, AbsoluteY),
-        0xba => (TSX, Implied),
-        0xbc => (LDY, AbsoluteX),
-        0xbd => (LDA, AbsoluteX),
-        0xbe => (LDX, AbsoluteY),
-
-        0xc0 => (CPY, Immediate),
-        0xc1 => (CMP, XIndirect),
-        0xc4 => (CPY, Zeropage),
-        0xc5 => (CMP, Zeropage),
-        0xc6 => (DEC, Zeropage),
-        0xc8 => (INY, Implied),
-        0xc9 => (CMP, Immediate),
-        0xca => (DEX, Implied),
-        0xcc => (CPY, Absolute),
-        0xcd => (CMP, Absolute),
-        0xce => (DEC, Absolute),
-
-        0xd0 => (BNE, Relative),
-        0xd1 => (CMP, IndirectY),
-        0xd5 => (CMP, ZeropageX),
-        0xd6 => (DEC, ZeropageX),
-        0xd8 => (CLD, Implied),
-        0xd9 => (CMP, AbsoluteY),
-        0xdd => (CMP, AbsoluteX),
-        0xde => (DEC, AbsoluteX),
-
-        0xe0 => (CPX, Immediate),
-        0xe1 => (SBC, XIndirect),
-        0xe4 => (CPX, Zeropage),
-        0xe5 => (SBC, Zeropage),
-        0xe6 => (INC, Zeropage),
-        0xe8 => (INX, Implied),
-        0xe9 => (SBC, Immediate),
-        0xea => (NOP, Implied),
-        0xec => (CPX, Absolute),
-        0xed => (SBC, Absolute),
-        0xee => (INC, Absolute),
-
-        0xf0 => (BEQ, Relative),
-        0xf1 => (SBC, IndirectY),
-        0xf5 => (SBC, ZeropageX),
-        0xf6 => (INC, ZeropageX),
-        0xf8 => (SED, Implied),
-        0xf9 => (SBC, AbsoluteY),
-        0xfd => (SBC, AbsoluteX),
-        0xfe => (INC, AbsoluteX),
-
-        _ => (Operation::Unknown, AddressMode::Unknown),
+    pub fn len(&self) -> usize {
+        self.raw_bytes.len()
     }
-}
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
-struct InstructionBuilder {
-    operation: Operation,
-    address_mode: AddressMode,
-    raw_bytes: Vec<u8>,
-    offset: usize,
-}
+    pub fn is_empty(&self) -> bool {
+        self.raw_bytes.is_empty()
+    }
 
-impl InstructionBuilder {
-    fn new(offset: usize, token: u8) -> Self {
-        let (operation, address_mode) = decode_opcode(token);
+    /// First address after the instruction
+    pub fn end(&self) -> usize {
+        self.offset + self.len()
+    }
 
-        InstructionBuilder {
-            offset,
-            operation,
-            address_mode,
-            raw_bytes: vec![token],
+    /// Value of the operand bytes, if there are any
+    pub fn operand(&self) -> Option<usize> {
+        if !self.is_satisfied() {
+            return None;
         }
-    }
 
-    fn add(&mut self, token: u8) {
-        self.raw_bytes.push(token);
+        match self.raw_bytes[..] {
+            [_, low] => Some(low as usize),
+            [_, low, high] => Some(u16::from_le_bytes([low, high]) as usize),
+            _ => None,
+        }
     }
 
-    fn is_satisfied(&self) -> bool {
-        self.address_mode.length() == self.raw_bytes.len()
+    /// Address that the instruction reads, writes or jumps to, with branch
+    /// displacements resolved. Immediate values are not addresses.
+    pub fn target(&self) -> Option<usize> {
+        match self.address_mode {
+            AddressMode::Relative => self.operand().map(|displacement| {
+                // Same wrapping as when the branch is formatted
+                let target = (self.offset + 2) as isize + (displacement as u8 as i8) as isize;
+                target as u16 as usize
+            }),
+            AddressMode::Immediate => None,
+            _ => self.operand(),
+        }
     }
 }
 
@@ -351,10 +86,12 @@ pub struct Instruction {
     pub bytes: String,
     pub operation: String,
     pub address: String,
+    pub label: Option<String>,
+    pub comment: Option<String>,
 }
 
-impl From<InstructionBuilder> for Instruction {
-    fn from(value: InstructionBuilder) -> Self {
+impl From<Decoded> for Instruction {
+    fn from(value: Decoded) -> Self {
         let formatted_bytes: Vec<String> = value
             .raw_bytes
             .iter()
@@ -368,51 +105,294 @@ impl From<InstructionBuilder> for Instruction {
                 .address_mode
                 .format(&formatted_bytes, &value.raw_bytes, value.offset),
             operation: value.operation.to_string(),
+            label: None,
+            comment: None,
         }
     }
 }
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let base = format!("{:04X}   {: <8}", self.offset, self.bytes);
-
-        let opcode = if self.address.is_empty() {
-            self.operation.to_string()
-        } else if self.operation == "???" {
-            format!("???                {}", self.address)
-        } else {
-            format!("{} {}", self.operation, self.address)
-        };
-
-        write!(f, "{}      {}", base, opcode)
+        write!(f, "{}", ListingFormat::default().format(self))
     }
 }
 
+/// Disassembles a flat binary that is loaded at address zero
 pub fn disassemble(bytes: &[u8]) -> Vec<Instruction> {
-    bytes
+    Decoder::new(
+        vec![(0, Cow::Borrowed(bytes))],
+        BTreeSet::new(),
+        vec![],
+        Cpu::default(),
+    )
+    .map(Instruction::from)
+    .collect()
+}
+
+/// Instructions above which `disassemble_image` formats them on all cores.
+/// Below it spinning up the threads costs more than it saves.
+const PARALLEL_THRESHOLD: usize = 32 * 1024;
+
+/// Disassembles every segment of the image at its own load address, see
+/// `decode_image`. Labels, comments and symbolic operands of the image are
+/// filled in.
+pub fn disassemble_image(image: &MemoryImage) -> Vec<Instruction> {
+    // Decoding is sequential, as every instruction starts where the previous
+    // one ended, but formatting is where the time goes
+    let decoded = decode_image(image);
+    if decoded.len() < PARALLEL_THRESHOLD {
+        decoded
+            .into_iter()
+            .map(|decoded| annotate(decoded, image))
+            .collect()
+    } else {
+        decoded
+            .into_par_iter()
+            .map(|decoded| annotate(decoded, image))
+            .collect()
+    }
+}
+
+/// Lazy version of `disassemble_image`, instructions are decoded as they are
+/// consumed
+pub fn disassemble_image_iter(image: &MemoryImage) -> impl Iterator<Item = Instruction> + '_ {
+    decode_image_iter(image).map(|decoded| annotate(decoded, image))
+}
+
+/// Instructions that start inside the range. Decoding begins where the full
+/// listing restarts before the range, so instruction boundaries are the same,
+/// and ends with the range. In banked images that is the first bank that has
+/// the start of the range.
+pub fn disassemble_range(
+    image: &MemoryImage,
+    range: Range<usize>,
+) -> impl Iterator<Item = Instruction> + '_ {
+    let mut decoder = decode_image_iter(image);
+    decoder.seek_before(range.start);
+    decoder
+        .skip_while(move |decoded| decoded.offset < range.start)
+        .take_while(move |decoded| decoded.offset < range.end)
+        .map(|decoded| annotate(decoded, image))
+}
+
+/// Instruction that the address is a part of, operand bytes included
+pub fn instruction_at(image: &MemoryImage, address: usize) -> Option<Instruction> {
+    let mut decoder = decode_image_iter(image);
+    decoder.seek_before(address);
+    decoder
+        .take_while(|decoded| decoded.offset <= address)
+        .find(|decoded| address < decoded.end())
+        .map(|decoded| annotate(decoded, image))
+}
+
+/// Owning version of `disassemble_image_iter`, for when the instructions are
+/// consumed somewhere the image can't be borrowed, like a streamed response
+pub fn into_instructions(image: MemoryImage) -> Instructions {
+    let decoder = decode_image_iter(&image).into_owned();
+    Instructions { image, decoder }
+}
+
+/// Lazy instructions of an image that the iterator owns
+pub struct Instructions {
+    image: MemoryImage,
+    decoder: Decoder<'static>,
+}
+
+impl Iterator for Instructions {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let decoded = self.decoder.next()?;
+        Some(annotate(decoded, &self.image))
+    }
+}
+
+/// Decodes every segment of the image at its own load address. Adjacent
+/// segments are treated as one stretch of memory, gaps cut instructions short.
+/// Decoding is forced to restart at entry points, so that a preceding data
+/// block can't swallow the first bytes of a routine.
+pub fn decode_image(image: &MemoryImage) -> Vec<Decoded> {
+    decode_image_iter(image).collect()
+}
+
+/// Lazy version of `decode_image`
+pub fn decode_image_iter(image: &MemoryImage) -> Decoder<'_> {
+    // Data ranges are cut out of the instruction stream at both ends
+    let mut boundaries: BTreeSet<usize> = image
+        .entry_points()
         .iter()
-        .enumerate()
-        .fold(
-            vec![],
-            |mut acc: Vec<InstructionBuilder>, (offset, token)| {
-                match acc.last_mut() {
-                    Some(last) if !last.is_satisfied() => last.add(*token),
-                    _ => acc.push(InstructionBuilder::new(offset, *token)),
-                };
-
-                acc
-            },
-        )
-        .into_iter()
-        .map(Instruction::from)
-        .collect()
+        .map(|entry| entry.address)
+        .collect();
+    boundaries.extend(image.code_starts());
+    boundaries.extend(
+        image
+            .data_ranges()
+            .iter()
+            .flat_map(|range| [range.start, range.end]),
+    );
+
+    Decoder::new(
+        image.runs(),
+        boundaries,
+        image.data_ranges().to_vec(),
+        image.cpu(),
+    )
+}
+
+/// Lazy version of `decode_image` that starts at the address. Instruction
+/// boundaries are the same as in the full listing as long as one of its
+/// instructions starts there.
+pub fn decode_image_from(image: &MemoryImage, address: usize) -> Decoder<'_> {
+    let mut decoder = decode_image_iter(image);
+    decoder.seek(address);
+    decoder
+}
+
+/// Iterator that decodes runs of memory one instruction at a time. Bytes in
+/// data ranges come out one by one as unknown operations.
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    runs: Vec<(usize, Cow<'a, [u8]>)>,
+    boundaries: BTreeSet<usize>,
+    data: Vec<Range<usize>>,
+    cpu: Cpu,
+    run: usize,
+    index: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(
+        runs: Vec<(usize, Cow<'a, [u8]>)>,
+        boundaries: BTreeSet<usize>,
+        data: Vec<Range<usize>>,
+        cpu: Cpu,
+    ) -> Self {
+        Decoder {
+            runs,
+            boundaries,
+            data,
+            cpu,
+            run: 0,
+            index: 0,
+        }
+    }
+
+    /// Continues at the run that holds the address, or at the first one
+    /// after it
+    fn seek(&mut self, address: usize) {
+        let run = self
+            .runs
+            .iter()
+            .position(|(origin, bytes)| (*origin..origin + bytes.len()).contains(&address))
+            .or_else(|| self.runs.iter().position(|(origin, _)| *origin > address));
+
+        (self.run, self.index) = match run {
+            Some(run) => (run, address.saturating_sub(self.runs[run].0)),
+            None => (self.runs.len(), 0),
+        };
+    }
+
+    /// Goes back from the address to where decoding restarts anyway, the
+    /// start of its run or the boundary before it, so that the instructions
+    /// from there on are the same as in the full listing
+    fn seek_before(&mut self, address: usize) {
+        self.seek(address);
+        let Some((origin, _)) = self.runs.get(self.run) else {
+            return;
+        };
+        let restart = self
+            .boundaries
+            .range(*origin..=origin + self.index)
+            .next_back()
+            .copied()
+            .unwrap_or(*origin);
+        self.index = restart - origin;
+    }
+
+    /// Copies the bytes, so that the decoder no longer borrows the image
+    fn into_owned(self) -> Decoder<'static> {
+        Decoder {
+            runs: self
+                .runs
+                .into_iter()
+                .map(|(origin, bytes)| (origin, Cow::Owned(bytes.into_owned())))
+                .collect(),
+            boundaries: self.boundaries,
+            data: self.data,
+            cpu: self.cpu,
+            run: self.run,
+            index: self.index,
+        }
+    }
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = Decoded;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (origin, bytes) = self.runs.get(self.run)?;
+            if self.index >= bytes.len() {
+                self.run += 1;
+                self.index = 0;
+                continue;
+            }
+
+            let address = origin + self.index;
+            let token = bytes[self.index];
+            self.index += 1;
+
+            if self.data.iter().any(|range| range.contains(&address)) {
+                return Some(Decoded {
+                    offset: address,
+                    operation: Operation::Unknown,
+                    address_mode: AddressMode::Unknown,
+                    raw_bytes: vec![token],
+                });
+            }
+
+            let mut decoded = Decoded::new(address, token, self.cpu);
+            while !decoded.is_satisfied()
+                && self.index < bytes.len()
+                && !self.boundaries.contains(&(origin + self.index))
+            {
+                decoded.add(bytes[self.index]);
+                self.index += 1;
+            }
+
+            return Some(decoded);
+        }
+    }
+}
+
+pub(crate) fn annotate(decoded: Decoded, image: &MemoryImage) -> Instruction {
+    // Operands that the image knows a symbolic form for, like relocated
+    // references in object files, are shown as such
+    let reference = match decoded.address_mode.length() {
+        1 => None,
+        _ if !decoded.is_satisfied() => None,
+        _ => image.reference(decoded.offset + 1),
+    }
+    .map(|operand| decoded.address_mode.wrap(operand));
+
+    let mut instruction = Instruction::from(decoded);
+    if let Some(address) = reference {
+        instruction.address = address;
+    }
+    instruction.label = image.label(instruction.offset).map(String::from);
+    instruction.comment = image.comment(instruction.offset).map(String::from);
+    instruction
 }
 
 #[cfg(test)]
 mod test {
     use std::{fs, io::BufRead};
 
-    use crate::disassemble;
+    use crate::{
+        decode_image_iter, disassemble, disassemble_image, disassemble_image_iter,
+        disassemble_range, instruction_at, into_instructions, Cpu, Instruction, MemoryImage,
+        Segment,
+    };
 
     #[test]
     fn test_binary_one() {
@@ -432,6 +412,194 @@ mod test {
         test_example_bin("mega");
     }
 
+    #[test]
+    fn test_branch_into_other_segment() {
+        let image = MemoryImage::new()
+            .with_segment(Segment::new("main", 0xbff0, vec![0xea; 0x0c]))
+            // BNE back to the end of main, BEQ forward into the overlay
+            .with_segment(Segment::new("patch", 0xbffc, vec![0xd0, 0xfc, 0xf0, 0x02]))
+            .with_segment(Segment::new("overlay", 0xc004, vec![0x20, 0xf0, 0xbf]));
+
+        let lines: Vec<String> = disassemble_image(&image)
+            .into_iter()
+            .skip(0x0c)
+            .map(|instruction| instruction.to_string())
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "BFFC   D0 FC         BNE $BFFA",
+                "BFFE   F0 02         BEQ $C002",
+                "C004   20 F0 BF      JSR $BFF0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gap_cuts_instruction() {
+        let image = MemoryImage::new()
+            .with_segment(Segment::new("main", 0x1000, vec![0xea, 0x4c, 0x00]))
+            .with_segment(Segment::new("other", 0x2000, vec![0x60]));
+
+        let lines: Vec<String> = disassemble_image(&image)
+            .into_iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "1000   EA            NOP",
+                "1001   4C 00         JMP *Missing operands*",
+                "2000   60            RTS",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entry_point_restarts_decoding() {
+        let image = MemoryImage::new()
+            .with_segment(Segment::new("main", 0x1000, vec![0xad, 0xea, 0x60]))
+            .with_entry_point("routine", 0x1001);
+
+        let instructions = disassemble_image(&image);
+
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[0].address, "*Missing operands*");
+        assert_eq!(instructions[1].operation, "NOP");
+        assert_eq!(instructions[1].label, Some("routine".into()));
+        assert_eq!(instructions[2].label, None);
+    }
+
+    #[test]
+    fn test_data_ranges() {
+        let mut image = MemoryImage::new().with_segment(Segment::new(
+            "main",
+            0x1000,
+            vec![0xa9, 0x00, 0x41, 0x42, 0x60],
+        ));
+        image.add_data_range(0x1001..0x1004);
+
+        let lines: Vec<String> = disassemble_image(&image)
+            .into_iter()
+            .map(|instruction| format!("{} {}", instruction.operation, instruction.address))
+            .collect();
+
+        assert_eq!(
+            lines,
+            [
+                "LDA *Missing operands*",
+                "??? ;%00000000",
+                "??? ;%01000001 'A'",
+                "??? ;%01000010 'B'",
+                "RTS ",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cpu_variants() {
+        let lines = |bytes: &[u8], cpu| -> Vec<String> {
+            let image = MemoryImage::from(bytes).with_cpu(cpu);
+            disassemble_image(&image)
+                .into_iter()
+                .map(|instruction| format!("{} {}", instruction.operation, instruction.address))
+                .collect()
+        };
+
+        let illegal = [0xa7, 0x10, 0x02];
+        assert_eq!(lines(&illegal, Cpu::Nmos), ["??? ;%10100111", "BPL $0005"]);
+        assert_eq!(lines(&illegal, Cpu::NmosIllegal), ["LAX $10", "JAM "]);
+
+        let cmos = [0xb2, 0x10, 0x80, 0xfc];
+        assert_eq!(lines(&cmos, Cpu::Cmos), ["LDA ($10)", "BRA $0000"]);
+    }
+
+    #[test]
+    fn test_iterator_is_lazy() {
+        let image = MemoryImage::from(&[0xea; 0x10000][..]);
+
+        let first: Vec<usize> = disassemble_image_iter(&image)
+            .take(2)
+            .map(|instruction| instruction.offset)
+            .collect();
+
+        assert_eq!(first, vec![0, 1]);
+        assert_eq!(decode_image_iter(&image).count(), 0x10000);
+    }
+
+    #[test]
+    fn test_owned_iterator() {
+        let image = MemoryImage::new()
+            .with_segment(Segment::new("code", 0xc000, vec![0xa9, 0xbd, 0x60]))
+            .with_label(0xc002, "done");
+
+        let expected = disassemble_image(&image);
+        assert_eq!(into_instructions(image).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_range_and_lookup() {
+        // LDA $1234, NOP, RTS
+        let image = MemoryImage::new().with_segment(Segment::new(
+            "code",
+            0xc000,
+            vec![0xad, 0x34, 0x12, 0xea, 0x60],
+        ));
+
+        let offsets: Vec<usize> = disassemble_range(&image, 0xc001..0xc005)
+            .map(|instruction| instruction.offset)
+            .collect();
+        assert_eq!(offsets, [0xc003, 0xc004]);
+
+        assert_eq!(instruction_at(&image, 0xc002).unwrap().operation, "LDA");
+        assert_eq!(instruction_at(&image, 0xc004).unwrap().operation, "RTS");
+        assert_eq!(instruction_at(&image, 0xc005), None);
+
+        // Decoding picks up at the entry point before the range, and every
+        // range gives the same instructions as the full listing
+        let mut image = MemoryImage::new()
+            .with_segment(Segment::new(
+                "code",
+                0xc000,
+                [0xad, 0x34, 0x12, 0xea].repeat(8),
+            ))
+            .with_entry_point("main", 0xc00d);
+        image.add_data_range(0xc016..0xc018);
+        let full = disassemble_image(&image);
+        for start in 0xbfff..0xc021 {
+            for end in start..0xc021 {
+                let expected: Vec<Instruction> = full
+                    .iter()
+                    .filter(|instruction| (start..end).contains(&instruction.offset))
+                    .cloned()
+                    .collect();
+                assert_eq!(
+                    disassemble_range(&image, start..end).collect::<Vec<_>>(),
+                    expected
+                );
+            }
+            let expected = full.iter().find(|instruction| {
+                let length = instruction.bytes.split(' ').count();
+                (instruction.offset..instruction.offset + length).contains(&start)
+            });
+            assert_eq!(instruction_at(&image, start).as_ref(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        // Big enough to be formatted in parallel
+        let bytes: Vec<u8> = (0..0x20000).map(|index| (index * 7) as u8).collect();
+        let image = MemoryImage::from(&bytes[..]).with_label(0x100, "start");
+
+        assert_eq!(
+            disassemble_image(&image),
+            disassemble_image_iter(&image).collect::<Vec<_>>()
+        );
+    }
+
     fn test_example_bin(case: &'static str) {
         let input = fs::read(format!("test-bin/{}.bin", case)).unwrap();
         // Used https://www.masswerk.at/6502/disassembler.html as a reference
@@ -0,0 +1,230 @@
+use crate::{MemoryImage, Segment};
+
+use super::{read_string, read_u16_be, read_u16_le, read_u32_be, require, Format, FormatError};
+
+// https://vice-emu.sourceforge.io/vice_17.html#SEC418
+const SIGNATURE: &[u8] = b"C64 CARTRIDGE   ";
+const CHIP_HEADER_LENGTH: usize = 0x10;
+const ROML: usize = 0x8000;
+const ROMH: usize = 0xa000;
+const ULTIMAX_ROMH: usize = 0xe000;
+// "CBM80" in PETSCII, right after the cold and warm start vectors
+const AUTOSTART: &[u8] = &[0xc3, 0xc2, 0xcd, 0x38, 0x30];
+
+const HARDWARE_TYPES: &[&str] = &[
+    "Normal cartridge",
+    "Action Replay",
+    "KCS Power Cartridge",
+    "Final Cartridge III",
+    "Simons' BASIC",
+    "Ocean type 1",
+    "Expert Cartridge",
+    "Fun Play, Power Play",
+    "Super Games",
+    "Atomic Power",
+    "Epyx Fastload",
+    "Westermann Learning",
+    "Rex Utility",
+    "Final Cartridge I",
+    "Magic Formel",
+    "C64 Game System, System 3",
+    "Warp Speed",
+    "Dinamic",
+    "Zaxxon, Super Zaxxon",
+    "Magic Desk, Domark, HES Australia",
+    "Super Snapshot V5",
+    "Comal-80",
+    "Structured BASIC",
+    "Ross",
+    "Dela EP64",
+    "Dela EP7x8",
+    "Dela EP256",
+    "Rex EP256",
+    "Mikro Assembler",
+    "Final Cartridge Plus",
+    "Action Replay 4",
+    "Stardos",
+    "EasyFlash",
+    "EasyFlash Xbank",
+    "Capture",
+    "Action Replay 3",
+    "Retro Replay",
+];
+
+pub(super) fn is_crt(bytes: &[u8]) -> bool {
+    bytes.starts_with(SIGNATURE)
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<MemoryImage, FormatError> {
+    require(bytes, Format::Crt, 0x40)?;
+
+    let header_length = read_u32_be(bytes, 0x10) as usize;
+    let version = read_u16_be(bytes, 0x14);
+    let hardware = read_u16_be(bytes, 0x16) as usize;
+    // The lines are active low
+    let exrom = bytes[0x18] != 0;
+    let game = bytes[0x19] != 0;
+    let is_ultimax = exrom && !game;
+
+    let mut image = MemoryImage::new()
+        .with_property(
+            "format",
+            format!("CRT v{}.{:0>2}", version >> 8, version & 0xff),
+        )
+        .with_property("name", read_string(&bytes[0x20..0x40]))
+        .with_property(
+            "hardware",
+            HARDWARE_TYPES
+                .get(hardware)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("unknown ({})", hardware)),
+        )
+        .with_property(
+            "mode",
+            match (exrom, game) {
+                (false, true) => "8K",
+                (false, false) => "16K",
+                (true, false) => "Ultimax",
+                (true, true) => "off",
+            },
+        );
+
+    let mut position = header_length.max(0x40);
+    let mut chips = 0;
+
+    while position < bytes.len() {
+        require(bytes, Format::Crt, position + CHIP_HEADER_LENGTH)?;
+        if &bytes[position..position + 4] != b"CHIP" {
+            return Err(FormatError::Invalid {
+                format: Format::Crt,
+                reason: format!("expected a CHIP packet at offset ${:X}", position),
+            });
+        }
+
+        let packet_length = read_u32_be(bytes, position + 0x04) as usize;
+        let bank = read_u16_be(bytes, position + 0x0a) as usize;
+        let load_address = read_u16_be(bytes, position + 0x0c) as usize;
+        let size = read_u16_be(bytes, position + 0x0e) as usize;
+
+        let data_start = position + CHIP_HEADER_LENGTH;
+        require(bytes, Format::Crt, data_start + size)?;
+        let data = &bytes[data_start..data_start + size];
+
+        // Ultimax carts see ROMH at the top of memory, but some formats such
+        // as EasyFlash still store it with the regular ROMH load address
+        let address = if is_ultimax && load_address == ROMH {
+            ULTIMAX_ROMH
+        } else {
+            load_address
+        };
+
+        let area = match address {
+            ROML => "ROML",
+            ROMH | ULTIMAX_ROMH => "ROMH",
+            _ => "ROM",
+        };
+
+        image.add_segment(
+            Segment::new(format!("bank {} {}", bank, area), address, data.to_vec()).with_bank(bank),
+        );
+
+        if bank == 0 && address == ROML && data.get(4..9) == Some(AUTOSTART) {
+            image.add_property("autostart", "CBM80");
+            image.add_entry_point("cold start", read_u16_le(data, 0) as usize);
+            image.add_entry_point("warm start", read_u16_le(data, 2) as usize);
+        }
+
+        if bank == 0 && address + size == 0x10000 && size >= 6 {
+            // Ultimax carts replace the kernal, so they own the CPU vectors
+            let vectors = &data[size - 6..];
+            image.add_entry_point("nmi", read_u16_le(vectors, 0) as usize);
+            image.add_entry_point("reset", read_u16_le(vectors, 2) as usize);
+            image.add_entry_point("irq", read_u16_le(vectors, 4) as usize);
+        }
+
+        chips += 1;
+        // The packet length should always cover the data, but don't get stuck
+        // on one that claims to be shorter than its header
+        position += packet_length.max(CHIP_HEADER_LENGTH + size);
+    }
+
+    image.add_property("chips", chips.to_string());
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{disassemble_image, formats::load};
+
+    use super::*;
+
+    fn header(hardware: u16, exrom: u8, game: u8) -> Vec<u8> {
+        let mut bytes = vec![0; 0x40];
+        bytes[0..16].copy_from_slice(SIGNATURE);
+        bytes[0x10..0x14].copy_from_slice(&0x40u32.to_be_bytes());
+        bytes[0x14..0x16].copy_from_slice(&0x0100u16.to_be_bytes());
+        bytes[0x16..0x18].copy_from_slice(&hardware.to_be_bytes());
+        bytes[0x18] = exrom;
+        bytes[0x19] = game;
+        bytes[0x20..0x26].copy_from_slice(b"GAME 1");
+        bytes
+    }
+
+    fn chip(bank: u16, load_address: u16, data: Vec<u8>) -> Vec<u8> {
+        let mut bytes = b"CHIP".to_vec();
+        bytes.extend((CHIP_HEADER_LENGTH as u32 + data.len() as u32).to_be_bytes());
+        bytes.extend([0, 0]);
+        bytes.extend(bank.to_be_bytes());
+        bytes.extend(load_address.to_be_bytes());
+        bytes.extend((data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_magic_desk() {
+        let mut bank_0 = vec![0x09, 0x80, 0x0c, 0x80];
+        bank_0.extend(AUTOSTART);
+        bank_0.extend([0x78, 0x60, 0x60]);
+
+        let bytes = [
+            header(19, 0, 1),
+            chip(0, 0x8000, bank_0),
+            chip(1, 0x8000, vec![0xa9, 0x01]),
+        ]
+        .concat();
+
+        let image = load(&bytes).unwrap();
+
+        assert_eq!(image.segments().len(), 2);
+        assert_eq!(image.segments()[1].name, "bank 1 ROML");
+        assert_eq!(image.segments()[1].bank, Some(1));
+        assert_eq!(image.label(0x8009), Some("cold start"));
+        assert_eq!(image.label(0x800c), Some("warm start"));
+        assert!(image.metadata().contains(&crate::Property {
+            name: "hardware".into(),
+            value: "Magic Desk, Domark, HES Australia".into()
+        }));
+
+        // Each bank is its own region, bank 1 restarts at $8000
+        let offsets: Vec<usize> = disassemble_image(&image)
+            .into_iter()
+            .map(|instruction| instruction.offset)
+            .collect();
+        assert_eq!(offsets.last(), Some(&0x8000));
+    }
+
+    #[test]
+    fn test_ultimax() {
+        let mut romh = vec![0xea; 0x2000];
+        romh[0x1ffa..].copy_from_slice(&[0x00, 0xe0, 0x10, 0xe0, 0x20, 0xe0]);
+
+        let bytes = [header(32, 1, 0), chip(0, 0xa000, romh)].concat();
+
+        let image = load(&bytes).unwrap();
+
+        assert_eq!(image.segments()[0].load_address, 0xe000);
+        assert_eq!(image.label(0xe010), Some("reset"));
+    }
+}
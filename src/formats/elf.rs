@@ -0,0 +1,474 @@
+use std::collections::BTreeMap;
+
+use gimli::{AttributeValue, DebugLine, DebugLineOffset, DebugLineStr, DebugStr, EndianSlice};
+
+use crate::{MemoryImage, Segment};
+
+use super::{read_u16_le, read_u32_le, require, Format, FormatError};
+
+// https://refspecs.linuxfoundation.org/elf/elf.pdf
+const HEADER_LENGTH: usize = 0x34;
+const SECTION_HEADER_LENGTH: usize = 0x28;
+const PROGRAM_HEADER_LENGTH: usize = 0x20;
+const SYMBOL_LENGTH: usize = 0x10;
+
+const EM_MOS: u16 = 0x1966;
+
+const PT_LOAD: u32 = 1;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u32 = 0x2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+// Section indices from here on are reserved, for example absolute symbols
+const SHN_LORESERVE: u16 = 0xff00;
+
+// llvm-mos uses 16 bit addresses in DWARF
+const DWARF_ADDRESS_SIZE: u8 = 2;
+
+type Reader<'a> = EndianSlice<'a, gimli::LittleEndian>;
+
+pub(super) fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\x7fELF")
+}
+
+#[derive(Debug)]
+struct Section<'a> {
+    name: String,
+    kind: u32,
+    flags: u32,
+    address: usize,
+    data: &'a [u8],
+    link: usize,
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<MemoryImage, FormatError> {
+    require(bytes, Format::Elf, HEADER_LENGTH)?;
+
+    // Only 32 bit little endian makes sense for the 6502
+    if bytes[4] != 1 || bytes[5] != 1 {
+        return Err(FormatError::Invalid {
+            format: Format::Elf,
+            reason: "only 32 bit little endian files are supported".into(),
+        });
+    }
+
+    let machine = read_u16_le(bytes, 0x12);
+    let entry = read_u32_le(bytes, 0x18) as usize;
+    let sections = sections(bytes)?;
+
+    let mut image = MemoryImage::new()
+        .with_property("format", "ELF")
+        .with_property(
+            "machine",
+            if machine == EM_MOS {
+                "MOS".to_string()
+            } else {
+                format!("unknown (${:04X})", machine)
+            },
+        );
+
+    let loadable: Vec<&Section> = sections
+        .iter()
+        .filter(|section| section.kind == SHT_PROGBITS && section.flags & SHF_ALLOC != 0)
+        .filter(|section| !section.data.is_empty())
+        .collect();
+
+    if loadable.is_empty() {
+        // Stripped of section headers, fall back to the program headers
+        for (index, (address, data)) in program_segments(bytes)?.into_iter().enumerate() {
+            image.add_segment(Segment::new(
+                format!("segment {}", index),
+                address,
+                data.to_vec(),
+            ));
+        }
+    } else {
+        for section in loadable {
+            image.add_segment(Segment::new(
+                section.name.clone(),
+                section.address,
+                section.data.to_vec(),
+            ));
+        }
+    }
+
+    if let Some(symtab) = sections.iter().find(|section| section.kind == SHT_SYMTAB) {
+        let strtab = sections.get(symtab.link).map(|section| section.data);
+
+        for symbol in symtab.data.chunks_exact(SYMBOL_LENGTH).skip(1) {
+            let name = read_c_string(strtab.unwrap_or_default(), read_u32_le(symbol, 0) as usize);
+            let address = read_u32_le(symbol, 4) as usize;
+            let kind = symbol[12] & 0xf;
+            let section_index = read_u16_le(symbol, 14);
+
+            if name.is_empty() || section_index == 0 || section_index >= SHN_LORESERVE {
+                continue;
+            }
+
+            match kind {
+                // Decoding has to start at the beginning of each function
+                STT_FUNC => {
+                    image.add_label(address, name.clone());
+                    image.add_entry_point(name, address);
+                }
+                STT_OBJECT => image.add_label(address, name),
+                // Plain labels from assembly sources have no type
+                0 => image.add_label(address, name),
+                _ => {}
+            }
+        }
+    }
+
+    if entry != 0 {
+        image.add_property("entry", format!("${:04X}", entry));
+        image.add_entry_point("entry", entry);
+    }
+
+    let section_data = |name: &str| {
+        sections
+            .iter()
+            .find(|section| section.name == name)
+            .map(|section| section.data)
+            .unwrap_or_default()
+    };
+
+    // Source annotations are a nicety, broken debug info shouldn't prevent
+    // disassembly of an otherwise fine file
+    if let Ok(lines) = line_info(
+        section_data(".debug_line"),
+        section_data(".debug_str"),
+        section_data(".debug_line_str"),
+    ) {
+        for (address, location) in lines {
+            image.add_comment(address, location);
+        }
+    }
+
+    Ok(image)
+}
+
+fn sections(bytes: &[u8]) -> Result<Vec<Section<'_>>, FormatError> {
+    let offset = read_u32_le(bytes, 0x20) as usize;
+    let count = read_u16_le(bytes, 0x30) as usize;
+    let names_index = read_u16_le(bytes, 0x32) as usize;
+
+    if offset == 0 {
+        return Ok(vec![]);
+    }
+
+    require(bytes, Format::Elf, offset + count * SECTION_HEADER_LENGTH)?;
+
+    let mut sections = (0..count)
+        .map(|index| {
+            let header = &bytes[offset + index * SECTION_HEADER_LENGTH..];
+            let kind = read_u32_le(header, 0x04);
+            let data_offset = read_u32_le(header, 0x10) as usize;
+            let size = read_u32_le(header, 0x14) as usize;
+
+            // NOBITS sections such as .bss take no room in the file
+            let data = if index == 0 || kind == SHT_NOBITS {
+                &[]
+            } else {
+                require(bytes, Format::Elf, data_offset + size)?;
+                &bytes[data_offset..data_offset + size]
+            };
+
+            Ok(Section {
+                name: String::new(),
+                kind,
+                flags: read_u32_le(header, 0x08),
+                address: read_u32_le(header, 0x0c) as usize,
+                data,
+                link: read_u32_le(header, 0x18) as usize,
+            })
+        })
+        .collect::<Result<Vec<Section>, FormatError>>()?;
+
+    if let Some(names) = sections.get(names_index).map(|section| section.data) {
+        for (index, section) in sections.iter_mut().enumerate() {
+            let header = &bytes[offset + index * SECTION_HEADER_LENGTH..];
+            section.name = read_c_string(names, read_u32_le(header, 0) as usize);
+        }
+    }
+
+    Ok(sections)
+}
+
+fn program_segments(bytes: &[u8]) -> Result<Vec<(usize, &[u8])>, FormatError> {
+    let offset = read_u32_le(bytes, 0x1c) as usize;
+    let count = read_u16_le(bytes, 0x2c) as usize;
+
+    require(bytes, Format::Elf, offset + count * PROGRAM_HEADER_LENGTH)?;
+
+    (0..count)
+        .map(|index| &bytes[offset + index * PROGRAM_HEADER_LENGTH..])
+        .filter(|header| read_u32_le(header, 0) == PT_LOAD)
+        .filter(|header| read_u32_le(header, 0x10) != 0)
+        .map(|header| {
+            let data_offset = read_u32_le(header, 0x04) as usize;
+            let size = read_u32_le(header, 0x10) as usize;
+            require(bytes, Format::Elf, data_offset + size)?;
+            Ok((
+                read_u32_le(header, 0x08) as usize,
+                &bytes[data_offset..data_offset + size],
+            ))
+        })
+        .collect()
+}
+
+/// Maps addresses to "file:line" using the DWARF line number programs.
+/// Programs are walked one after another, which avoids having to parse the
+/// compilation units in .debug_info.
+fn line_info(
+    debug_line: &[u8],
+    debug_str: &[u8],
+    debug_line_str: &[u8],
+) -> gimli::Result<BTreeMap<usize, String>> {
+    let endian = gimli::LittleEndian;
+    let lines = DebugLine::new(debug_line, endian);
+    let strings = DebugStr::new(debug_str, endian);
+    let line_strings = DebugLineStr::new(debug_line_str, endian);
+
+    let string = |value: AttributeValue<Reader>| -> gimli::Result<String> {
+        let reader = match value {
+            AttributeValue::String(reader) => reader,
+            AttributeValue::DebugStrRef(offset) => strings.get_str(offset)?,
+            AttributeValue::DebugLineStrRef(offset) => line_strings.get_str(offset)?,
+            _ => return Ok(String::new()),
+        };
+        Ok(reader.to_string_lossy().to_string())
+    };
+
+    let mut locations = BTreeMap::new();
+    let mut offset = 0;
+
+    while offset < debug_line.len() {
+        let program = lines.program(DebugLineOffset(offset), DWARF_ADDRESS_SIZE, None, None)?;
+        // The length doesn't count itself, which is longer in 64-bit DWARF
+        let header = program.header();
+        let next = offset + header.unit_length() + header.format().initial_length_size() as usize;
+
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if row.end_sequence() {
+                continue;
+            }
+
+            let file = match header.file(row.file_index()) {
+                Some(file) => string(file.path_name())?,
+                None => continue,
+            };
+            let line = row.line().map(|line| line.get()).unwrap_or_default();
+
+            // Several rows may point to the same address, the last one wins
+            // as it is the most specific
+            locations.insert(row.address() as usize, format!("{}:{}", file, line));
+        }
+
+        offset = next;
+    }
+
+    Ok(locations)
+}
+
+fn read_c_string(bytes: &[u8], at: usize) -> String {
+    bytes
+        .get(at..)
+        .unwrap_or_default()
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{disassemble_image, formats::load};
+
+    use super::*;
+
+    struct TestSection {
+        name: &'static str,
+        kind: u32,
+        flags: u32,
+        address: u32,
+        link: u32,
+        data: Vec<u8>,
+    }
+
+    fn section(name: &'static str, kind: u32, data: Vec<u8>) -> TestSection {
+        TestSection {
+            name,
+            kind,
+            flags: 0,
+            address: 0,
+            link: 0,
+            data,
+        }
+    }
+
+    fn build_elf(mut sections: Vec<TestSection>) -> Vec<u8> {
+        let mut names = vec![0];
+        let mut name_offsets = vec![];
+        for section in sections.iter() {
+            name_offsets.push(names.len() as u32);
+            names.extend(section.name.bytes());
+            names.push(0);
+        }
+        name_offsets.push(names.len() as u32);
+        names.extend(b".shstrtab\0");
+        sections.push(section(".shstrtab", 3, names));
+
+        let mut bytes = vec![0; HEADER_LENGTH];
+        bytes[0..6].copy_from_slice(b"\x7fELF\x01\x01");
+        bytes[0x12..0x14].copy_from_slice(&EM_MOS.to_le_bytes());
+        bytes[0x18..0x1c].copy_from_slice(&0x0200u32.to_le_bytes());
+
+        let mut data_offsets = vec![];
+        for section in sections.iter() {
+            data_offsets.push(bytes.len() as u32);
+            bytes.extend(&section.data);
+        }
+
+        let header_offset = bytes.len() as u32;
+        // Null section comes first
+        bytes.extend([0; SECTION_HEADER_LENGTH]);
+        for (index, section) in sections.iter().enumerate() {
+            for field in [
+                name_offsets[index],
+                section.kind,
+                section.flags,
+                section.address,
+                data_offsets[index],
+                section.data.len() as u32,
+                section.link,
+                0,
+                0,
+                0,
+            ] {
+                bytes.extend(field.to_le_bytes());
+            }
+        }
+
+        bytes[0x20..0x24].copy_from_slice(&header_offset.to_le_bytes());
+        bytes[0x30..0x32].copy_from_slice(&(sections.len() as u16 + 1).to_le_bytes());
+        bytes[0x32..0x34].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        bytes
+    }
+
+    fn symbol(name: u32, value: u32, kind: u8, section: u16) -> Vec<u8> {
+        [
+            name.to_le_bytes().to_vec(),
+            value.to_le_bytes().to_vec(),
+            0u32.to_le_bytes().to_vec(),
+            vec![0x10 | kind, 0],
+            section.to_le_bytes().to_vec(),
+        ]
+        .concat()
+    }
+
+    fn debug_line() -> Vec<u8> {
+        line_unit(false, 0x0200)
+    }
+
+    /// Line program of one sequence of 3 bytes from the address
+    fn line_unit(dwarf64: bool, address: u16) -> Vec<u8> {
+        let header = [
+            vec![1, 1, 1, 0xfb, 14, 13],
+            vec![0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1],
+            // No include directories, one file
+            vec![0],
+            b"main.c\0".to_vec(),
+            vec![0, 0, 0, 0],
+        ]
+        .concat();
+
+        let program = [
+            // DW_LNE_set_address
+            [vec![0, 3, 2], address.to_le_bytes().to_vec()].concat(),
+            // DW_LNS_advance_line 4, DW_LNS_copy
+            vec![3, 4, 1],
+            // DW_LNS_advance_pc 2, DW_LNS_advance_line 1, DW_LNS_copy
+            vec![2, 2, 3, 1, 1],
+            // DW_LNS_advance_pc 1, DW_LNE_end_sequence
+            vec![2, 1, 0, 1, 1],
+        ]
+        .concat();
+
+        let mut unit = 4u16.to_le_bytes().to_vec();
+        match dwarf64 {
+            true => unit.extend((header.len() as u64).to_le_bytes()),
+            false => unit.extend((header.len() as u32).to_le_bytes()),
+        }
+        unit.extend(header);
+        unit.extend(program);
+
+        let mut bytes = match dwarf64 {
+            true => [vec![0xff; 4], (unit.len() as u64).to_le_bytes().to_vec()].concat(),
+            false => (unit.len() as u32).to_le_bytes().to_vec(),
+        };
+        bytes.extend(unit);
+        bytes
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut text = section(".text", SHT_PROGBITS, vec![0xa9, 0x01, 0x60]);
+        text.flags = SHF_ALLOC | 0x4;
+        text.address = 0x0200;
+
+        let mut symbols = section(
+            ".symtab",
+            SHT_SYMTAB,
+            [
+                vec![0; SYMBOL_LENGTH],
+                symbol(1, 0x0200, STT_FUNC, 1),
+                symbol(6, 0x0202, 0, 1),
+                // Absolute symbols are constants rather than addresses
+                symbol(11, 0x0002, 0, 0xfff1),
+            ]
+            .concat(),
+        );
+        symbols.link = 3;
+
+        build_elf(vec![
+            text,
+            symbols,
+            section(".strtab", 3, b"\0main\0done\0__rc0\0".to_vec()),
+            section(".debug_line", SHT_PROGBITS, debug_line()),
+        ])
+    }
+
+    #[test]
+    fn test_elf() {
+        let image = load(&sample()).unwrap();
+
+        assert_eq!(image.segments().len(), 1);
+        assert_eq!(image.segments()[0].name, ".text");
+        assert_eq!(image.segments()[0].load_address, 0x0200);
+        assert_eq!(image.label(0x0200), Some("main"));
+        assert_eq!(image.label(0x0202), Some("done"));
+        assert_eq!(image.label(0x0002), None);
+        assert_eq!(image.entry_points()[0].name, "main");
+    }
+
+    #[test]
+    fn test_source_annotations() {
+        let instructions = disassemble_image(&load(&sample()).unwrap());
+
+        assert_eq!(instructions[0].label, Some("main".into()));
+        assert_eq!(instructions[0].comment, Some("main.c:5".into()));
+        assert_eq!(instructions[1].label, Some("done".into()));
+        assert_eq!(instructions[1].comment, Some("main.c:6".into()));
+    }
+
+    #[test]
+    fn test_64_bit_dwarf() {
+        let debug_line = [line_unit(true, 0x0200), line_unit(false, 0x0300)].concat();
+        let locations = line_info(&debug_line, &[], &[]).unwrap();
+
+        assert_eq!(locations.get(&0x0200), Some(&"main.c:5".into()));
+        assert_eq!(locations.get(&0x0300), Some(&"main.c:5".into()));
+    }
+}
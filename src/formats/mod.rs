@@ -0,0 +1,159 @@
+mod crt;
+mod elf;
+mod nsf;
+mod o65;
+mod sid;
+mod xex;
+
+use std::{error::Error, fmt::Display};
+
+use poem_openapi::Enum;
+use serde::{Deserialize, Serialize};
+
+use crate::{MemoryImage, Segment};
+
+/// Container formats that can be recognised from their header
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Enum)]
+pub enum Format {
+    /// Flat binary, whatever its header looks like
+    Raw,
+    Sid,
+    Nsf,
+    Xex,
+    Elf,
+    Crt,
+    O65,
+}
+
+impl Format {
+    pub fn detect(bytes: &[u8]) -> Self {
+        if sid::is_sid(bytes) {
+            Format::Sid
+        } else if nsf::is_nsf(bytes) {
+            Format::Nsf
+        } else if xex::is_xex(bytes) {
+            Format::Xex
+        } else if elf::is_elf(bytes) {
+            Format::Elf
+        } else if crt::is_crt(bytes) {
+            Format::Crt
+        } else if o65::is_o65(bytes) {
+            Format::O65
+        } else {
+            Format::Raw
+        }
+    }
+
+    /// Whether the format is only guessed from bytes that flat binaries can
+    /// start with as well, rather than from a signature
+    fn is_guess(self) -> bool {
+        self == Format::Xex
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FormatError {
+    Truncated {
+        format: Format,
+        needed: usize,
+        got: usize,
+    },
+    Invalid {
+        format: Format,
+        reason: String,
+    },
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Truncated {
+                format,
+                needed,
+                got,
+            } => write!(
+                f,
+                "{:?} file is truncated, needed {} bytes but got {}",
+                format, needed, got
+            ),
+            FormatError::Invalid { format, reason } => {
+                write!(f, "Invalid {:?} file: {}", format, reason)
+            }
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+/// Detects the container format and lays the payload out in memory.
+/// Anything that is not recognised is treated as a flat binary at zero.
+pub fn load(bytes: &[u8]) -> Result<MemoryImage, FormatError> {
+    load_at(bytes, 0)
+}
+
+/// Same as `load`, but flat binaries are placed at the origin. Containers
+/// carry their own load addresses, so the origin does not affect them. Bytes
+/// that only looked like a container are loaded as a flat binary when they
+/// don't parse as one.
+pub fn load_at(bytes: &[u8], origin: usize) -> Result<MemoryImage, FormatError> {
+    let format = Format::detect(bytes);
+    match load_as(bytes, format, origin) {
+        Err(_) if format.is_guess() => load_as(bytes, Format::Raw, origin),
+        result => result,
+    }
+}
+
+/// Loads the bytes as the format without detecting it, for when detection
+/// gets it wrong
+pub fn load_as(bytes: &[u8], format: Format, origin: usize) -> Result<MemoryImage, FormatError> {
+    match format {
+        Format::Raw => {
+            Ok(MemoryImage::new().with_segment(Segment::new("main", origin, bytes.to_vec())))
+        }
+        Format::Sid => sid::load(bytes),
+        Format::Nsf => nsf::load(bytes),
+        Format::Xex => xex::load(bytes),
+        Format::Elf => elf::load(bytes),
+        Format::Crt => crt::load(bytes),
+        Format::O65 => o65::load(bytes),
+    }
+}
+
+fn require(bytes: &[u8], format: Format, needed: usize) -> Result<(), FormatError> {
+    if bytes.len() < needed {
+        Err(FormatError::Truncated {
+            format,
+            needed,
+            got: bytes.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn read_u16_le(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u16_be(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32_le(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn read_u32_be(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Reads a zero padded latin-1 string, which is what the music formats use
+fn read_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,170 @@
+use crate::{MemoryImage, Segment};
+
+use super::{read_string, read_u16_le, require, Format, FormatError};
+
+// https://www.nesdev.org/wiki/NSF
+const HEADER_LENGTH: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+const BANKED_AREA: usize = 0x8000;
+
+pub(super) fn is_nsf(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"NESM\x1a")
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<MemoryImage, FormatError> {
+    require(bytes, Format::Nsf, HEADER_LENGTH)?;
+
+    let version = bytes[0x05];
+    let songs = bytes[0x06];
+    let start_song = bytes[0x07];
+    let load = read_u16_le(bytes, 0x08) as usize;
+    let init = read_u16_le(bytes, 0x0a) as usize;
+    let play = read_u16_le(bytes, 0x0c) as usize;
+    let bank_setup = &bytes[0x70..0x78];
+    let region = bytes[0x7a];
+    let chips = bytes[0x7b];
+
+    if load < BANKED_AREA / 2 {
+        return Err(FormatError::Invalid {
+            format: Format::Nsf,
+            reason: format!("load address ${:04X} is below $4000", load),
+        });
+    }
+
+    // NSF2 may have metadata chunks after the program data
+    let program_length = u32::from_le_bytes([bytes[0x7d], bytes[0x7e], bytes[0x7f], 0]) as usize;
+    let payload = if version >= 2 && program_length != 0 {
+        require(bytes, Format::Nsf, HEADER_LENGTH + program_length)?;
+        &bytes[HEADER_LENGTH..HEADER_LENGTH + program_length]
+    } else {
+        &bytes[HEADER_LENGTH..]
+    };
+
+    let mut image = MemoryImage::new();
+    let is_banked = bank_setup.iter().any(|&bank| bank != 0);
+
+    if is_banked {
+        // Banked data is padded so that the load address lines up within a
+        // 4K bank. Only the initial bank setup is laid out, other banks are
+        // swapped in by the driver at runtime.
+        let mut padded = vec![0; load & (BANK_SIZE - 1)];
+        padded.extend_from_slice(payload);
+        let banks: Vec<&[u8]> = padded.chunks(BANK_SIZE).collect();
+
+        for (slot, &bank) in bank_setup.iter().enumerate() {
+            if let Some(data) = banks.get(bank as usize) {
+                image.add_segment(Segment::new(
+                    format!("bank {}", bank),
+                    BANKED_AREA + slot * BANK_SIZE,
+                    data.to_vec(),
+                ));
+            }
+        }
+
+        image.add_property("banks", banks.len().to_string());
+        image.add_property(
+            "bank setup",
+            bank_setup
+                .iter()
+                .map(|bank| format!("{:0>2X}", bank))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    } else {
+        image.add_segment(Segment::new("payload", load, payload.to_vec()));
+    }
+
+    image.add_entry_point("init", init);
+    image.add_entry_point("play", play);
+
+    let chip_names: Vec<&str> = ["VRC6", "VRC7", "FDS", "MMC5", "Namco 163", "Sunsoft 5B"]
+        .into_iter()
+        .enumerate()
+        .filter(|(bit, _)| chips & (1 << bit) != 0)
+        .map(|(_, name)| name)
+        .collect();
+
+    Ok(image
+        .with_property("format", format!("NSF v{}", version))
+        .with_property("title", read_string(&bytes[0x0e..0x2e]))
+        .with_property("author", read_string(&bytes[0x2e..0x4e]))
+        .with_property("copyright", read_string(&bytes[0x4e..0x6e]))
+        .with_property("songs", songs.to_string())
+        .with_property("start song", start_song.to_string())
+        .with_property("load", format!("${:04X}", load))
+        .with_property("init", format!("${:04X}", init))
+        .with_property("play", format!("${:04X}", play))
+        .with_property(
+            "region",
+            match region & 0b11 {
+                0 => "NTSC",
+                1 => "PAL",
+                _ => "NTSC and PAL",
+            },
+        )
+        .with_property(
+            "expansion audio",
+            if chip_names.is_empty() {
+                "none".to_string()
+            } else {
+                chip_names.join(", ")
+            },
+        ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::formats::load;
+
+    use super::*;
+
+    fn header(load_address: u16, bank_setup: [u8; 8]) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_LENGTH];
+        bytes[0..5].copy_from_slice(b"NESM\x1a");
+        bytes[0x05] = 1;
+        bytes[0x06] = 12;
+        bytes[0x07] = 1;
+        bytes[0x08..0x0a].copy_from_slice(&load_address.to_le_bytes());
+        bytes[0x0a..0x0c].copy_from_slice(&0x8000u16.to_le_bytes());
+        bytes[0x0c..0x0e].copy_from_slice(&0x8003u16.to_le_bytes());
+        bytes[0x0e..0x13].copy_from_slice(b"Music");
+        bytes[0x70..0x78].copy_from_slice(&bank_setup);
+        bytes[0x7b] = 0b0000_0101;
+        bytes
+    }
+
+    #[test]
+    fn test_flat_nsf() {
+        let mut bytes = header(0x8000, [0; 8]);
+        bytes.extend([0x4c, 0x06, 0x80, 0x4c, 0x07, 0x80, 0x60, 0x60]);
+
+        let image = load(&bytes).unwrap();
+
+        assert_eq!(image.segments().len(), 1);
+        assert_eq!(image.segments()[0].load_address, 0x8000);
+        assert_eq!(image.entry_points()[1].address, 0x8003);
+        assert!(image.metadata().contains(&crate::Property {
+            name: "expansion audio".into(),
+            value: "VRC6, FDS".into()
+        }));
+    }
+
+    #[test]
+    fn test_banked_nsf() {
+        let mut bytes = header(0x8100, [0, 1, 1, 0, 0, 0, 0, 2]);
+        bytes.extend(vec![0xea; 0x2000]);
+
+        let image = load(&bytes).unwrap();
+        let segments = image.segments();
+
+        // Bank 0 starts with the padding
+        assert_eq!(segments[0].load_address, 0x8000);
+        assert_eq!(segments[0].bytes[0xff], 0x00);
+        assert_eq!(segments[0].bytes[0x100], 0xea);
+        assert_eq!(segments[1].name, "bank 1");
+        assert_eq!(segments[2].load_address, 0xa000);
+        // Bank 2 is only partially filled
+        assert_eq!(segments[7].load_address, 0xf000);
+        assert_eq!(segments[7].bytes.len(), 0x100);
+    }
+}
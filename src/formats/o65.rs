@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+
+use crate::{MemoryImage, Segment};
+
+use super::{read_u16_le, read_u32_le, require, Format, FormatError};
+
+// http://www.6502.org/users/andre/o65/fileformat.html
+const MAGIC: &[u8] = &[0x01, 0x00, 0x6f, 0x36, 0x35];
+const MODE_LONG: u16 = 0x2000;
+const MODE_PAGED: u16 = 0x4000;
+
+const SEGMENT_UNDEFINED: u8 = 0;
+const SEGMENT_ABSOLUTE: u8 = 1;
+
+const RELOC_WORD: u8 = 0x80;
+const RELOC_HIGH: u8 = 0x40;
+const RELOC_LOW: u8 = 0x20;
+const RELOC_SEGADR: u8 = 0xc0;
+const RELOC_SEG: u8 = 0xa0;
+
+pub(super) fn is_o65(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Cursor that knows whether sizes are 16 or 32 bits wide
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    is_long: bool,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, FormatError> {
+        require(self.bytes, Format::O65, self.position + 1)?;
+        self.position += 1;
+        Ok(self.bytes[self.position - 1])
+    }
+
+    fn word(&mut self) -> Result<usize, FormatError> {
+        if self.is_long {
+            require(self.bytes, Format::O65, self.position + 4)?;
+            self.position += 4;
+            Ok(read_u32_le(self.bytes, self.position - 4) as usize)
+        } else {
+            require(self.bytes, Format::O65, self.position + 2)?;
+            self.position += 2;
+            Ok(read_u16_le(self.bytes, self.position - 2) as usize)
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&[u8], FormatError> {
+        require(self.bytes, Format::O65, self.position + length)?;
+        self.position += length;
+        Ok(&self.bytes[self.position - length..self.position])
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let start = self.position;
+        while self.byte()? != 0 {}
+        Ok(String::from_utf8_lossy(&self.bytes[start..self.position - 1]).to_string())
+    }
+}
+
+#[derive(Debug)]
+struct Relocation {
+    address: usize,
+    kind: u8,
+    segment: u8,
+    import: Option<usize>,
+    /// HIGH relocations store the low byte separately, so that carries into
+    /// the high byte can be computed
+    low_byte: Option<u8>,
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<MemoryImage, FormatError> {
+    require(bytes, Format::O65, 8)?;
+    let mode = read_u16_le(bytes, 6);
+
+    let mut reader = Reader {
+        bytes,
+        position: 8,
+        is_long: mode & MODE_LONG != 0,
+    };
+
+    let text_base = reader.word()?;
+    let text_length = reader.word()?;
+    let data_base = reader.word()?;
+    let data_length = reader.word()?;
+    let bss_base = reader.word()?;
+    let bss_length = reader.word()?;
+    let zero_base = reader.word()?;
+    let zero_length = reader.word()?;
+    let _stack = reader.word()?;
+
+    let mut image = MemoryImage::new().with_property("format", "o65");
+
+    loop {
+        let length = reader.byte()? as usize;
+        if length == 0 {
+            break;
+        }
+        if length < 2 {
+            return Err(FormatError::Invalid {
+                format: Format::O65,
+                reason: "header option is too short".into(),
+            });
+        }
+
+        let kind = reader.byte()?;
+        let value = reader.take(length - 2)?;
+        let text = || {
+            String::from_utf8_lossy(value)
+                .trim_end_matches('\0')
+                .to_string()
+        };
+
+        match kind {
+            0 => image.add_property("filename", text()),
+            1 => image.add_property("os", format!("{:0>2X}", value.first().unwrap_or(&0))),
+            2 => image.add_property("assembler", text()),
+            3 => image.add_property("author", text()),
+            4 => image.add_property("created", text()),
+            _ => {}
+        }
+    }
+
+    let text = reader.take(text_length)?.to_vec();
+    let _data = reader.take(data_length)?;
+
+    let import_count = reader.word()?;
+    let imports = (0..import_count)
+        .map(|_| reader.string())
+        .collect::<Result<Vec<String>, FormatError>>()?;
+
+    let paged = mode & MODE_PAGED != 0;
+    let text_relocations = relocations(&mut reader, text_base, paged)?;
+    let _data_relocations = relocations(&mut reader, data_base, paged)?;
+
+    let bases = BTreeMap::from([
+        (2, ("text", text_base)),
+        (3, ("data", data_base)),
+        (4, ("bss", bss_base)),
+        (5, ("zero", zero_base)),
+    ]);
+
+    let export_count = reader.word()?;
+    for _ in 0..export_count {
+        let name = reader.string()?;
+        let segment = reader.byte()?;
+        let value = reader.word()?;
+
+        // Absolute exports are constants, not addresses in the image
+        if segment != SEGMENT_UNDEFINED && segment != SEGMENT_ABSOLUTE {
+            image.add_label(value, name);
+        }
+    }
+
+    for relocation in text_relocations {
+        if relocation.segment == SEGMENT_ABSOLUTE {
+            continue;
+        }
+
+        let Some(offset) = relocation.address.checked_sub(text_base) else {
+            continue;
+        };
+        let Some(&stored) = text.get(offset) else {
+            continue;
+        };
+
+        let target = |value: usize| match relocation.import {
+            Some(index) => {
+                let name = imports.get(index).map(String::as_str).unwrap_or("?");
+                if value == 0 {
+                    name.to_string()
+                } else {
+                    format!("{}+${:X}", name, value)
+                }
+            }
+            None => match image.labels().get(&value) {
+                Some(label) => label.clone(),
+                None => match bases.get(&relocation.segment) {
+                    Some((name, base)) => format!("{}+${:04X}", name, value.wrapping_sub(*base)),
+                    None => format!("${:04X}", value),
+                },
+            },
+        };
+
+        let operand = match relocation.kind {
+            RELOC_WORD => match text.get(offset + 1) {
+                Some(&high) => target((high as usize) << 8 | stored as usize),
+                None => continue,
+            },
+            RELOC_HIGH => format!(
+                ">{}",
+                target((stored as usize) << 8 | relocation.low_byte.unwrap_or(0) as usize)
+            ),
+            // Only the low byte is stored, so the full address is unknown
+            RELOC_LOW => {
+                let segment = match relocation.import {
+                    Some(index) => imports.get(index).map(String::as_str).unwrap_or("?"),
+                    None => bases
+                        .get(&relocation.segment)
+                        .map(|(name, _)| *name)
+                        .unwrap_or("?"),
+                };
+                format!("<{}:${:02X}", segment, stored)
+            }
+            _ => continue,
+        };
+
+        image.add_reference(relocation.address, operand);
+    }
+
+    image.add_segment(Segment::new("text", text_base, text));
+    image.add_entry_point("text", text_base);
+
+    Ok(image
+        .with_property(
+            "text",
+            format!("${:04X} (${:X} bytes)", text_base, text_length),
+        )
+        .with_property(
+            "data",
+            format!("${:04X} (${:X} bytes)", data_base, data_length),
+        )
+        .with_property(
+            "bss",
+            format!("${:04X} (${:X} bytes)", bss_base, bss_length),
+        )
+        .with_property(
+            "zero",
+            format!("${:04X} (${:X} bytes)", zero_base, zero_length),
+        )
+        .with_property("imports", imports.join(", ")))
+}
+
+fn relocations(
+    reader: &mut Reader,
+    base: usize,
+    paged: bool,
+) -> Result<Vec<Relocation>, FormatError> {
+    let mut relocations = vec![];
+    // Offsets are relative to the previous entry, starting just before the base
+    let mut address = base.wrapping_sub(1);
+
+    loop {
+        let offset = reader.byte()? as usize;
+        match offset {
+            0 => break,
+            255 => {
+                address = address.wrapping_add(254);
+                continue;
+            }
+            _ => address = address.wrapping_add(offset),
+        }
+
+        let type_byte = reader.byte()?;
+        let kind = type_byte & 0xe0;
+        let segment = type_byte & 0x1f;
+
+        let import = if segment == SEGMENT_UNDEFINED {
+            Some(reader.word()?)
+        } else {
+            None
+        };
+
+        let low_byte = match kind {
+            RELOC_HIGH if !paged => Some(reader.byte()?),
+            RELOC_SEG => {
+                reader.take(2)?;
+                None
+            }
+            RELOC_WORD | RELOC_LOW | RELOC_HIGH | RELOC_SEGADR => None,
+            _ => {
+                return Err(FormatError::Invalid {
+                    format: Format::O65,
+                    reason: format!("unknown relocation type ${:02X}", type_byte),
+                })
+            }
+        };
+
+        relocations.push(Relocation {
+            address,
+            kind,
+            segment,
+            import,
+            low_byte,
+        });
+    }
+
+    Ok(relocations)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{disassemble_image, formats::load};
+
+    use super::*;
+
+    fn sample() -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([0x00, 0x00, 0x00]);
+        for word in [0x1000u16, 11, 0x2000, 2, 0x3000, 0, 0, 0, 0] {
+            bytes.extend(word.to_le_bytes());
+        }
+
+        // Assembler option
+        bytes.extend([6, 2]);
+        bytes.extend(b"xa\0\0");
+        bytes.push(0);
+
+        bytes.extend([
+            0x20, 0x00, 0x00, // JSR print
+            0xad, 0x00, 0x20, // LDA counter
+            0xa9, 0x06, // LDA #<loop
+            0x4c, 0x06, 0x10, // JMP $1006
+        ]);
+        bytes.extend([0x00, 0x00]);
+
+        // Imports
+        bytes.extend([1, 0]);
+        bytes.extend(b"print\0");
+
+        // Text relocations
+        bytes.extend([2, RELOC_WORD | SEGMENT_UNDEFINED, 0, 0]);
+        bytes.extend([3, RELOC_WORD | 3]);
+        bytes.extend([3, RELOC_LOW | 2]);
+        bytes.extend([2, RELOC_WORD | 2]);
+        bytes.push(0);
+        // Data relocations
+        bytes.push(0);
+
+        // Exports
+        bytes.extend([3, 0]);
+        bytes.extend(b"start\0");
+        bytes.extend([2, 0x00, 0x10]);
+        bytes.extend(b"loop\0");
+        bytes.extend([2, 0x06, 0x10]);
+        bytes.extend(b"size\0");
+        bytes.extend([SEGMENT_ABSOLUTE, 0x03, 0x10]);
+
+        bytes
+    }
+
+    #[test]
+    fn test_o65() {
+        let image = load(&sample()).unwrap();
+
+        assert_eq!(image.segments()[0].load_address, 0x1000);
+        assert_eq!(image.label(0x1000), Some("start"));
+        assert_eq!(image.label(0x1006), Some("loop"));
+        assert_eq!(image.label(0x1003), None);
+
+        let addresses: Vec<String> = disassemble_image(&image)
+            .into_iter()
+            .map(|instruction| instruction.address)
+            .collect();
+
+        assert_eq!(addresses, vec!["print", "data+$0000", "#<text:$06", "loop"]);
+    }
+}
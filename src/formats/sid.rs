@@ -0,0 +1,174 @@
+use crate::{MemoryImage, Segment};
+
+use super::{read_string, read_u16_be, read_u16_le, require, Format, FormatError};
+
+// https://www.hvsc.c64.org/download/C64Music/DOCUMENTS/SID_file_format.txt
+const V1_HEADER_LENGTH: usize = 0x76;
+const V2_HEADER_LENGTH: usize = 0x7c;
+
+pub(super) fn is_sid(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"PSID") || bytes.starts_with(b"RSID")
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<MemoryImage, FormatError> {
+    require(bytes, Format::Sid, V1_HEADER_LENGTH)?;
+
+    let kind = String::from_utf8_lossy(&bytes[0..4]).to_string();
+    let version = read_u16_be(bytes, 0x04);
+    let data_offset = read_u16_be(bytes, 0x06) as usize;
+    let header_load = read_u16_be(bytes, 0x08);
+    let init = read_u16_be(bytes, 0x0a);
+    let play = read_u16_be(bytes, 0x0c);
+    let songs = read_u16_be(bytes, 0x0e);
+    let start_song = read_u16_be(bytes, 0x10);
+
+    if !(1..=4).contains(&version) {
+        return Err(FormatError::Invalid {
+            format: Format::Sid,
+            reason: format!("unknown version {}", version),
+        });
+    }
+
+    if data_offset != V1_HEADER_LENGTH && data_offset != V2_HEADER_LENGTH {
+        return Err(FormatError::Invalid {
+            format: Format::Sid,
+            reason: format!("unexpected data offset ${:04X}", data_offset),
+        });
+    }
+
+    require(bytes, Format::Sid, data_offset)?;
+
+    // A zero load address means the payload starts with one, C64 PRG style
+    let (load, payload) = if header_load == 0 {
+        require(bytes, Format::Sid, data_offset + 2)?;
+        (read_u16_le(bytes, data_offset), &bytes[data_offset + 2..])
+    } else {
+        (header_load, &bytes[data_offset..])
+    };
+
+    // Zero init means the driver is initialised from the load address
+    let init = if init == 0 { load } else { init };
+
+    let mut image = MemoryImage::new()
+        .with_segment(Segment::new("payload", load as usize, payload.to_vec()))
+        .with_entry_point("init", init as usize)
+        .with_property("format", format!("{} v{}", kind, version))
+        .with_property("title", read_string(&bytes[0x16..0x36]))
+        .with_property("author", read_string(&bytes[0x36..0x56]))
+        .with_property("copyright", read_string(&bytes[0x56..0x76]))
+        .with_property("songs", songs.to_string())
+        .with_property("start song", start_song.to_string())
+        .with_property("load", format!("${:04X}", load))
+        .with_property("init", format!("${:04X}", init));
+
+    // Zero play means the tune installs its own interrupt handler
+    if play != 0 {
+        image.add_entry_point("play", play as usize);
+        image.add_property("play", format!("${:04X}", play));
+    }
+
+    if version >= 2 && data_offset == V2_HEADER_LENGTH {
+        let flags = read_u16_be(bytes, 0x76);
+
+        image.add_property(
+            "clock",
+            match (flags >> 2) & 0b11 {
+                1 => "PAL",
+                2 => "NTSC",
+                3 => "PAL and NTSC",
+                _ => "unknown",
+            },
+        );
+        image.add_property(
+            "sid model",
+            match (flags >> 4) & 0b11 {
+                1 => "6581",
+                2 => "8580",
+                3 => "6581 and 8580",
+                _ => "unknown",
+            },
+        );
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::formats::{load, FormatError};
+
+    use super::*;
+
+    fn header(load_address: u16, init: u16, play: u16) -> Vec<u8> {
+        let mut bytes = vec![0; V2_HEADER_LENGTH];
+        bytes[0..4].copy_from_slice(b"PSID");
+        bytes[0x04..0x06].copy_from_slice(&2u16.to_be_bytes());
+        bytes[0x06..0x08].copy_from_slice(&(V2_HEADER_LENGTH as u16).to_be_bytes());
+        bytes[0x08..0x0a].copy_from_slice(&load_address.to_be_bytes());
+        bytes[0x0a..0x0c].copy_from_slice(&init.to_be_bytes());
+        bytes[0x0c..0x0e].copy_from_slice(&play.to_be_bytes());
+        bytes[0x0e..0x10].copy_from_slice(&3u16.to_be_bytes());
+        bytes[0x10..0x12].copy_from_slice(&1u16.to_be_bytes());
+        bytes[0x16..0x1b].copy_from_slice(b"Tune!");
+        bytes[0x36..0x3c].copy_from_slice(b"Author");
+        bytes[0x56..0x5a].copy_from_slice(b"1987");
+        bytes[0x76..0x78].copy_from_slice(&0b0001_0100u16.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_psid() {
+        let mut bytes = header(0x1000, 0x1000, 0x1003);
+        bytes.extend([0x4c, 0x06, 0x10, 0x4c, 0x07, 0x10, 0x60, 0x60]);
+
+        let image = load(&bytes).unwrap();
+
+        assert_eq!(image.segments()[0].load_address, 0x1000);
+        assert_eq!(image.segments()[0].bytes.len(), 8);
+        assert_eq!(image.entry_points()[0].address, 0x1000);
+        assert_eq!(image.entry_points()[1].address, 0x1003);
+
+        let property = |name: &str| {
+            image
+                .metadata()
+                .iter()
+                .find(|property| property.name == name)
+                .unwrap()
+                .value
+                .clone()
+        };
+        assert_eq!(property("format"), "PSID v2");
+        assert_eq!(property("title"), "Tune!");
+        assert_eq!(property("author"), "Author");
+        assert_eq!(property("copyright"), "1987");
+        assert_eq!(property("songs"), "3");
+        assert_eq!(property("clock"), "PAL");
+        assert_eq!(property("sid model"), "6581");
+    }
+
+    #[test]
+    fn test_load_address_in_payload() {
+        let mut bytes = header(0, 0, 0);
+        bytes.extend([0x00, 0xc0, 0x60]);
+
+        let image = load(&bytes).unwrap();
+
+        assert_eq!(image.segments()[0].load_address, 0xc000);
+        assert_eq!(image.segments()[0].bytes, vec![0x60]);
+        // No play address, init defaults to load address
+        assert_eq!(image.entry_points().len(), 1);
+        assert_eq!(image.entry_points()[0].address, 0xc000);
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert_eq!(
+            load(b"PSID\x00\x02"),
+            Err(FormatError::Truncated {
+                format: Format::Sid,
+                needed: V1_HEADER_LENGTH,
+                got: 6
+            })
+        );
+    }
+}
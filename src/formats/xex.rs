@@ -0,0 +1,163 @@
+use std::ops::RangeInclusive;
+
+use crate::{MemoryImage, Segment};
+
+use super::{read_u16_le, require, Format, FormatError};
+
+// https://www.atarimax.com/jindroush.atari.org/afmtexe.html
+const MARKER: [u8; 2] = [0xff, 0xff];
+const RUNAD: usize = 0x02e0;
+const INITAD: usize = 0x02e2;
+const VECTORS: RangeInclusive<usize> = RUNAD..=INITAD + 1;
+
+pub(super) fn is_xex(bytes: &[u8]) -> bool {
+    // The marker alone is a common enough pair of bytes, so require that the
+    // first segment header makes sense as well
+    bytes.len() >= 6 && bytes.starts_with(&MARKER) && read_u16_le(bytes, 4) >= read_u16_le(bytes, 2)
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<MemoryImage, FormatError> {
+    let mut image = MemoryImage::new();
+    let mut inits = vec![];
+    let mut run = None;
+    let mut position = 0;
+    let mut index = 0;
+
+    while position < bytes.len() {
+        // The marker is mandatory for the first segment only
+        if bytes[position..].starts_with(&MARKER) {
+            position += 2;
+        } else if index == 0 {
+            return Err(FormatError::Invalid {
+                format: Format::Xex,
+                reason: "missing $FFFF header".into(),
+            });
+        }
+
+        require(bytes, Format::Xex, position + 4)?;
+        let start = read_u16_le(bytes, position) as usize;
+        let end = read_u16_le(bytes, position + 2) as usize;
+        position += 4;
+
+        if end < start {
+            return Err(FormatError::Invalid {
+                format: Format::Xex,
+                reason: format!(
+                    "segment {} ends at ${:04X} before it starts at ${:04X}",
+                    index, end, start
+                ),
+            });
+        }
+
+        let length = end - start + 1;
+        require(bytes, Format::Xex, position + length)?;
+        let data = &bytes[position..position + length];
+        position += length;
+
+        let vector = |address: usize| {
+            (start <= address && address < end).then(|| read_u16_le(data, address - start) as usize)
+        };
+
+        if let Some(address) = vector(INITAD) {
+            inits.push(address);
+        }
+        if let Some(address) = vector(RUNAD) {
+            run = Some(address);
+        }
+
+        // Segments that only set the vectors are not worth disassembling
+        if !(VECTORS.contains(&start) && VECTORS.contains(&end)) {
+            image.add_segment(Segment::new(
+                format!("segment {}", index),
+                start,
+                data.to_vec(),
+            ));
+        }
+
+        index += 1;
+    }
+
+    image.add_property("format", "Atari XEX");
+    image.add_property("segments", index.to_string());
+
+    for (number, &address) in inits.iter().enumerate() {
+        let name = if inits.len() == 1 {
+            "init".to_string()
+        } else {
+            format!("init {}", number + 1)
+        };
+        image.add_property(name.as_str(), format!("${:04X}", address));
+        image.add_entry_point(name, address);
+    }
+
+    if let Some(address) = run {
+        image.add_property("run", format!("${:04X}", address));
+        image.add_entry_point("run", address);
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::formats::{load, load_as};
+
+    use super::*;
+
+    #[test]
+    fn test_xex() {
+        let bytes = [
+            // Loader segment that has an init vector
+            vec![0xff, 0xff, 0x00, 0x06, 0x02, 0x06, 0xa9, 0x00, 0x60],
+            vec![0xe2, 0x02, 0xe3, 0x02, 0x00, 0x06],
+            // Main program with the marker repeated
+            vec![0xff, 0xff, 0x00, 0x20, 0x02, 0x20, 0x4c, 0x00, 0x20],
+            vec![0xe0, 0x02, 0xe1, 0x02, 0x00, 0x20],
+        ]
+        .concat();
+
+        assert_eq!(Format::detect(&bytes), Format::Xex);
+        let image = load(&bytes).unwrap();
+
+        assert_eq!(image.segments().len(), 2);
+        assert_eq!(image.segments()[0].range(), 0x0600..0x0603);
+        assert_eq!(image.segments()[1].range(), 0x2000..0x2003);
+        assert_eq!(image.entry_points()[0].name, "init");
+        assert_eq!(image.entry_points()[0].address, 0x0600);
+        assert_eq!(image.entry_points()[1].name, "run");
+        assert_eq!(image.entry_points()[1].address, 0x2000);
+    }
+
+    #[test]
+    fn test_truncated_segment() {
+        let bytes = [0xff, 0xff, 0x00, 0x06, 0x10, 0x06, 0xa9];
+
+        assert_eq!(
+            load_as(&bytes, Format::Xex, 0),
+            Err(FormatError::Truncated {
+                format: Format::Xex,
+                needed: 23,
+                got: 7
+            })
+        );
+    }
+
+    #[test]
+    fn test_flat_binary_fallback() {
+        // Looks like a header but is code, so it is disassembled as it is
+        let bytes = [0xff, 0xff, 0x00, 0x00, 0x10, 0x00, 0xea, 0xea];
+        assert_eq!(Format::detect(&bytes), Format::Xex);
+
+        let image = load(&bytes).unwrap();
+        assert_eq!(image.segments().len(), 1);
+        assert_eq!(image.segments()[0].bytes, bytes);
+    }
+
+    #[test]
+    fn test_not_xex() {
+        assert_eq!(
+            Format::detect(&[0xff, 0xff, 0x10, 0x06, 0x00, 0x06]),
+            Format::Raw
+        );
+    }
+}
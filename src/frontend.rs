@@ -1,100 +1,278 @@
+use std::sync::Arc;
+
 use askama::Template;
 use poem::web::{Form, Multipart};
-use poem_openapi::{payload::Html, OpenApi};
+use poem_openapi::{param::Path, payload::Html, ApiResponse, OpenApi};
 use serde::Deserialize;
-use tracing::{event, Level};
+use tracing::{event, instrument, Level};
 
-use crate::{disassemble, Instruction};
+use crate::{
+    api::{blocking, Failure},
+    disassemble_image, hex_listing, hex_rows, load, parse_hex_text, Charset, HexLine, Instruction,
+    Limits, MemoryImage, Property, Store,
+};
 
-#[derive(Debug, Template)]
+#[derive(Debug, Default, Template)]
 #[template(path = "main.html")]
-struct MainPage;
+struct MainPage {
+    /// Hex rows put in the text area
+    bytes: String,
+    /// Table rendered up front
+    output: String,
+}
 
 #[derive(Debug, Deserialize)]
 pub struct TableParams {
     bytes: String,
+    /// `hex` puts the hex and text next to each instruction
+    #[serde(default)]
+    view: String,
+    /// `petscii` renders the text column as Commodore characters
+    #[serde(default)]
+    charset: String,
 }
 
 #[derive(Debug, Template)]
 #[template(path = "table.html")]
 struct TableTemplate {
+    metadata: Vec<Property>,
     lines: Vec<Instruction>,
 }
 
+#[derive(Debug, Template)]
+#[template(path = "hex-table.html")]
+struct HexTableTemplate {
+    metadata: Vec<Property>,
+    lines: Vec<HexLine>,
+}
+
 #[derive(Debug, Template)]
 #[template(path = "table-error.html")]
 struct TableErrorTemplate {
     illegals: Vec<(usize, String)>,
 }
 
-#[derive(Debug)]
-pub struct Frontend;
+#[derive(Debug, Template)]
+#[template(path = "load-error.html")]
+struct LoadErrorTemplate {
+    message: String,
+}
+
+/// HTML fragment, errors carry the same code as the JSON API in a header
+#[derive(Debug, ApiResponse)]
+pub enum PageOutput {
+    /// Uploads also carry the permalink of the stored binary, which htmx
+    /// puts in the address bar
+    #[oai(status = 200)]
+    Ok(Html<String>, #[oai(header = "HX-Push-Url")] Option<String>),
+    /// The input looked like a known container format but its header is
+    /// broken, or the upload could not be read
+    #[oai(status = 400)]
+    BadRequest(Html<String>, #[oai(header = "X-Error-Code")] String),
+    #[oai(status = 404)]
+    NotFound(Html<String>, #[oai(header = "X-Error-Code")] String),
+    /// The input is larger than the server accepts
+    #[oai(status = 413)]
+    PayloadTooLarge(Html<String>, #[oai(header = "X-Error-Code")] String),
+    /// The text contains something that is not a byte
+    #[oai(status = 422)]
+    UnprocessableEntity(Html<String>, #[oai(header = "X-Error-Code")] String),
+    #[oai(status = 500)]
+    InternalServerError(Html<String>, #[oai(header = "X-Error-Code")] String),
+}
+
+impl PageOutput {
+    fn failed(failure: Failure, html: String) -> Self {
+        let code = failure.code().to_string();
+        match failure {
+            Failure::BadRequest(_) => PageOutput::BadRequest(Html(html), code),
+            Failure::NotFound(_) => PageOutput::NotFound(Html(html), code),
+            Failure::TooLarge(_) => PageOutput::PayloadTooLarge(Html(html), code),
+            Failure::Unprocessable(_) => PageOutput::UnprocessableEntity(Html(html), code),
+            Failure::Internal(_) => PageOutput::InternalServerError(Html(html), code),
+        }
+    }
+
+    /// Error shown with the generic error fragment
+    fn error(failure: Failure) -> Self {
+        let message = failure.body().message;
+        match (LoadErrorTemplate { message }).render() {
+            Ok(html) => Self::failed(failure, html),
+            Err(err) => Self::failed(Failure::Internal(err.to_string()), err.to_string()),
+        }
+    }
+}
+
+/// Disassembly of the image as the HTML table fragment
+pub(crate) fn table_html(image: &MemoryImage) -> Result<String, Failure> {
+    TableTemplate {
+        metadata: image.metadata().to_vec(),
+        lines: disassemble_image(image),
+    }
+    .render()
+    .map_err(|err| Failure::Internal(format!("Rendering failed: {}", err)))
+}
+
+/// Renders the template, or explains that it could not be rendered
+fn render(template: &impl Template) -> PageOutput {
+    match template.render() {
+        Ok(html) => PageOutput::Ok(Html(html), None),
+        Err(err) => PageOutput::error(Failure::Internal(format!("Rendering failed: {}", err))),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Frontend {
+    limits: Limits,
+    store: Arc<Store>,
+}
+
+impl Frontend {
+    pub fn new(limits: Limits) -> Self {
+        Frontend {
+            limits,
+            store: Arc::default(),
+        }
+    }
+
+    /// Shares the store with the API, so that permalinks and IDs are the same
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = store;
+        self
+    }
+}
 
 #[OpenApi]
 impl Frontend {
     #[oai(path = "/", method = "get")]
-    pub async fn front_page(&self) -> Html<String> {
+    pub async fn front_page(&self) -> PageOutput {
         event!(Level::INFO, "Front page");
-        Html(MainPage.render().unwrap())
+        render(&MainPage::default())
+    }
+
+    /// Permalink of an uploaded binary: the front page with its bytes filled
+    /// in and disassembled with the annotations of its project
+    #[instrument(skip_all, fields(id = %id.0))]
+    #[oai(path = "/binaries/:id", method = "get")]
+    pub async fn permalink(&self, id: Path<String>) -> PageOutput {
+        event!(Level::INFO, "Permalink");
+
+        let Some(bytes) = self.store.get(&id) else {
+            return PageOutput::error(Failure::NotFound(format!(
+                "There is no binary with the ID {}",
+                id.0
+            )));
+        };
+        let project = self.store.project(&id);
+
+        let result = blocking(move || {
+            let image = match project {
+                Some(project) => project.image(&bytes)?,
+                None => load(&bytes)?,
+            };
+            let output = table_html(&image)?;
+            Ok(MainPage {
+                bytes: hex_rows(&bytes, 8).join("\n"),
+                output,
+            })
+        })
+        .await;
+
+        match result {
+            Ok(page) => render(&page),
+            Err(failure) => PageOutput::error(failure),
+        }
     }
 
     #[oai(path = "/table", method = "post")]
-    pub async fn table(&self, Form(params): Form<TableParams>) -> Html<String> {
+    pub async fn table(&self, Form(params): Form<TableParams>) -> PageOutput {
         event!(Level::INFO, "Table");
 
-        let mut illegals = vec![];
-
-        let filtered: Vec<char> = params
-            .bytes
-            .chars()
-            .filter(|c| !c.is_ascii_whitespace()) // Strip whitespace
-            .collect();
-
-        let bytes: Vec<u8> = filtered
-            // This could be manually folded or chunked with itertools,
-            // but I like the simplicity of collecting and using std rust tools.
-            .chunks(2)
-            .enumerate()
-            .filter_map(|(index, chars)| {
-                let token = String::from_iter(chars);
-                if let Ok(digit) = u8::from_str_radix(token.as_str(), 16) {
-                    Some(digit)
-                } else {
-                    illegals.push((index, token));
-                    None
-                }
-            })
-            .collect();
+        let bytes = match parse_hex_text(&params.bytes) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                let failure = Failure::Unprocessable(err.to_string());
+                let illegals = err.invalid;
+                return match (TableErrorTemplate { illegals }).render() {
+                    Ok(html) => PageOutput::failed(failure, html),
+                    Err(err) => PageOutput::error(Failure::Internal(err.to_string())),
+                };
+            }
+        };
+        if bytes.len() > self.limits.max_payload {
+            return PageOutput::error(Failure::TooLarge(self.limits.max_payload));
+        }
+
+        let result = blocking(move || {
+            let image = load(&bytes)?;
+            if params.view != "hex" {
+                return table_html(&image);
+            }
+
+            let charset = match params.charset.as_str() {
+                "petscii" => Charset::Petscii,
+                _ => Charset::Ascii,
+            };
+            HexTableTemplate {
+                metadata: image.metadata().to_vec(),
+                lines: hex_listing(&image, 8, charset),
+            }
+            .render()
+            .map_err(|err| Failure::Internal(format!("Rendering failed: {}", err)))
+        })
+        .await;
 
-        if illegals.is_empty() {
-            let lines = disassemble(&bytes);
-            Html(TableTemplate { lines }.render().unwrap())
-        } else {
-            Html(TableErrorTemplate { illegals }.render().unwrap())
+        match result {
+            Ok(html) => PageOutput::Ok(Html(html), None),
+            Err(failure) => PageOutput::error(failure),
         }
     }
 
+    /// Hex rows of the uploaded file. The file is only stored, and gets a
+    /// permalink, when the form has a `permalink` field next to it.
     #[oai(path = "/decode", method = "post")]
-    pub async fn decode_file(&self, mut multipart: Multipart) -> Html<String> {
+    pub async fn decode_file(&self, mut multipart: Multipart) -> PageOutput {
         event!(Level::INFO, "Decode file");
 
-        let Ok(Some(file)) = multipart.next_field().await else {
-            // No file is treated as an empty file
-            return Html(String::new());
+        let mut bytes = None;
+        let mut keep = false;
+        loop {
+            let field = match multipart.next_field().await {
+                Ok(Some(field)) => field,
+                Ok(None) => break,
+                Err(err) => return PageOutput::error(Failure::BadRequest(err.to_string())),
+            };
+            // Fields that are not the permalink choice are the file, which
+            // keeps scripts that post a single unnamed part working
+            if field.name() == Some("permalink") {
+                keep = true;
+                continue;
+            }
+            bytes = match field.bytes().await {
+                Ok(bytes) => Some(bytes),
+                Err(err) => return PageOutput::error(Failure::BadRequest(err.to_string())),
+            };
+        }
+        let Some(bytes) = bytes else {
+            return PageOutput::error(Failure::BadRequest("No file was uploaded".into()));
         };
+        if bytes.len() > self.limits.max_payload {
+            return PageOutput::error(Failure::TooLarge(self.limits.max_payload));
+        }
 
-        Html(
-            file.bytes()
-                .await
-                .unwrap_or_default() // Failed to parse file, treat as an empty file
-                .into_iter()
-                .map(|byte| format!("{:0>2X}", byte))
-                .collect::<Vec<_>>()
-                .chunks(8)
-                .map(|chunk| chunk.join(" "))
-                .collect::<Vec<String>>()
-                .join("\n"),
-        )
+        let rows = hex_rows(&bytes, 8).join("\n");
+        if !keep {
+            return PageOutput::Ok(Html(rows), None);
+        }
+        // The upload still works without a permalink
+        let permalink = match self.store.insert(bytes) {
+            Ok(id) => Some(format!("/binaries/{}", id)),
+            Err(err) => {
+                event!(Level::WARN, "Storing the upload failed: {}", err);
+                None
+            }
+        };
+        PageOutput::Ok(Html(rows), permalink)
     }
 }
 
@@ -158,6 +336,60 @@ mod tests {
         assert!(output.contains("'gh' at byte 3"), "output: {}", output);
     }
 
+    #[tokio::test]
+    async fn test_table_from_c_array() {
+        let client = reqwest::Client::new();
+
+        let output = client
+            .post("http://localhost:9999/table")
+            .form(
+                &vec![("bytes", "char code[] = { 0xA9, 0xBD, 0x60 };")]
+                    .into_iter()
+                    .collect::<HashMap<&str, &str>>(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert!(output.contains("#$BD"), "output: {}", output);
+        assert!(output.contains("RTS"), "output: {}", output);
+    }
+
+    #[tokio::test]
+    async fn test_hex_table() {
+        let client = reqwest::Client::new();
+
+        let output = client
+            .post("http://localhost:9999/table")
+            .form(
+                &vec![
+                    ("bytes", "a9 41 02 02 60"),
+                    ("view", "hex"),
+                    ("charset", "petscii"),
+                ]
+                .into_iter()
+                .collect::<HashMap<&str, &str>>(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        for chunk in ["A9 41", ".A", "02 02", "RTS"] {
+            assert!(
+                output.contains(chunk),
+                "output: {}, chunk: {}",
+                output,
+                chunk
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_decode() {
         let client = reqwest::Client::new();
@@ -188,4 +420,78 @@ F8 EE 61 E6 61 00 04 02
 
         assert_eq!(expected, lines);
     }
+
+    #[tokio::test]
+    async fn test_permalink() {
+        let client = reqwest::Client::new();
+
+        let upload = |permalink: bool| {
+            let form = reqwest::multipart::Form::new()
+                .part("file", reqwest::multipart::Part::bytes(vec![0xa9, 0xbd]));
+            let form = match permalink {
+                true => form.text("permalink", "on"),
+                false => form,
+            };
+            client
+                .post("http://localhost:9999/decode")
+                .multipart(form)
+                .send()
+        };
+
+        // Nothing is kept unless the client asks for it
+        let response = upload(false).await.unwrap();
+        assert!(!response.headers().contains_key("HX-Push-Url"));
+
+        let response = upload(true).await.unwrap();
+        let permalink = response.headers()["HX-Push-Url"].to_str().unwrap();
+        assert!(permalink.starts_with("/binaries/"), "{}", permalink);
+
+        let page = client
+            .get(format!("http://localhost:9999{}", permalink))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(page.contains("<textarea name=\"bytes\">A9 BD</textarea>"));
+        assert!(page.contains("#$BD"), "{}", page);
+
+        let id = permalink.trim_start_matches("/binaries/");
+        let deleted = client
+            .delete(format!("http://localhost:9999/json/binaries/{}", id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(deleted.status(), 204);
+        let response = client
+            .get(format!("http://localhost:9999{}", permalink))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post("http://localhost:9999/table")
+            .form(&[("bytes", "abcdefgh")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 422);
+        assert_eq!(response.headers()["X-Error-Code"], "unprocessable");
+
+        let response = client
+            .post("http://localhost:9999/decode")
+            .multipart(reqwest::multipart::Form::new())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.headers()["X-Error-Code"], "bad_request");
+    }
 }
@@ -0,0 +1,91 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use poem::{http::StatusCode, Endpoint, Request, Response, Result};
+
+use crate::{limits::error_response, ErrorBody, Store};
+
+/// Whether the server should get traffic, served at `/readyz` for load
+/// balancers and orchestrators. Clones share the state, so the server can
+/// keep one to turn readiness off when it starts shutting down.
+#[derive(Debug, Clone)]
+pub struct Health {
+    ready: Arc<AtomicBool>,
+    store: Arc<Store>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            ready: Arc::new(AtomicBool::new(true)),
+            store: Arc::default(),
+        }
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Not ready while the directory of the store is unavailable
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = store;
+        self
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+}
+
+impl Endpoint for Health {
+    type Output = Response;
+
+    async fn call(&self, _req: Request) -> Result<Self::Output> {
+        let unavailable = |code, message| {
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorBody::new(code, message),
+            )
+        };
+
+        Ok(if !self.ready.load(Ordering::Relaxed) {
+            unavailable("shutting_down", "The server is shutting down")
+        } else if !self.store.is_available() {
+            unavailable("store_unavailable", "The store directory is missing")
+        } else {
+            Response::builder().content_type("text/plain").body("ready")
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let directory = std::env::temp_dir().join(format!("health-{}", std::process::id()));
+        let store = Arc::new(Store::open(&directory, 0).unwrap());
+        let health = Health::new().with_store(store);
+        let status = |health: Health| async move {
+            health
+                .call(Request::default())
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        };
+
+        assert_eq!(status(health.clone()).await, 200);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(status(health.clone()).await, 503);
+
+        let health = Health::new();
+        health.clone().set_ready(false);
+        assert_eq!(status(health).await, 503);
+    }
+}
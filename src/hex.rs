@@ -0,0 +1,294 @@
+use std::{error::Error, fmt::Display};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::parse_number;
+
+/// Tokens that could not be turned into bytes
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HexTextError {
+    /// Zero-indexed byte position and the offending token
+    pub invalid: Vec<(usize, String)>,
+}
+
+impl Display for HexTextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let invalid: Vec<String> = self
+            .invalid
+            .iter()
+            .map(|(index, token)| format!("'{}' at byte {}", token, index))
+            .collect();
+        write!(f, "invalid bytes: {}", invalid.join(", "))
+    }
+}
+
+impl Error for HexTextError {}
+
+/// Reads bytes pasted as text. Understands plain hex (`A9 BD`, `a9bd`),
+/// prefixed hex (`$A9,$BD`, `0xA9, 0xBD`), C arrays, `.byte`/`!byte` lines,
+/// monitor dumps with a leading `C000:` or `>C:c000` address and base64,
+/// which is tried when the text is not hex or has a `base64:` prefix.
+/// Inside C arrays and `.byte` lines numbers without a prefix are decimal,
+/// like the assemblers and compilers read them.
+pub fn parse_hex_text(text: &str) -> Result<Vec<u8>, HexTextError> {
+    let text = text.trim();
+
+    if let Some(encoded) = text.strip_prefix("base64:") {
+        return decode_base64(encoded).ok_or_else(|| HexTextError {
+            invalid: vec![(0, "base64".into())],
+        });
+    }
+
+    let mut parser = Parser::default();
+
+    if let Some((_, rest)) = text.split_once('{') {
+        let body = rest.rsplit_once('}').map_or(rest, |(body, _)| body);
+        for token in strip_c_comments(body).split([',', ' ', '\t', '\r', '\n']) {
+            parser.number(token);
+        }
+    } else {
+        for line in text.lines() {
+            parser.line(line);
+        }
+        parser.flush();
+    }
+
+    if parser.invalid.is_empty() {
+        return Ok(parser.bytes);
+    }
+    match looks_like_base64(text)
+        .then(|| decode_base64(text))
+        .flatten()
+    {
+        Some(bytes) => Ok(bytes),
+        None => Err(HexTextError {
+            invalid: parser.invalid,
+        }),
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    bytes: Vec<u8>,
+    invalid: Vec<(usize, String)>,
+    /// Digits of plain hex, which are read in pairs once something else
+    /// comes, so that whitespace between them doesn't matter
+    run: String,
+}
+
+impl Parser {
+    fn line(&mut self, line: &str) {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        let mut tokens = line.split_whitespace().peekable();
+
+        // Address column of a monitor dump
+        if tokens
+            .peek()
+            .is_some_and(|first| first.starts_with('>') || first.ends_with(':'))
+        {
+            tokens.next();
+        }
+
+        let is_data_directive = tokens.peek().is_some_and(|first| {
+            matches!(
+                first.to_ascii_lowercase().as_str(),
+                ".byte" | "!byte" | ".db" | "db" | "!by" | "!08" | ".by"
+            )
+        });
+
+        if is_data_directive {
+            tokens.next();
+            let rest: Vec<&str> = tokens.collect();
+            for token in rest.join(" ").split(',') {
+                self.number(token);
+            }
+        } else {
+            for token in tokens.flat_map(|token| token.split(',')) {
+                self.hex(token);
+            }
+        }
+    }
+
+    /// Hex, where plain digits join the run and prefixed ones are read in
+    /// pairs on their own
+    fn hex(&mut self, token: &str) {
+        let prefixed = token
+            .strip_prefix('$')
+            .or_else(|| token.strip_prefix("0x"))
+            .or_else(|| token.strip_prefix("0X"));
+        let digits = prefixed.unwrap_or(token);
+
+        // Intel style 0FFh is a single value with a leading zero
+        if let Some(value) = digits.strip_suffix(['h', 'H']) {
+            if let Ok(byte) = u8::from_str_radix(value, 16) {
+                self.flush();
+                self.bytes.push(byte);
+                return;
+            }
+        }
+
+        match prefixed {
+            Some(digits) => {
+                self.flush();
+                self.pairs(digits);
+            }
+            None => self.run.push_str(digits),
+        }
+    }
+
+    /// Reads the run of plain hex, an odd digit at the end is a byte alone
+    fn flush(&mut self) {
+        let run = std::mem::take(&mut self.run);
+        self.pairs(&run);
+    }
+
+    fn pairs(&mut self, digits: &str) {
+        let digits: Vec<char> = digits.chars().collect();
+        for chunk in digits.chunks(2) {
+            let chunk = String::from_iter(chunk);
+            match u8::from_str_radix(&chunk, 16) {
+                Ok(byte) => self.bytes.push(byte),
+                Err(_) => self.reject(chunk),
+            }
+        }
+    }
+
+    /// Number the way a compiler or an assembler reads it
+    fn number(&mut self, token: &str) {
+        let token = token.trim();
+        if token.is_empty() {
+            return;
+        }
+
+        match parse_number(token).and_then(|value| u8::try_from(value).ok()) {
+            Some(byte) => self.bytes.push(byte),
+            None => self.reject(token.to_string()),
+        }
+    }
+
+    fn reject(&mut self, token: String) {
+        // Invalid tokens still take up a position, so that later positions
+        // line up with what was typed
+        self.invalid
+            .push((self.bytes.len() + self.invalid.len(), token));
+    }
+}
+
+/// Whether text that is not hex can be base64, so that a typo in hex is
+/// reported instead of being decoded as base64. `A9BG` is a typo, `qb0gKLo=`
+/// is base64.
+fn looks_like_base64(text: &str) -> bool {
+    let compact: String = text.split_whitespace().collect();
+    let is_alphabet = compact
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '='));
+    let has_non_hex = compact.chars().any(|c| {
+        matches!(c, '+' | '/' | '=') || (c.is_ascii_alphabetic() && !c.is_ascii_hexdigit())
+    });
+    let has_upper = compact.chars().any(|c| c.is_ascii_uppercase());
+    let has_lower = compact.chars().any(|c| c.is_ascii_lowercase());
+    let has_symbol = compact.contains(['+', '/', '=']);
+
+    !compact.is_empty()
+        && compact.len().is_multiple_of(4)
+        && is_alphabet
+        && has_non_hex
+        && (has_symbol || (has_upper && has_lower))
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let compact: String = text.split_whitespace().collect();
+    STANDARD.decode(compact).ok()
+}
+
+fn strip_c_comments(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    loop {
+        let start = match (rest.find("/*"), rest.find("//")) {
+            (Some(block), Some(line)) => block.min(line),
+            (Some(start), None) | (None, Some(start)) => start,
+            (None, None) => {
+                result.push_str(rest);
+                return result;
+            }
+        };
+
+        result.push_str(&rest[..start]);
+        let end = if rest[start..].starts_with("/*") {
+            "*/"
+        } else {
+            "\n"
+        };
+        rest = rest[start..].split_once(end).map_or("", |(_, after)| after);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXPECTED: [u8; 3] = [0xa9, 0xbd, 0x60];
+
+    #[test]
+    fn test_shapes() {
+        for text in [
+            "A9 BD 60",
+            "a9bd60",
+            "$A9,$BD,$60",
+            "0xA9, 0xBD, 0x60",
+            "0A9h 0BDh 60h",
+            "unsigned char code[] = { 0xa9, 189, 0x60 }; // LDA #$BD, RTS",
+            "const uint8_t code[3] = {\n  0xA9, /* LDA */ 0xBD,\n  0x60\n};",
+            ".byte $A9, 189\n.byte %01100000 ; RTS",
+            "!byte $a9,$bd,$60",
+            "C000: A9 BD\nC002: 60",
+            ">C:c000  a9 bd 60",
+            "base64:qb1g",
+        ] {
+            assert_eq!(parse_hex_text(text), Ok(EXPECTED.to_vec()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(
+            parse_hex_text("qb0gKLo="),
+            Ok(vec![0xa9, 0xbd, 0x20, 0x28, 0xba])
+        );
+        assert!(parse_hex_text("base64:!!").is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_text_is_hex() {
+        assert_eq!(parse_hex_text("abcdef"), Ok(vec![0xab, 0xcd, 0xef]));
+        assert_eq!(parse_hex_text("A9bd"), Ok(vec![0xa9, 0xbd]));
+        assert_eq!(parse_hex_text("0A9h 0BDh"), Ok(vec![0xa9, 0xbd]));
+        assert!(parse_hex_text("A9BG").is_err());
+    }
+
+    #[test]
+    fn test_whitespace_is_ignored() {
+        assert_eq!(parse_hex_text("A 9 B D"), Ok(vec![0xa9, 0xbd]));
+        assert_eq!(parse_hex_text("A9\nBD"), Ok(vec![0xa9, 0xbd]));
+        assert_eq!(parse_hex_text("A9B"), Ok(vec![0xa9, 0x0b]));
+        assert_eq!(parse_hex_text("A9B $60"), Ok(vec![0xa9, 0x0b, 0x60]));
+    }
+
+    #[test]
+    fn test_invalid_positions() {
+        assert_eq!(
+            parse_hex_text("abcdefgh"),
+            Err(HexTextError {
+                invalid: vec![(3, "gh".into())]
+            })
+        );
+        assert_eq!(
+            parse_hex_text(".byte 1, 256, $02"),
+            Err(HexTextError {
+                invalid: vec![(1, "256".into())]
+            })
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{disassemble_image, Instruction, MemoryImage};
+
+/// How bytes are shown in the text column next to the hex
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    /// Commodore character set in its uppercase mode, graphics characters
+    /// show as dots
+    Petscii,
+}
+
+impl Charset {
+    pub fn render(self, byte: u8) -> char {
+        match self {
+            Charset::Ascii if byte.is_ascii_graphic() || byte == b' ' => byte as char,
+            Charset::Petscii => match byte {
+                0x20..=0x5b | 0x5d => byte as char,
+                0x5c => '£',
+                0x5e => '↑',
+                0x5f => '←',
+                // Shifted space
+                0xa0 => ' ',
+                _ => '.',
+            },
+            _ => '.',
+        }
+    }
+
+    pub fn render_all(self, bytes: &[u8]) -> String {
+        bytes.iter().map(|&byte| self.render(byte)).collect()
+    }
+}
+
+/// Bytes as space separated hex pairs, `columns` bytes to a row
+pub fn hex_rows(bytes: &[u8], columns: usize) -> Vec<String> {
+    bytes
+        .iter()
+        .map(|byte| format!("{:0>2X}", byte))
+        .collect::<Vec<_>>()
+        .chunks(columns)
+        .map(|chunk| chunk.join(" "))
+        .collect()
+}
+
+/// `xxd` style dump of the image. Every stretch of memory starts a row of
+/// its own, so addresses stay aligned to where the bytes are loaded.
+pub fn hexdump(image: &MemoryImage, columns: usize, charset: Charset) -> String {
+    let mut text = String::new();
+
+    for (start, bytes) in image.runs() {
+        for (index, row) in hex_rows(&bytes, columns).into_iter().enumerate() {
+            let offset = index * columns;
+            let chunk = &bytes[offset..(offset + columns).min(bytes.len())];
+            text.push_str(&format!(
+                "{:08X}: {: <width$}  {}\n",
+                start + offset,
+                row,
+                charset.render_all(chunk),
+                width = columns * 3 - 1
+            ));
+        }
+    }
+
+    text
+}
+
+/// Row of a listing that has the hex and text next to the disassembly
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct HexLine {
+    pub offset: usize,
+    pub bytes: String,
+    pub text: String,
+    pub label: Option<String>,
+    /// Data rows have no instruction
+    pub instruction: Option<Instruction>,
+}
+
+/// Hex and text next to the disassembly. Every instruction gets a row of
+/// its own, so rows line up with instruction boundaries. Undecodable bytes
+/// and marked data are grouped up to `columns` to a row instead, which keeps
+/// data heavy regions readable.
+pub fn hex_listing(image: &MemoryImage, columns: usize, charset: Charset) -> Vec<HexLine> {
+    let mut lines: Vec<HexLine> = vec![];
+    // Raw bytes of the data row that is being grouped
+    let mut data: Vec<u8> = vec![];
+
+    for instruction in disassemble_image(image) {
+        let raw: Vec<u8> = instruction
+            .bytes
+            .split_whitespace()
+            .filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+            .collect();
+
+        if instruction.operation != "???" {
+            data.clear();
+            lines.push(HexLine {
+                offset: instruction.offset,
+                bytes: instruction.bytes.clone(),
+                text: charset.render_all(&raw),
+                label: instruction.label.clone(),
+                instruction: Some(instruction),
+            });
+            continue;
+        }
+
+        let continues = match lines.last() {
+            Some(last) => {
+                last.instruction.is_none()
+                    && last.offset + data.len() == instruction.offset
+                    && data.len() < columns
+                    && !instruction.offset.is_multiple_of(columns)
+                    && instruction.label.is_none()
+            }
+            None => false,
+        };
+
+        if continues {
+            data.extend(&raw);
+            let last = lines.last_mut().expect("checked above");
+            last.bytes = hex_rows(&data, columns).concat();
+            last.text = charset.render_all(&data);
+        } else {
+            data = raw;
+            lines.push(HexLine {
+                offset: instruction.offset,
+                bytes: instruction.bytes,
+                text: charset.render_all(&data),
+                label: instruction.label,
+                instruction: None,
+            });
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Segment;
+
+    use super::*;
+
+    #[test]
+    fn test_charsets() {
+        let bytes = b"HI!\x5c\x00\xc1";
+
+        assert_eq!(Charset::Ascii.render_all(bytes), "HI!\\..");
+        assert_eq!(Charset::Petscii.render_all(bytes), "HI!£..");
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let image = MemoryImage::new()
+            .with_segment(Segment::new("code", 0xc000, b"HELLO".to_vec()))
+            .with_segment(Segment::new("data", 0xd000, vec![0x00]));
+
+        assert_eq!(
+            hexdump(&image, 4, Charset::Ascii),
+            "0000C000: 48 45 4C 4C  HELL\n\
+             0000C004: 4F           O\n\
+             0000D000: 00           .\n"
+        );
+    }
+
+    #[test]
+    fn test_rows_follow_instructions() {
+        // LDA #$41, then data that does not decode, then RTS
+        let mut bytes = vec![0xa9, 0x41];
+        bytes.extend([0x02; 7]);
+        bytes.push(0x60);
+        let image = MemoryImage::new().with_segment(Segment::new("code", 0x1000, bytes));
+
+        let lines = hex_listing(&image, 4, Charset::Ascii);
+        let rows: Vec<(usize, &str, bool)> = lines
+            .iter()
+            .map(|line| (line.offset, line.bytes.as_str(), line.instruction.is_some()))
+            .collect();
+
+        assert_eq!(
+            rows,
+            [
+                (0x1000, "A9 41", true),
+                (0x1002, "02 02", false),
+                (0x1004, "02 02 02 02", false),
+                (0x1008, "02", false),
+                (0x1009, "60", true),
+            ]
+        );
+        assert_eq!(lines[0].text, ".A");
+    }
+}
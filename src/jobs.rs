@@ -0,0 +1,533 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{watch, Semaphore};
+
+use crate::{
+    analysis::{control_flow_of, cross_references_of, statistics_of},
+    decode_image_iter,
+    disassemble::annotate,
+    instruction_changes, Change, Decoded, ErrorBody, Instruction, MemoryImage,
+};
+
+/// Finished jobs that are kept for clients to fetch, the oldest are dropped
+/// first
+const KEPT_JOBS: usize = 1000;
+
+/// Bytes of JSON that the results of the kept jobs may have together
+const KEPT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Bytes the inputs of a diff may have together. Every instruction of both
+/// is held while they are compared, several hundred times the bytes.
+const MAX_DIFF_BYTES: usize = 512 * 1024;
+
+/// Jobs that may wait for a worker at once, each of them holds its inputs
+const MAX_QUEUED: usize = 64;
+
+/// Instructions decoded between progress reports
+const REPORT_EVERY: usize = 4096;
+
+/// Analysis that runs in the background
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Enum)]
+pub enum JobKind {
+    /// Instructions of the whole input, as from `/json/structured`
+    Disassembly,
+    Statistics,
+    CrossReferences,
+    ControlFlow,
+    /// Instructions that were removed from the input or added in the other
+    /// one, compared without their offsets
+    Diff,
+}
+
+impl JobKind {
+    /// Inputs the analysis takes
+    pub fn inputs(self) -> usize {
+        match self {
+            JobKind::Diff => 2,
+            _ => 1,
+        }
+    }
+
+    /// Bytes the inputs may have together, when the analysis needs a lot
+    /// more memory than they take
+    pub fn max_bytes(self) -> Option<usize> {
+        match self {
+            JobKind::Diff => Some(MAX_DIFF_BYTES),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Enum)]
+pub enum JobStatus {
+    /// Waiting for a free worker
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+/// State of a job as clients see it
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Object)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    /// From 0 to 1
+    pub progress: f64,
+    /// Output of the analysis once the job is done
+    #[serde(default)]
+    pub result: Option<Value>,
+    #[serde(default)]
+    pub error: Option<ErrorBody>,
+}
+
+/// The job was cancelled, the work should stop
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+/// As many jobs as allowed already wait for a worker
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueueFull;
+
+/// How the work of a job tells how far it got, and learns that nobody wants
+/// the result anymore
+#[derive(Debug, Clone)]
+pub struct Progress {
+    /// Jobs are pruned in the order they were submitted in
+    order: u64,
+    state: Arc<watch::Sender<Job>>,
+    cancelled: Arc<AtomicBool>,
+    /// Size of the result as JSON, once there is one
+    bytes: Arc<AtomicUsize>,
+}
+
+impl Progress {
+    pub fn report(&self, progress: f64) -> Result<(), Cancelled> {
+        self.check()?;
+        self.state.send_modify(|job| job.progress = progress);
+        Ok(())
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.cancelled.load(Ordering::Relaxed) {
+            true => Err(Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    /// Changes the job unless it already finished, so that a cancelled job
+    /// stays cancelled whatever its work does afterwards
+    fn update(&self, change: impl FnOnce(&mut Job)) {
+        self.state
+            .send_if_modified(|job| match job.status.is_finished() {
+                true => false,
+                false => {
+                    change(job);
+                    true
+                }
+            });
+    }
+}
+
+/// Analyses that take too long for a request, run by a limited number of
+/// workers on the blocking threads of the runtime
+#[derive(Debug)]
+pub struct Jobs {
+    workers: Arc<Semaphore>,
+    max_queued: usize,
+    next: AtomicU64,
+    jobs: Arc<Mutex<HashMap<String, Progress>>>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, usize::from))
+    }
+}
+
+impl Jobs {
+    /// Runs at most `workers` jobs at once, the rest wait in the queue
+    pub fn new(workers: usize) -> Self {
+        Jobs {
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            max_queued: MAX_QUEUED,
+            next: AtomicU64::new(1),
+            jobs: Arc::default(),
+        }
+    }
+
+    /// Jobs that may wait for a worker, later ones are turned away
+    pub fn with_max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued;
+        self
+    }
+
+    /// Queues the analysis of the images, there have to be as many as the
+    /// kind takes. The ID is random, so that clients can't guess the jobs of
+    /// others.
+    pub fn submit(&self, kind: JobKind, images: Vec<MemoryImage>) -> Result<Job, QueueFull> {
+        assert_eq!(images.len(), kind.inputs(), "inputs of {:?}", kind);
+
+        let id = format!("{:032x}", rand::random::<u128>());
+        let job = Job {
+            id: id.clone(),
+            kind,
+            status: JobStatus::Queued,
+            progress: 0.0,
+            result: None,
+            error: None,
+        };
+        let progress = Progress {
+            order: self.next.fetch_add(1, Ordering::Relaxed),
+            state: Arc::new(watch::channel(job.clone()).0),
+            cancelled: Arc::default(),
+            bytes: Arc::default(),
+        };
+        {
+            let mut jobs = self.lock();
+            let queued = jobs
+                .values()
+                .filter(|progress| progress.state.borrow().status == JobStatus::Queued)
+                .count();
+            if queued >= self.max_queued {
+                return Err(QueueFull);
+            }
+            jobs.insert(id.clone(), progress.clone());
+        }
+        prune(&self.jobs, &id, KEPT_JOBS, KEPT_BYTES);
+
+        let workers = self.workers.clone();
+        let jobs = self.jobs.clone();
+        tokio::spawn(async move {
+            // A job that is cancelled while it waits lets go of its inputs
+            // right away instead of when a worker is free
+            let mut state = progress.state.subscribe();
+            let permit = tokio::select! {
+                biased;
+                _ = async { drop(state.wait_for(|job| job.status.is_finished()).await) } => {
+                    return;
+                }
+                permit = workers.acquire_owned() => permit.expect("never closed"),
+            };
+            if progress.check().is_err() {
+                return;
+            }
+            progress.update(|job| job.status = JobStatus::Running);
+
+            // The worker is only free again once the work has stopped, not
+            // when it was cancelled
+            let work = progress.clone();
+            let outcome = tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                run(kind, &images, &work)
+            })
+            .await;
+
+            progress.update(|job| match outcome {
+                Ok(Ok(result)) => {
+                    progress.bytes.store(json_size(&result), Ordering::Relaxed);
+                    job.status = JobStatus::Done;
+                    job.progress = 1.0;
+                    job.result = Some(result);
+                }
+                Ok(Err(Cancelled)) => job.status = JobStatus::Cancelled,
+                Err(err) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(ErrorBody::new(
+                        "internal_error",
+                        format!("The analysis failed: {}", err),
+                    ));
+                }
+            });
+            prune(&jobs, &id, KEPT_JOBS, KEPT_BYTES);
+        });
+
+        Ok(job)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        Some(self.progress(id)?.state.borrow().clone())
+    }
+
+    /// Receives every change of the job until it finishes
+    pub fn subscribe(&self, id: &str) -> Option<watch::Receiver<Job>> {
+        Some(self.progress(id)?.state.subscribe())
+    }
+
+    /// Stops the job if it has not finished yet, and returns its state
+    pub fn cancel(&self, id: &str) -> Option<Job> {
+        let progress = self.progress(id)?;
+        progress.cancelled.store(true, Ordering::Relaxed);
+        progress.update(|job| job.status = JobStatus::Cancelled);
+        let job = progress.state.borrow().clone();
+        Some(job)
+    }
+
+    fn progress(&self, id: &str) -> Option<Progress> {
+        self.lock().get(id).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Progress>> {
+        self.jobs.lock().expect("jobs lock is never poisoned")
+    }
+}
+
+/// Drops the oldest finished jobs beyond `max_jobs`, or beyond `max_bytes` of
+/// results. The job `keep` stays, so that a client can still fetch the result
+/// that just came in even when it alone is over.
+fn prune(jobs: &Mutex<HashMap<String, Progress>>, keep: &str, max_jobs: usize, max_bytes: usize) {
+    let mut jobs = jobs.lock().expect("jobs lock is never poisoned");
+    let mut finished: Vec<(&String, &Progress)> = jobs
+        .iter()
+        .filter(|(id, progress)| *id != keep && progress.state.borrow().status.is_finished())
+        .collect();
+    finished.sort_by_key(|(_, progress)| std::cmp::Reverse(progress.order));
+
+    let (mut kept, mut bytes) = (0, 0);
+    let mut dropped = vec![];
+    for (id, progress) in finished {
+        kept += 1;
+        bytes += progress.bytes.load(Ordering::Relaxed);
+        if kept > max_jobs || bytes > max_bytes {
+            dropped.push(id.clone());
+        }
+    }
+    for id in dropped {
+        jobs.remove(&id);
+    }
+}
+
+fn run(kind: JobKind, images: &[MemoryImage], progress: &Progress) -> Result<Value, Cancelled> {
+    let image = &images[0];
+    let value = match kind {
+        JobKind::Disassembly => to_value(disassemble(image, progress, 0.0, 1.0)?),
+        JobKind::Statistics => to_value(statistics_of(&decode(image, progress)?)),
+        JobKind::CrossReferences => to_value(cross_references_of(&decode(image, progress)?)),
+        JobKind::ControlFlow => to_value(control_flow_of(image, &decode(image, progress)?)),
+        JobKind::Diff => {
+            let old = disassemble(image, progress, 0.0, 0.4)?;
+            let new = disassemble(&images[1], progress, 0.4, 0.8)?;
+            let changes: Vec<Change<&Instruction>> = instruction_changes(&old, &new);
+            to_value(changes)
+        }
+    };
+    progress.check()?;
+    Ok(value)
+}
+
+/// Decodes the image for the analyses, which take a lot less time than the
+/// decoding once it is done
+fn decode(image: &MemoryImage, progress: &Progress) -> Result<Vec<Decoded>, Cancelled> {
+    let decoded = each_decoded(image, progress, 0.0, 0.9, |decoded| decoded)?;
+    progress.check()?;
+    Ok(decoded)
+}
+
+/// Disassembles the image while reporting progress from `from` to `to`
+fn disassemble(
+    image: &MemoryImage,
+    progress: &Progress,
+    from: f64,
+    to: f64,
+) -> Result<Vec<Instruction>, Cancelled> {
+    each_decoded(image, progress, from, to, |decoded| {
+        annotate(decoded, image)
+    })
+}
+
+/// Decodes the image into what `each` makes of every instruction, while
+/// reporting progress from `from` to `to`
+fn each_decoded<T>(
+    image: &MemoryImage,
+    progress: &Progress,
+    from: f64,
+    to: f64,
+    mut each: impl FnMut(Decoded) -> T,
+) -> Result<Vec<T>, Cancelled> {
+    let total: usize = image
+        .segments()
+        .iter()
+        .map(|segment| segment.bytes.len())
+        .sum();
+    let mut decoded_bytes = 0;
+    let mut results = vec![];
+
+    for (index, decoded) in decode_image_iter(image).enumerate() {
+        if index % REPORT_EVERY == 0 {
+            progress.report(from + (to - from) * decoded_bytes as f64 / total.max(1) as f64)?;
+        }
+        decoded_bytes += decoded.len();
+        results.push(each(decoded));
+    }
+
+    Ok(results)
+}
+
+fn to_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).expect("analyses always serialize")
+}
+
+/// Length of the value as JSON, counted without writing it anywhere
+fn json_size(value: &Value) -> usize {
+    struct Counter(usize);
+
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, value).expect("values always serialize");
+    counter.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::load_at;
+
+    async fn finished(jobs: &Jobs, id: &str) -> Job {
+        let mut receiver = jobs.subscribe(id).unwrap();
+        let job = receiver
+            .wait_for(|job| job.status.is_finished())
+            .await
+            .unwrap()
+            .clone();
+        job
+    }
+
+    #[tokio::test]
+    async fn test_jobs() {
+        let jobs = Jobs::new(1);
+        let old = load_at(&[0xa9, 0x01, 0xea, 0x60], 0x1000).unwrap();
+        let new = load_at(&[0xa9, 0x02, 0xea, 0x60], 0x1000).unwrap();
+
+        let job = jobs.submit(JobKind::Diff, vec![old.clone(), new]).unwrap();
+        assert_eq!(job.status, JobStatus::Queued);
+        let job = finished(&jobs, &job.id).await;
+        assert_eq!(job.status, JobStatus::Done);
+        let changes: Vec<Change<Instruction>> =
+            serde_json::from_value(job.result.unwrap()).unwrap();
+        assert!(
+            matches!(&changes[..], [Change::Delete(old), Change::Insert(new)]
+            if old.address == "#$01" && new.address == "#$02")
+        );
+
+        let job = jobs.submit(JobKind::Statistics, vec![old]).unwrap();
+        let job = finished(&jobs, &job.id).await;
+        assert_eq!(job.result.unwrap()["instructions"], 3);
+        assert_eq!(jobs.get("0"), None);
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let jobs = Jobs::new(1).with_max_queued(2);
+        let image = load_at(&[0xea; 0x10000], 0).unwrap();
+
+        // Both wait for the only worker, which the first one holds, and fill
+        // the queue
+        let first = jobs
+            .submit(JobKind::Disassembly, vec![image.clone()])
+            .unwrap();
+        let second = jobs
+            .submit(JobKind::Disassembly, vec![image.clone()])
+            .unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(
+            jobs.submit(JobKind::Disassembly, vec![image.clone()]),
+            Err(QueueFull)
+        );
+        let cancelled = jobs.cancel(&second.id).unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        jobs.submit(JobKind::Disassembly, vec![image]).unwrap();
+
+        assert_eq!(finished(&jobs, &first.id).await.status, JobStatus::Done);
+        assert_eq!(
+            finished(&jobs, &second.id).await.status,
+            JobStatus::Cancelled
+        );
+        assert_eq!(jobs.get(&second.id).unwrap().result, None);
+    }
+
+    #[test]
+    fn test_analyses_stop() {
+        let images = [load_at(&[0xea; 0x100], 0).unwrap()];
+        let job = Job {
+            id: "1".into(),
+            kind: JobKind::Statistics,
+            status: JobStatus::Running,
+            progress: 0.0,
+            result: None,
+            error: None,
+        };
+        let progress = Progress {
+            order: 1,
+            state: Arc::new(watch::channel(job).0),
+            cancelled: Arc::new(AtomicBool::new(true)),
+            bytes: Arc::default(),
+        };
+        for kind in [
+            JobKind::Statistics,
+            JobKind::CrossReferences,
+            JobKind::ControlFlow,
+        ] {
+            assert_eq!(run(kind, &images, &progress), Err(Cancelled));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let jobs = Jobs::new(1);
+        let image = load_at(&[0xea; 0x100], 0).unwrap();
+        let mut ids = vec![];
+        for _ in 0..4 {
+            let job = jobs
+                .submit(JobKind::Disassembly, vec![image.clone()])
+                .unwrap();
+            finished(&jobs, &job.id).await;
+            ids.push(job.id);
+        }
+        let bytes = jobs
+            .progress(&ids[0])
+            .unwrap()
+            .bytes
+            .load(Ordering::Relaxed);
+        assert!(bytes > 0);
+
+        // The last one is kept whatever the limits, the next newest fit
+        prune(&jobs.jobs, &ids[3], 3, 2 * bytes);
+        let kept: Vec<bool> = ids.iter().map(|id| jobs.get(id).is_some()).collect();
+        assert_eq!(kept, [false, true, true, true]);
+        prune(&jobs.jobs, &ids[3], 1, usize::MAX);
+        let kept: Vec<bool> = ids.iter().map(|id| jobs.get(id).is_some()).collect();
+        assert_eq!(kept, [false, false, true, true]);
+    }
+}
@@ -1,7 +1,56 @@
+mod analysis;
 mod api;
+mod assemble;
+mod diff;
 mod disassemble;
+mod formats;
 mod frontend;
+mod health;
+mod hex;
+mod hexdump;
+mod jobs;
+mod limits;
+mod listing;
+mod memory;
+mod metrics;
+mod negotiation;
+mod opcodes;
+mod platform;
+mod project;
+mod search;
+mod session;
+mod source;
+mod store;
+mod symbols;
 
-pub use api::Api;
-pub use disassemble::{disassemble, Instruction};
+pub use analysis::{
+    control_flow, control_flow_dot, cross_references, statistics, BasicBlock, CrossReference,
+    ReferenceKind, Statistics,
+};
+pub use api::{Api, ErrorBody, StructuredDisassembly};
+pub use assemble::{assemble, assemble_instruction, parse_number, AssembleError, Assembled};
+pub use diff::{diff, instruction_changes, Change};
+pub use disassemble::{
+    decode_image, decode_image_from, decode_image_iter, disassemble, disassemble_image,
+    disassemble_image_iter, disassemble_range, instruction_at, into_instructions, Decoded, Decoder,
+    Instruction, Instructions,
+};
+pub use formats::{load, load_as, load_at, Format, FormatError};
 pub use frontend::Frontend;
+pub use health::Health;
+pub use hex::{parse_hex_text, HexTextError};
+pub use hexdump::{hex_listing, hex_rows, hexdump, Charset, HexLine};
+pub use jobs::{Job, JobKind, JobStatus, Jobs, QueueFull};
+pub use limits::{Limits, LimitsEndpoint};
+pub use listing::{csv_field, HexStyle, ListingFormat};
+pub use memory::{EntryPoint, MemoryImage, Property, Segment};
+pub use metrics::{Metrics, MetricsEndpoint};
+pub use negotiation::{DisassembleEndpoint, Representation};
+pub use opcodes::{AddressMode, Cpu, Operation};
+pub use platform::Platform;
+pub use project::{sha256, Project, ProjectError};
+pub use search::{parse_pattern, search_bytes, search_instructions};
+pub use session::{Edit, Session, Sessions, Update};
+pub use source::{assembly_source, Dialect};
+pub use store::Store;
+pub use symbols::{parse_symbols, SymbolError};
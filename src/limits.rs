@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use poem::{
+    error::ReadBodyError, http::StatusCode, web::headers::HeaderMapExt, Endpoint, IntoResponse,
+    Middleware, Request, Response, Result,
+};
+
+use crate::ErrorBody;
+
+/// Bounds on what a request may cost, so that a small server stays up when
+/// someone sends it something enormous
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest request body, and largest input once hex or base64 is decoded
+    pub max_payload: usize,
+    /// Time a request may take, reading its body included, before it is
+    /// answered with 503
+    pub timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_payload: 4 * 1024 * 1024,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_payload(mut self, max_payload: usize) -> Self {
+        self.max_payload = max_payload;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for Limits {
+    type Output = LimitsEndpoint<E>;
+
+    fn transform(&self, inner: E) -> Self::Output {
+        LimitsEndpoint {
+            inner,
+            limits: *self,
+        }
+    }
+}
+
+/// Endpoint that enforces `Limits` on every request before passing it on
+pub struct LimitsEndpoint<E> {
+    inner: E,
+    limits: Limits,
+}
+
+impl<E: Endpoint> Endpoint for LimitsEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let declared = req
+            .headers()
+            .typed_get::<poem::web::headers::ContentLength>()
+            .map(|length| length.0);
+        if declared.is_some_and(|length| length > self.limits.max_payload as u64) {
+            return Ok(too_large(self.limits.max_payload));
+        }
+
+        // Chunked bodies have no length up front, so the body is read here
+        // with the limit instead of trusting the header. A client that sends
+        // it slowly uses up the same time as a slow answer.
+        let respond = async {
+            match req
+                .take_body()
+                .into_bytes_limit(self.limits.max_payload)
+                .await
+            {
+                Ok(bytes) => req.set_body(bytes),
+                Err(ReadBodyError::PayloadTooLarge) => {
+                    return Ok(too_large(self.limits.max_payload))
+                }
+                Err(err) => {
+                    return Ok(error_response(
+                        StatusCode::BAD_REQUEST,
+                        ErrorBody::new("bad_request", err.to_string()),
+                    ))
+                }
+            }
+            self.inner.call(req).await.map(IntoResponse::into_response)
+        };
+
+        match tokio::time::timeout(self.limits.timeout, respond).await {
+            Ok(response) => response,
+            Err(_) => Ok(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorBody::new(
+                    "timeout",
+                    format!(
+                        "The request took longer than {} seconds",
+                        self.limits.timeout.as_secs_f64()
+                    ),
+                ),
+            )),
+        }
+    }
+}
+
+fn too_large(max_payload: usize) -> Response {
+    error_response(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorBody::too_large(max_payload),
+    )
+}
+
+pub(crate) fn error_response(status: StatusCode, body: ErrorBody) -> Response {
+    Response::builder()
+        .status(status)
+        .content_type("application/json")
+        .body(serde_json::to_string(&body).expect("error bodies always serialize"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use poem::{endpoint::make_sync, Body, EndpointExt};
+
+    #[tokio::test]
+    async fn test_slow_body() {
+        let limits = Limits::new().with_timeout(Duration::from_millis(50));
+        let endpoint = make_sync(|_| "done").with(limits);
+
+        // A body that never ends runs into the timeout while it is read
+        let body = Body::from_bytes_stream(futures_util::stream::pending::<
+            Result<Vec<u8>, std::io::Error>,
+        >());
+        let response = endpoint.call(Request::builder().body(body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = endpoint.call(Request::default()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,312 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Instruction, Operation};
+
+/// How numbers in operands are written
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum HexStyle {
+    /// `$FF`, like most 6502 assemblers
+    #[default]
+    Dollar,
+    /// `0xFF`
+    C,
+    /// `0FFh`
+    Intel,
+}
+
+/// Layout of a text listing. The default is the classic layout that
+/// `Instruction` is displayed with.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListingFormat {
+    pub lowercase: bool,
+    pub hex_style: HexStyle,
+    pub show_offsets: bool,
+    pub show_bytes: bool,
+    /// Branches as `*+5` instead of the target address
+    pub relative_branches: bool,
+    pub bytes_width: usize,
+    pub mnemonic_width: usize,
+    /// Room left for the operand of undecodable bytes before their comment
+    pub operand_width: usize,
+    /// Column where comments of annotated lines start
+    pub comment_column: usize,
+    /// ANSI colours by instruction class
+    pub color: bool,
+}
+
+impl Default for ListingFormat {
+    fn default() -> Self {
+        ListingFormat {
+            lowercase: false,
+            hex_style: HexStyle::Dollar,
+            show_offsets: true,
+            show_bytes: true,
+            relative_branches: false,
+            bytes_width: 8,
+            mnemonic_width: 4,
+            operand_width: 15,
+            comment_column: 40,
+            color: false,
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+impl ListingFormat {
+    /// One line for the instruction, without its label or comment
+    pub fn format(&self, instruction: &Instruction) -> String {
+        let (columns, code) = self.columns(instruction);
+        format!("{}{}", columns, self.paint(instruction, &code))
+    }
+
+    /// Same as `format`, followed by the comment of the instruction at the
+    /// comment column
+    pub fn format_commented(&self, instruction: &Instruction) -> String {
+        let (columns, code) = self.columns(instruction);
+        let mut text = format!("{}{}", columns, self.paint(instruction, &code));
+
+        if let Some(comment) = &instruction.comment {
+            // Padding is worked out on the text without escape codes
+            let width = columns.chars().count() + code.chars().count();
+            let padding = self.comment_column.saturating_sub(width);
+            text.push_str(&format!("{}; {}", " ".repeat(padding), comment));
+        }
+
+        text
+    }
+
+    /// Offset and bytes columns, and the mnemonic with its operand
+    fn columns(&self, instruction: &Instruction) -> (String, String) {
+        let mut columns = String::new();
+        if self.show_offsets {
+            columns.push_str(&self.case(&format!("{:04X}   ", instruction.offset)));
+        }
+        if self.show_bytes {
+            columns.push_str(&self.case(&format!(
+                "{: <width$}      ",
+                instruction.bytes,
+                width = self.bytes_width
+            )));
+        }
+
+        let mnemonic = self.case(&instruction.operation);
+        let code = if instruction.address.is_empty() {
+            mnemonic
+        } else if instruction.operation == "???" {
+            // The operand of an undecodable byte is a comment about its value
+            format!(
+                "{: <mnemonic$}{: <operand$}{}",
+                mnemonic,
+                "",
+                instruction.address,
+                mnemonic = self.mnemonic_width,
+                operand = self.operand_width
+            )
+        } else {
+            format!(
+                "{: <width$}{}",
+                mnemonic,
+                self.operand(instruction),
+                width = self.mnemonic_width
+            )
+        };
+
+        (columns, code)
+    }
+
+    fn operand(&self, instruction: &Instruction) -> String {
+        if self.relative_branches && instruction.address.starts_with('$') {
+            let is_branch = Operation::from_mnemonic(&instruction.operation)
+                .is_some_and(|operation| operation.is_branch());
+            let displacement = instruction
+                .bytes
+                .split_whitespace()
+                .nth(1)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok());
+
+            if let (true, Some(displacement)) = (is_branch, displacement) {
+                // Relative to the start of the branch, like `*` in ca65
+                let distance = displacement as i8 as isize + 2;
+                return format!("*{:+}", distance);
+            }
+        }
+
+        // Listings are big, the default layout skips the rewriting
+        if self.hex_style == HexStyle::Dollar && !self.lowercase {
+            return instruction.address.clone();
+        }
+
+        let mut operand = String::new();
+        let mut rest = instruction.address.as_str();
+        while let Some(start) = rest.find('$') {
+            operand.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let digits = after
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(after.len());
+
+            if digits == 0 {
+                operand.push('$');
+            } else {
+                operand.push_str(&self.number(&after[..digits]));
+            }
+            rest = &after[digits..];
+        }
+        operand.push_str(rest);
+
+        if self.lowercase {
+            operand = operand.replace(",X", ",x").replace(",Y", ",y");
+            if operand == "A" {
+                operand = "a".into();
+            }
+        }
+        operand
+    }
+
+    fn number(&self, digits: &str) -> String {
+        let digits = self.case(digits);
+        match self.hex_style {
+            HexStyle::Dollar => format!("${}", digits),
+            HexStyle::C => format!("0x{}", digits),
+            HexStyle::Intel if digits.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                format!("0{}h", digits)
+            }
+            HexStyle::Intel => format!("{}h", digits),
+        }
+    }
+
+    fn case(&self, text: &str) -> String {
+        if self.lowercase {
+            text.to_ascii_lowercase()
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn paint(&self, instruction: &Instruction, code: &str) -> String {
+        if !self.color {
+            return code.to_string();
+        }
+
+        let color = match Operation::from_mnemonic(&instruction.operation) {
+            // Undecodable bytes and marked data
+            None => Some("\x1b[33m"),
+            Some(operation) if operation.is_illegal() => Some("\x1b[31m"),
+            Some(operation)
+                if operation.is_branch()
+                    || operation.is_jump()
+                    || operation.is_return()
+                    || operation.ends_flow() =>
+            {
+                Some("\x1b[36m")
+            }
+            Some(operation)
+                if operation.is_load() || operation.is_store() || operation.is_modify() =>
+            {
+                Some("\x1b[32m")
+            }
+            Some(_) => None,
+        };
+
+        match color {
+            Some(color) => format!("{}{}{}", color, code, RESET),
+            None => code.to_string(),
+        }
+    }
+}
+
+/// Field of a CSV row, quoted when it has to be
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::disassemble;
+
+    use super::*;
+
+    fn lines(format: &ListingFormat, bytes: &[u8]) -> Vec<String> {
+        disassemble(bytes)
+            .iter()
+            .map(|instruction| format.format(instruction))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_matches_display() {
+        let bytes = [0xa9, 0xbd, 0xd0, 0xfc, 0x0a, 0x02, 0x60, 0x41];
+
+        for instruction in disassemble(&bytes) {
+            assert_eq!(
+                ListingFormat::default().format(&instruction),
+                instruction.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let format = ListingFormat {
+            lowercase: true,
+            hex_style: HexStyle::Intel,
+            show_offsets: false,
+            show_bytes: false,
+            relative_branches: true,
+            ..ListingFormat::default()
+        };
+
+        assert_eq!(
+            lines(&format, &[0xbd, 0x00, 0xc0, 0xd0, 0xfb, 0x0a, 0xa5, 0x10]),
+            ["lda 0c000h,x", "bne *-3", "asl a", "lda 10h"]
+        );
+
+        let format = ListingFormat {
+            hex_style: HexStyle::C,
+            show_bytes: false,
+            ..ListingFormat::default()
+        };
+        assert_eq!(lines(&format, &[0x6c, 0xfe, 0xff]), ["0000   JMP (0xFFFE)"]);
+    }
+
+    #[test]
+    fn test_color() {
+        let format = ListingFormat {
+            color: true,
+            show_offsets: false,
+            show_bytes: false,
+            ..ListingFormat::default()
+        };
+
+        assert_eq!(
+            lines(&format, &[0xd0, 0x00, 0x8d, 0x20, 0xd0, 0x02, 0xea]),
+            [
+                "\x1b[36mBNE $0002\x1b[0m",
+                "\x1b[32mSTA $D020\x1b[0m",
+                "\x1b[33m???                ;%00000010\x1b[0m",
+                "NOP",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_commented() {
+        let mut instruction = disassemble(&[0x60]).remove(0);
+        instruction.comment = Some("back to BASIC".into());
+
+        let format = ListingFormat {
+            comment_column: 30,
+            ..ListingFormat::default()
+        };
+        assert_eq!(
+            format.format_commented(&instruction),
+            "0000   60            RTS      ; back to BASIC"
+        );
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::Cpu;
+
+/// A named chunk of bytes that gets loaded at a specific address.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub name: String,
+    pub load_address: usize,
+    pub bytes: Vec<u8>,
+    /// Segments of different banks share the address space but are never
+    /// visible at the same time
+    pub bank: Option<usize>,
+}
+
+impl Segment {
+    pub fn new(name: impl Into<String>, load_address: usize, bytes: Vec<u8>) -> Self {
+        Segment {
+            name: name.into(),
+            load_address,
+            bytes,
+            bank: None,
+        }
+    }
+
+    pub fn with_bank(mut self, bank: usize) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+
+    /// First address after the segment
+    pub fn end(&self) -> usize {
+        self.load_address + self.bytes.len()
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.load_address..self.end()
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.range().contains(&address)
+    }
+}
+
+/// Address where execution is known to start, such as a reset vector or the
+/// init routine of a music driver
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct EntryPoint {
+    pub name: String,
+    pub address: usize,
+}
+
+/// Descriptive header field of a container format, like the title of a tune
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Object)]
+pub struct Property {
+    pub name: String,
+    pub value: String,
+}
+
+/// Sparse address space assembled from segments. Segments may leave gaps
+/// between each other and they may overlap, which is how banked memory is
+/// represented. When segments overlap, lookups and decoding prefer the
+/// segment in the lowest bank, unbanked ones first, then the one that starts
+/// lowest, then the one that was added first.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct MemoryImage {
+    cpu: Cpu,
+    segments: Vec<Segment>,
+    entry_points: Vec<EntryPoint>,
+    labels: BTreeMap<usize, String>,
+    comments: BTreeMap<usize, String>,
+    references: BTreeMap<usize, String>,
+    metadata: Vec<Property>,
+    /// Sorted and non-overlapping ranges that hold data rather than code
+    data: Vec<Range<usize>>,
+    /// Addresses where decoding restarts, like at entry points
+    code_starts: BTreeSet<usize>,
+}
+
+impl MemoryImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_cpu(mut self, cpu: Cpu) -> Self {
+        self.cpu = cpu;
+        self
+    }
+
+    pub fn set_cpu(&mut self, cpu: Cpu) {
+        self.cpu = cpu;
+    }
+
+    pub fn cpu(&self) -> Cpu {
+        self.cpu
+    }
+
+    pub fn with_segment(mut self, segment: Segment) -> Self {
+        self.add_segment(segment);
+        self
+    }
+
+    pub fn add_segment(&mut self, segment: Segment) {
+        self.segments.push(segment);
+    }
+
+    pub fn with_entry_point(mut self, name: impl Into<String>, address: usize) -> Self {
+        self.add_entry_point(name, address);
+        self
+    }
+
+    pub fn add_entry_point(&mut self, name: impl Into<String>, address: usize) {
+        self.entry_points.push(EntryPoint {
+            name: name.into(),
+            address,
+        });
+    }
+
+    pub fn with_label(mut self, address: usize, name: impl Into<String>) -> Self {
+        self.add_label(address, name);
+        self
+    }
+
+    pub fn add_label(&mut self, address: usize, name: impl Into<String>) {
+        self.labels.insert(address, name.into());
+    }
+
+    pub fn remove_label(&mut self, address: usize) {
+        self.labels.remove(&address);
+    }
+
+    pub fn add_comment(&mut self, address: usize, comment: impl Into<String>) {
+        self.comments.insert(address, comment.into());
+    }
+
+    pub fn remove_comment(&mut self, address: usize) {
+        self.comments.remove(&address);
+    }
+
+    /// Symbolic form for the operand that starts at the address
+    pub fn add_reference(&mut self, address: usize, operand: impl Into<String>) {
+        self.references.insert(address, operand.into());
+    }
+
+    /// Marks the range as data, so that it is never decoded as instructions
+    pub fn add_data_range(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        self.data.push(range);
+        self.data.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<usize>> = vec![];
+        for range in self.data.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.data = merged;
+    }
+
+    /// Marks the range as code: it stops being data and decoding restarts at
+    /// its start
+    pub fn add_code_range(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        self.data = self
+            .data
+            .drain(..)
+            .flat_map(|data| {
+                [
+                    data.start..data.end.min(range.start),
+                    data.start.max(range.end)..data.end,
+                ]
+            })
+            .filter(|data| !data.is_empty())
+            .collect();
+        self.code_starts.insert(range.start);
+    }
+
+    pub fn with_property(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.add_property(name, value);
+        self
+    }
+
+    pub fn add_property(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.metadata.push(Property {
+            name: name.into(),
+            value: value.into(),
+        });
+    }
+
+    pub fn entry_points(&self) -> &[EntryPoint] {
+        &self.entry_points
+    }
+
+    pub fn labels(&self) -> &BTreeMap<usize, String> {
+        &self.labels
+    }
+
+    /// Name for an address, explicit labels take precedence over entry points
+    pub fn label(&self, address: usize) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str).or_else(|| {
+            self.entry_points
+                .iter()
+                .find(|entry| entry.address == address)
+                .map(|entry| entry.name.as_str())
+        })
+    }
+
+    pub fn comment(&self, address: usize) -> Option<&str> {
+        self.comments.get(&address).map(String::as_str)
+    }
+
+    pub fn reference(&self, address: usize) -> Option<&str> {
+        self.references.get(&address).map(String::as_str)
+    }
+
+    pub fn data_ranges(&self) -> &[Range<usize>] {
+        &self.data
+    }
+
+    pub fn is_data(&self, address: usize) -> bool {
+        self.data.iter().any(|range| range.contains(&address))
+    }
+
+    pub fn code_starts(&self) -> &BTreeSet<usize> {
+        &self.code_starts
+    }
+
+    pub fn comments(&self) -> &BTreeMap<usize, String> {
+        &self.comments
+    }
+
+    pub fn metadata(&self) -> &[Property] {
+        &self.metadata
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn segment(&self, name: &str) -> Option<&Segment> {
+        self.segments.iter().find(|segment| segment.name == name)
+    }
+
+    pub fn segment_at(&self, address: usize) -> Option<&Segment> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.contains(address))
+            .min_by_key(|segment| (segment.bank, segment.load_address))
+    }
+
+    pub fn read(&self, address: usize) -> Option<u8> {
+        self.segment_at(address)
+            .map(|segment| segment.bytes[address - segment.load_address])
+    }
+
+    /// Changes a byte in the segment that `read` would read it from. A byte
+    /// right after a segment grows that segment, anywhere else a new segment
+    /// is added.
+    pub fn write(&mut self, address: usize, byte: u8) {
+        if let Some(segment) = self
+            .segments
+            .iter_mut()
+            .filter(|segment| segment.contains(address))
+            .min_by_key(|segment| (segment.bank, segment.load_address))
+        {
+            segment.bytes[address - segment.load_address] = byte;
+        } else if let Some(segment) = self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.bank.is_none() && segment.end() == address)
+        {
+            segment.bytes.push(byte);
+        } else {
+            self.add_segment(Segment::new("written", address, vec![byte]));
+        }
+    }
+
+    /// Copy of the image that only has the bytes inside the range. Entry
+    /// points, labels and other annotations are kept as they are.
+    pub fn crop(&self, range: Range<usize>) -> MemoryImage {
+        let segments = self
+            .segments
+            .iter()
+            .filter_map(|segment| {
+                let start = segment.load_address.max(range.start);
+                let end = segment.end().min(range.end);
+                (start < end).then(|| Segment {
+                    name: segment.name.clone(),
+                    load_address: start,
+                    bytes: segment.bytes[start - segment.load_address..end - segment.load_address]
+                        .to_vec(),
+                    bank: segment.bank,
+                })
+            })
+            .collect();
+
+        // Only the bytes in the range are copied, the annotations all are
+        MemoryImage {
+            cpu: self.cpu,
+            segments,
+            entry_points: self.entry_points.clone(),
+            labels: self.labels.clone(),
+            comments: self.comments.clone(),
+            references: self.references.clone(),
+            metadata: self.metadata.clone(),
+            data: self.data.clone(),
+            code_starts: self.code_starts.clone(),
+        }
+    }
+
+    /// Address ranges between the lowest and the highest loaded address that
+    /// are not covered by any segment
+    pub fn gaps(&self) -> Vec<Range<usize>> {
+        let mut gaps = vec![];
+        let mut covered_until: Option<usize> = None;
+
+        for segment in self.sorted_segments() {
+            match covered_until {
+                Some(end) if segment.load_address > end => {
+                    gaps.push(end..segment.load_address);
+                    covered_until = Some(segment.end());
+                }
+                Some(end) => covered_until = Some(end.max(segment.end())),
+                None => covered_until = Some(segment.end()),
+            }
+        }
+
+        gaps
+    }
+
+    /// Contiguous stretches of memory as (start address, bytes) pairs, bank
+    /// by bank. Segments that are directly adjacent and in the same bank are
+    /// joined so that an instruction can continue from one segment into the
+    /// next. Overlapping segments are kept as separate runs, as they are
+    /// alternatives to one another.
+    pub(crate) fn runs(&self) -> Vec<(usize, Cow<'_, [u8]>)> {
+        let mut runs: Vec<(usize, Option<usize>, Cow<[u8]>)> = vec![];
+
+        let mut sorted = self.sorted_segments();
+        sorted.sort_by_key(|segment| segment.bank);
+
+        for segment in sorted {
+            match runs.last_mut() {
+                Some((start, bank, bytes))
+                    if *bank == segment.bank && *start + bytes.len() == segment.load_address =>
+                {
+                    bytes.to_mut().extend_from_slice(&segment.bytes);
+                }
+                _ => runs.push((
+                    segment.load_address,
+                    segment.bank,
+                    Cow::Borrowed(&segment.bytes),
+                )),
+            }
+        }
+
+        runs.into_iter()
+            .map(|(start, _, bytes)| (start, bytes))
+            .collect()
+    }
+
+    fn sorted_segments(&self) -> Vec<&Segment> {
+        let mut sorted: Vec<&Segment> = self
+            .segments
+            .iter()
+            .filter(|segment| !segment.bytes.is_empty())
+            .collect();
+        sorted.sort_by_key(|segment| segment.load_address);
+        sorted
+    }
+}
+
+impl From<&[u8]> for MemoryImage {
+    /// Flat binary loaded at address zero
+    fn from(bytes: &[u8]) -> Self {
+        MemoryImage::new().with_segment(Segment::new("main", 0, bytes.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image() -> MemoryImage {
+        MemoryImage::new()
+            .with_segment(Segment::new("main", 0x0801, vec![0xea; 0x10]))
+            .with_segment(Segment::new("overlay", 0xc000, vec![0x60; 4]))
+            .with_segment(Segment::new("tail", 0x0811, vec![0x00; 2]))
+    }
+
+    #[test]
+    fn test_lookup() {
+        let image = image();
+
+        assert_eq!(image.read(0x0801), Some(0xea));
+        assert_eq!(image.read(0xc003), Some(0x60));
+        assert_eq!(image.read(0xc004), None);
+        assert_eq!(image.segment_at(0x0812).unwrap().name, "tail");
+        assert_eq!(image.segment("overlay").unwrap().end(), 0xc004);
+    }
+
+    #[test]
+    fn test_crop() {
+        let cropped = image().crop(0x0810..0xc002);
+
+        assert_eq!(cropped.segments().len(), 3);
+        assert_eq!(cropped.segments()[0].range(), 0x0810..0x0811);
+        assert_eq!(cropped.segments()[1].range(), 0xc000..0xc002);
+        assert_eq!(cropped.read(0x0801), None);
+        assert!(image().crop(0x2000..0x3000).segments().is_empty());
+    }
+
+    #[test]
+    fn test_write() {
+        let mut image = image();
+        image.write(0x0801, 0xa9);
+        image.write(0xc004, 0x00);
+        image.write(0x1000, 0x01);
+
+        assert_eq!(image.read(0x0801), Some(0xa9));
+        assert_eq!(image.segment("overlay").unwrap().end(), 0xc005);
+        assert_eq!(image.segment_at(0x1000).unwrap().name, "written");
+    }
+
+    #[test]
+    fn test_data_ranges() {
+        let mut image = image();
+        image.add_data_range(0x0805..0x0808);
+        image.add_data_range(0x0807..0x080a);
+        image.add_data_range(0x0810..0x0812);
+        assert_eq!(image.data_ranges(), [0x0805..0x080a, 0x0810..0x0812]);
+
+        image.add_code_range(0x0806..0x0811);
+        assert_eq!(image.data_ranges(), [0x0805..0x0806, 0x0811..0x0812]);
+        assert!(image.is_data(0x0811));
+        assert!(!image.is_data(0x0806));
+        assert!(image.code_starts().contains(&0x0806));
+    }
+
+    #[test]
+    fn test_gaps() {
+        assert_eq!(image().gaps(), vec![0x0813..0xc000]);
+    }
+
+    #[test]
+    fn test_adjacent_segments_are_joined() {
+        let image = image();
+        let runs = image.runs();
+
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].0, 0x0801);
+        assert_eq!(runs[0].1.len(), 0x12);
+        assert_eq!(runs[1].0, 0xc000);
+    }
+
+    #[test]
+    fn test_banks_are_not_joined() {
+        let image = MemoryImage::new()
+            .with_segment(Segment::new("bank 0 low", 0x8000, vec![0; 0x2000]).with_bank(0))
+            .with_segment(Segment::new("bank 1 low", 0x8000, vec![1; 0x2000]).with_bank(1))
+            .with_segment(Segment::new("bank 0 high", 0xa000, vec![0; 0x2000]).with_bank(0));
+        let runs = image.runs();
+
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].1.len(), 0x4000);
+        assert_eq!(runs[0].1[0], 0);
+        assert_eq!(runs[1].1.len(), 0x2000);
+        assert_eq!(runs[1].1[0], 1);
+    }
+
+    #[test]
+    fn test_lowest_bank_is_read() {
+        // Added the other way round, reads still agree with the runs
+        let mut image = MemoryImage::new()
+            .with_segment(Segment::new("bank 1", 0x8000, vec![1; 0x10]).with_bank(1))
+            .with_segment(Segment::new("bank 0", 0x8000, vec![0; 0x10]).with_bank(0));
+        assert_eq!(image.read(0x8000), Some(image.runs()[0].1[0]));
+
+        image.write(0x8000, 2);
+        assert_eq!(image.segment("bank 0").unwrap().bytes[0], 2);
+        assert_eq!(image.segment("bank 1").unwrap().bytes[0], 1);
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use futures_util::TryStreamExt;
+use poem::{
+    error::NotFoundError, http::StatusCode, Body, Endpoint, IntoResponse, Middleware, PathPattern,
+    Request, Response, Result,
+};
+
+/// Upper bounds of the latency histogram in seconds
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Route of requests that no route answered, because none matched or the
+/// limits turned them down first. Paths aren't used, so that scanners can't
+/// make up a series for every path they try.
+const UNROUTED: &str = "none";
+
+/// Methods that get a series of their own, the ones HTTP defines
+const METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Method of requests with any other method, for the same reason as
+/// `UNROUTED`
+const OTHER_METHOD: &str = "other";
+
+#[derive(Debug, Default)]
+struct RouteMetrics {
+    statuses: BTreeMap<u16, u64>,
+    /// Requests per bucket, not yet cumulative
+    buckets: [u64; BUCKETS.len()],
+    seconds: f64,
+    bytes: u64,
+}
+
+/// Request counts, latencies, bytes and errors per method and route, kept
+/// for Prometheus to scrape. Routes are the patterns that matched, such as
+/// `/json/binaries/:param0` for the API, not the paths.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    routes: Arc<Mutex<BTreeMap<(String, String), RouteMetrics>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, method: &str, route: &str, status: StatusCode, seconds: f64, bytes: u64) {
+        let mut routes = self.routes.lock().expect("metrics lock is never poisoned");
+        let metrics = routes
+            .entry((method.to_string(), route.to_string()))
+            .or_default();
+        *metrics.statuses.entry(status.as_u16()).or_default() += 1;
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            metrics.buckets[bucket] += 1;
+        }
+        metrics.seconds += seconds;
+        metrics.bytes += bytes;
+    }
+
+    /// Metrics in the text format of Prometheus
+    pub fn render(&self) -> String {
+        let routes = self.routes.lock().expect("metrics lock is never poisoned");
+        let mut text = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, lines: &dyn Fn(&mut String)| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            lines(&mut text);
+        };
+
+        family(
+            "http_requests_total",
+            "counter",
+            "Requests answered, by status",
+            &|text| {
+                for ((method, route), metrics) in routes.iter() {
+                    for (status, count) in &metrics.statuses {
+                        let _ = writeln!(
+                            text,
+                            "http_requests_total{{{},status=\"{}\"}} {}",
+                            labels(method, route),
+                            status,
+                            count
+                        );
+                    }
+                }
+            },
+        );
+        family(
+            "http_request_errors_total",
+            "counter",
+            "Requests answered with a status of 400 or more",
+            &|text| {
+                for ((method, route), metrics) in routes.iter() {
+                    let errors: u64 = metrics.statuses.range(400..).map(|(_, count)| count).sum();
+                    let _ = writeln!(
+                        text,
+                        "http_request_errors_total{{{}}} {}",
+                        labels(method, route),
+                        errors
+                    );
+                }
+            },
+        );
+        family(
+            "http_request_duration_seconds",
+            "histogram",
+            "Time from receiving a request to answering it",
+            &|text| {
+                for ((method, route), metrics) in routes.iter() {
+                    let labels = labels(method, route);
+                    let count: u64 = metrics.statuses.values().sum();
+                    let mut cumulative = 0;
+                    for (bound, requests) in BUCKETS.iter().zip(metrics.buckets) {
+                        cumulative += requests;
+                        let _ = writeln!(
+                            text,
+                            "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                            labels, bound, cumulative
+                        );
+                    }
+                    let _ = writeln!(
+                        text,
+                        "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                        labels, count
+                    );
+                    let _ = writeln!(
+                        text,
+                        "http_request_duration_seconds_sum{{{}}} {}",
+                        labels, metrics.seconds
+                    );
+                    let _ = writeln!(
+                        text,
+                        "http_request_duration_seconds_count{{{}}} {}",
+                        labels, count
+                    );
+                }
+            },
+        );
+        family(
+            "http_request_bytes_total",
+            "counter",
+            "Bytes of request bodies read",
+            &|text| {
+                for ((method, route), metrics) in routes.iter() {
+                    let _ = writeln!(
+                        text,
+                        "http_request_bytes_total{{{}}} {}",
+                        labels(method, route),
+                        metrics.bytes
+                    );
+                }
+            },
+        );
+        text
+    }
+}
+
+fn labels(method: &str, route: &str) -> String {
+    let route = route.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("method=\"{}\",route=\"{}\"", method, route)
+}
+
+impl<E: Endpoint> Middleware<E> for Metrics {
+    type Output = MetricsEndpoint<E>;
+
+    fn transform(&self, inner: E) -> Self::Output {
+        MetricsEndpoint {
+            inner,
+            metrics: self.clone(),
+        }
+    }
+}
+
+/// Serves the metrics to Prometheus
+impl Endpoint for Metrics {
+    type Output = Response;
+
+    async fn call(&self, _req: Request) -> Result<Self::Output> {
+        Ok(Response::builder()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(self.render()))
+    }
+}
+
+/// Endpoint that records every request it passes on in `Metrics`
+pub struct MetricsEndpoint<E> {
+    inner: E,
+    metrics: Metrics,
+}
+
+impl<E: Endpoint> Endpoint for MetricsEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let start = Instant::now();
+        let method = METHODS
+            .into_iter()
+            .find(|method| *method == req.method().as_str())
+            .unwrap_or(OTHER_METHOD);
+
+        // Counted as the body is read, which works for chunked bodies and
+        // stops where the limits stop reading
+        let bytes = Arc::new(AtomicU64::new(0));
+        let counter = bytes.clone();
+        let body = req
+            .take_body()
+            .into_bytes_stream()
+            .inspect_ok(move |chunk| {
+                counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            });
+        req.set_body(Body::from_bytes_stream(body));
+
+        let result = self.inner.call(req).await.map(IntoResponse::into_response);
+        let (status, pattern) = match &result {
+            Ok(response) => (response.status(), response.data::<PathPattern>()),
+            // A nest still gives its own pattern to paths that nothing in it
+            // matched
+            Err(err) if err.is::<NotFoundError>() => (err.status(), None),
+            Err(err) => (err.status(), err.data::<PathPattern>()),
+        };
+        // Nesting at the root leaves the front page with an empty pattern
+        let route = match pattern {
+            Some(pattern) if pattern.0.is_empty() => "/",
+            Some(pattern) => &pattern.0,
+            None => UNROUTED,
+        };
+        self.metrics.record(
+            method,
+            route,
+            status,
+            start.elapsed().as_secs_f64(),
+            bytes.load(Ordering::Relaxed),
+        );
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use poem::{endpoint::make_sync, http::Method, EndpointExt, Route};
+
+    use super::*;
+    use crate::Limits;
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let metrics = Metrics::new();
+        let route = Route::new()
+            .at("/items/:id", make_sync(|_| "item"))
+            .with(Limits::new().with_max_payload(4))
+            .with(metrics.clone());
+
+        for path in ["/items/1", "/items/2", "/nothing"] {
+            let _ = route.call(Request::builder().uri_str(path).finish()).await;
+        }
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri_str("/items/3")
+            .body(vec![0; 3]);
+        let _ = route.call(request).await;
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri_str("/items/4")
+            .body(vec![0; 5]);
+        let _ = route.call(request).await;
+        let request = Request::builder()
+            .method(Method::from_bytes(b"SCAN").unwrap())
+            .uri_str("/items/5")
+            .finish();
+        let _ = route.call(request).await;
+
+        let text = metrics.render();
+        for line in [
+            r#"http_requests_total{method="GET",route="/items/:id",status="200"} 2"#,
+            r#"http_requests_total{method="GET",route="none",status="404"} 1"#,
+            r#"http_requests_total{method="POST",route="none",status="413"} 1"#,
+            r#"http_request_errors_total{method="GET",route="/items/:id"} 0"#,
+            r#"http_request_errors_total{method="GET",route="none"} 1"#,
+            r#"http_request_duration_seconds_bucket{method="GET",route="/items/:id",le="+Inf"} 2"#,
+            r#"http_request_duration_seconds_count{method="GET",route="/items/:id"} 2"#,
+            r#"http_request_bytes_total{method="POST",route="/items/:id"} 3"#,
+            r#"http_requests_total{method="other",route="/items/:id",status="200"} 1"#,
+        ] {
+            assert!(text.lines().any(|l| l == line), "{} in\n{}", line, text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server() {
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(format!("http://localhost:9999{}", path)).send();
+
+        assert_eq!(get("/healthz").await.unwrap().status(), 200);
+        assert_eq!(get("/readyz").await.unwrap().text().await.unwrap(), "ready");
+        get("/json/binaries/0/at/0").await.unwrap();
+        get("/nothing/here").await.unwrap();
+
+        let response = get("/metrics").await.unwrap();
+        assert!(response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain; version=0.0.4"));
+        let text = response.text().await.unwrap();
+        for series in [
+            r#"http_request_errors_total{method="GET",route="/json/binaries/:param0/at/:param1"}"#,
+            r#"http_requests_total{method="GET",route="none",status="404"}"#,
+        ] {
+            assert!(text.contains(series), "{} in\n{}", series, text);
+        }
+    }
+}
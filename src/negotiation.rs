@@ -0,0 +1,506 @@
+use std::sync::Arc;
+
+use poem::{
+    http::{header, HeaderValue, StatusCode},
+    Body, IntoResponse, Response,
+};
+use poem_openapi::{
+    param::{Header, Query},
+    registry::{MetaMediaType, MetaResponse, MetaResponses, MetaSchemaRef, Registry},
+    types::Type,
+    ApiResponse, OpenApi,
+};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    api::{annotated_image, blocking, Failure},
+    assembly_source, csv_field, disassemble_image,
+    frontend::table_html,
+    limits::error_response,
+    Cpu, Dialect, ErrorBody, Format, Limits, ListingFormat, MemoryImage, Store,
+    StructuredDisassembly,
+};
+
+/// What `/disassemble` can answer with
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Representation {
+    /// Table fragment of the frontend
+    Html,
+    /// Same as `/json/structured`
+    Json,
+    /// Listing with offsets and bytes
+    Text,
+    Csv,
+    Ca65,
+    Acme,
+}
+
+impl Representation {
+    /// Representation named by the `format` parameter
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "html" => Some(Representation::Html),
+            "json" => Some(Representation::Json),
+            "text" | "txt" => Some(Representation::Text),
+            "csv" => Some(Representation::Csv),
+            "asm" | "ca65" => Some(Representation::Ca65),
+            "acme" => Some(Representation::Acme),
+            _ => None,
+        }
+    }
+
+    /// Most preferred representation that an `Accept` header allows, text
+    /// when anything goes. Each representation gets the quality of the most
+    /// specific range that matches it, so `text/plain;q=0, */*` rules text
+    /// out even though `*/*` matches it.
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        let ranges: Vec<(String, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media = parts.next().filter(|media| !media.is_empty())?;
+                let quality = parts
+                    .filter_map(|parameter| parameter.strip_prefix("q="))
+                    .find_map(|quality| quality.parse().ok())
+                    .unwrap_or(1.0);
+                Some((media.to_ascii_lowercase(), quality))
+            })
+            .collect();
+
+        // Ties go to the range that comes first, then to the representation
+        // that comes first here
+        let mut best: Option<(Self, f32, usize)> = None;
+        for representation in [
+            Representation::Text,
+            Representation::Json,
+            Representation::Html,
+            Representation::Csv,
+            Representation::Ca65,
+        ] {
+            let Some((position, quality)) = ranges
+                .iter()
+                .enumerate()
+                .filter_map(|(position, (media, quality))| {
+                    Some((representation.specificity(media)?, position, *quality))
+                })
+                // The first of the most specific ones
+                .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))
+                .map(|(_, position, quality)| (position, quality))
+            else {
+                continue;
+            };
+            let better = best.is_none_or(|(_, best_quality, best_position)| {
+                quality > best_quality || (quality == best_quality && position < best_position)
+            });
+            if quality > 0.0 && better {
+                best = Some((representation, quality, position));
+            }
+        }
+        best.map(|(representation, _, _)| representation)
+    }
+
+    /// How closely the media range names the representation: 2 for its own
+    /// type, 1 for its type with any subtype and 0 for anything
+    fn specificity(self, media: &str) -> Option<u8> {
+        let types: &[&str] = match self {
+            Representation::Html => &["text/html"],
+            Representation::Json => &["application/json"],
+            Representation::Text => &["text/plain"],
+            Representation::Csv => &["text/csv"],
+            Representation::Ca65 | Representation::Acme => &["text/x-asm", "text/x-assembly"],
+        };
+        types
+            .iter()
+            .filter_map(|own| {
+                if media == *own {
+                    Some(2)
+                } else if media.strip_suffix("/*") == own.split('/').next() {
+                    Some(1)
+                } else {
+                    (media == "*/*").then_some(0)
+                }
+            })
+            .max()
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Representation::Html => "text/html; charset=utf-8",
+            Representation::Json => "application/json",
+            Representation::Text => "text/plain; charset=utf-8",
+            Representation::Csv => "text/csv; charset=utf-8",
+            Representation::Ca65 | Representation::Acme => "text/x-asm; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Params {
+    /// Overrides the `Accept` header
+    format: Option<String>,
+    origin: Option<usize>,
+    cpu: Option<Cpu>,
+    /// Container format of the input, not to be confused with `format`
+    input_format: Option<Format>,
+    /// Stored binary to disassemble instead of the body
+    id: Option<String>,
+}
+
+/// Disassembles the request body, whatever its content type, or a stored
+/// binary, in the representation the client asks for
+#[derive(Debug, Default)]
+pub struct DisassembleEndpoint {
+    limits: Limits,
+    store: Arc<Store>,
+}
+
+impl DisassembleEndpoint {
+    pub fn new(limits: Limits) -> Self {
+        DisassembleEndpoint {
+            limits,
+            store: Arc::default(),
+        }
+    }
+
+    /// Shares the store with the API, so that `id` finds uploaded binaries
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = store;
+        self
+    }
+
+    async fn respond(
+        &self,
+        params: Params,
+        accept: Option<String>,
+        body: Body,
+    ) -> Result<Response, Response> {
+        let failed = |failure: Failure| error_response(failure.status(), failure.body());
+
+        let representation = match &params.format {
+            Some(format) => Representation::from_name(format)
+                .ok_or_else(|| failed(Failure::BadRequest(format!("Unknown format {}", format))))?,
+            None => {
+                let accept = accept.as_deref().unwrap_or("*/*");
+                Representation::negotiate(accept).ok_or_else(|| {
+                    error_response(
+                        StatusCode::NOT_ACCEPTABLE,
+                        ErrorBody::new(
+                            "not_acceptable",
+                            "Available are text/html, application/json, text/plain, \
+                             text/csv and text/x-asm",
+                        ),
+                    )
+                })?
+            }
+        };
+
+        let body = body
+            .into_vec()
+            .await
+            .map_err(|err| failed(Failure::BadRequest(err.to_string())))?;
+        let (bytes, project) = match &params.id {
+            Some(_) if !body.is_empty() => {
+                return Err(failed(Failure::BadRequest(
+                    "give either a body or an id".into(),
+                )))
+            }
+            Some(id) => {
+                let bytes = self.store.get(id).ok_or_else(|| {
+                    failed(Failure::NotFound(format!(
+                        "There is no binary with the ID {}",
+                        id
+                    )))
+                })?;
+                (bytes.to_vec(), self.store.project(id))
+            }
+            None if body.len() > self.limits.max_payload => {
+                return Err(failed(Failure::TooLarge(self.limits.max_payload)))
+            }
+            None => (body, None),
+        };
+
+        let text = blocking(move || {
+            let image = annotated_image(
+                &bytes,
+                params.origin,
+                params.cpu,
+                params.input_format,
+                project.as_ref(),
+            )?;
+            Ok(match representation {
+                Representation::Html => table_html(&image)?,
+                Representation::Json => serde_json::to_string(&StructuredDisassembly {
+                    metadata: image.metadata().to_vec(),
+                    instructions: disassemble_image(&image),
+                })
+                .expect("disassemblies always serialize"),
+                Representation::Text => text_listing(&image),
+                Representation::Csv => lines(
+                    std::iter::once("offset,bytes,operation,address,label,comment".to_string())
+                        .chain(disassemble_image(&image).into_iter().map(|instruction| {
+                            [
+                                instruction.offset.to_string(),
+                                instruction.bytes,
+                                instruction.operation,
+                                instruction.address,
+                                instruction.label.unwrap_or_default(),
+                                instruction.comment.unwrap_or_default(),
+                            ]
+                            .iter()
+                            .map(|field| csv_field(field))
+                            .collect::<Vec<String>>()
+                            .join(",")
+                        })),
+                ),
+                Representation::Ca65 => assembly_source(&image, Dialect::Ca65),
+                Representation::Acme => assembly_source(&image, Dialect::Acme),
+            })
+        })
+        .await
+        .map_err(failed)?;
+
+        Ok(Response::builder()
+            .content_type(representation.content_type())
+            .body(text))
+    }
+}
+
+#[OpenApi]
+impl DisassembleEndpoint {
+    /// Disassembles the request body, whatever its content type, or the
+    /// stored binary `id`. The representation is the one `format` names
+    /// (`html`, `json`, `text`, `csv`, `ca65` or `acme`), or else the one the
+    /// `Accept` header prefers. `input_format` is the container format of
+    /// the input.
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip_all)]
+    #[oai(path = "/disassemble", method = "post")]
+    pub async fn disassemble(
+        &self,
+        body: Body,
+        format: Query<Option<String>>,
+        origin: Query<Option<usize>>,
+        cpu: Query<Option<Cpu>>,
+        input_format: Query<Option<Format>>,
+        id: Query<Option<String>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> Negotiated {
+        event!(Level::INFO, "Disassembling with content negotiation");
+        let params = Params {
+            format: format.0,
+            origin: origin.0,
+            cpu: cpu.0,
+            input_format: input_format.0,
+            id: id.0,
+        };
+        let response = self.respond(params, accept.0, body).await;
+        Negotiated(response.unwrap_or_else(|response| response))
+    }
+}
+
+/// Answer of `/disassemble`, in whichever representation was negotiated
+pub struct Negotiated(Response);
+
+impl IntoResponse for Negotiated {
+    fn into_response(self) -> Response {
+        let mut response = self.0;
+        // Caches have to keep the representations apart
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
+
+impl ApiResponse for Negotiated {
+    const BAD_REQUEST_HANDLER: bool = true;
+
+    fn meta() -> MetaResponses {
+        let text = |content_type| MetaMediaType {
+            content_type,
+            schema: String::schema_ref(),
+        };
+        let error = |status, description| MetaResponse {
+            description,
+            status: Some(status),
+            status_range: None,
+            content: vec![MetaMediaType {
+                content_type: "application/json",
+                schema: MetaSchemaRef::Reference("ErrorBody".into()),
+            }],
+            headers: vec![],
+        };
+
+        MetaResponses {
+            responses: vec![
+                MetaResponse {
+                    description: "The disassembly, JSON is the same as from `/json/structured`",
+                    status: Some(200),
+                    status_range: None,
+                    content: vec![
+                        text("text/plain"),
+                        MetaMediaType {
+                            content_type: "application/json",
+                            schema: MetaSchemaRef::Reference("StructuredDisassembly".into()),
+                        },
+                        text("text/html"),
+                        text("text/csv"),
+                        text("text/x-asm"),
+                    ],
+                    headers: vec![],
+                },
+                error(
+                    400,
+                    "The format is unknown, both a body and an id were given, or the input looked \
+                     like a known container format but its header is broken",
+                ),
+                error(404, "There is no stored binary with the ID"),
+                error(406, "The Accept header allows none of the representations"),
+                error(413, "The input is larger than the server accepts"),
+                error(422, "The project of the stored binary does not fit it"),
+                error(500, ""),
+            ],
+        }
+    }
+
+    fn register(registry: &mut Registry) {
+        ErrorBody::register(registry);
+        StructuredDisassembly::register(registry);
+    }
+
+    fn from_parse_request_error(err: poem::Error) -> Self {
+        let failure = Failure::BadRequest(err.to_string());
+        Negotiated(error_response(failure.status(), failure.body()))
+    }
+}
+
+/// Lines of a text response, each ending in a newline
+fn lines(lines: impl Iterator<Item = String>) -> String {
+    lines.map(|line| line + "\n").collect()
+}
+
+/// Same listing as the CLI prints, the metadata as comments up front, labels
+/// on lines of their own and comments in the comment column
+fn text_listing(image: &MemoryImage) -> String {
+    let format = ListingFormat::default();
+    let metadata = image
+        .metadata()
+        .iter()
+        .map(|property| format!("; {}: {}", property.name, property.value));
+    let instructions = disassemble_image(image)
+        .into_iter()
+        .flat_map(|instruction| {
+            let label = instruction
+                .label
+                .as_ref()
+                .map(|label| format!("{}:", label));
+            label
+                .into_iter()
+                .chain([format.format_commented(&instruction)])
+        });
+    lines(metadata.chain(instructions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_at;
+
+    #[test]
+    fn test_negotiate() {
+        for (accept, expected) in [
+            ("*/*", Some(Representation::Text)),
+            (
+                "text/html,application/xhtml+xml,*/*;q=0.8",
+                Some(Representation::Html),
+            ),
+            (
+                "text/csv;q=0.5, application/json",
+                Some(Representation::Json),
+            ),
+            ("text/x-asm", Some(Representation::Ca65)),
+            ("image/png", None),
+            ("text/plain;q=0, text/csv", Some(Representation::Csv)),
+            // Ruling text out leaves the next representation for anything
+            ("text/plain;q=0, */*", Some(Representation::Json)),
+            ("text/*;q=0, */*", Some(Representation::Json)),
+            ("text/*;q=0, text/csv, */*;q=0.5", Some(Representation::Csv)),
+            ("*/*;q=0", None),
+            ("text/*", Some(Representation::Text)),
+            ("application/*", Some(Representation::Json)),
+        ] {
+            assert_eq!(Representation::negotiate(accept), expected, "{}", accept);
+        }
+    }
+
+    #[test]
+    fn test_text_listing() {
+        let mut image = load_at(&[0xa9, 0xbd, 0x60], 0xc000).unwrap();
+        image.add_property("title", "Demo");
+        image.add_label(0xc002, "done");
+        image.add_comment(0xc000, "border colour");
+
+        assert_eq!(
+            text_listing(&image),
+            "; title: Demo\n\
+             C000   A9 BD         LDA #$BD           ; border colour\n\
+             done:\n\
+             C002   60            RTS\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_representations() {
+        let client = reqwest::Client::new();
+        let post = |accept: &str, query: &str| {
+            client
+                .post(format!("http://localhost:9999/disassemble{}", query))
+                .header("Accept", accept)
+                // What `curl --data-binary` sends
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(vec![0xa9, 0xbd, 0x60])
+                .send()
+        };
+
+        let response = post("*/*", "?origin=49152").await.unwrap();
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(response.headers()["Vary"], "accept");
+        assert_eq!(
+            response.text().await.unwrap(),
+            "C000   A9 BD         LDA #$BD\nC002   60            RTS\n"
+        );
+
+        let json = post("application/json", "").await.unwrap();
+        let json = json.json::<StructuredDisassembly>().await.unwrap();
+        assert_eq!(json.instructions[1].operation, "RTS");
+
+        let csv = post("text/csv", "").await.unwrap().text().await.unwrap();
+        assert_eq!(csv.lines().nth(1), Some("0,A9 BD,LDA,#$BD,,"));
+
+        let html = post("text/html", "").await.unwrap().text().await.unwrap();
+        assert!(html.contains("<table"), "{}", html);
+
+        // The parameter wins over the header
+        let acme = post("text/html", "?format=acme").await.unwrap();
+        assert_eq!(acme.headers()["Content-Type"], "text/x-asm; charset=utf-8");
+        assert!(acme.text().await.unwrap().contains("!cpu 6502"));
+
+        let response = post("text/plain;q=0, */*", "").await.unwrap();
+        assert_eq!(response.headers()["Content-Type"], "application/json");
+
+        let response = post("image/png", "").await.unwrap();
+        assert_eq!(response.status(), 406);
+        let response = post("*/*", "?origin=C000").await.unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.headers()["Vary"], "accept");
+        let response = post("*/*", "?format=pdf").await.unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            response.json::<ErrorBody>().await.unwrap().code,
+            "bad_request"
+        );
+    }
+}
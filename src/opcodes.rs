@@ -0,0 +1,651 @@
+use std::{collections::HashMap, fmt::Display, sync::OnceLock};
+
+use poem_openapi::Enum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Operation {
+    ADC,
+    AND,
+    ASL,
+    BCC,
+    BCS,
+    BEQ,
+    BIT,
+    BMI,
+    BNE,
+    BPL,
+    BRK,
+    BVC,
+    BVS,
+    CLC,
+    CLD,
+    CLI,
+    CLV,
+    CMP,
+    CPX,
+    CPY,
+    DEC,
+    DEX,
+    DEY,
+    EOR,
+    INC,
+    INX,
+    INY,
+    JMP,
+    JSR,
+    LDA,
+    LDX,
+    LDY,
+    LSR,
+    NOP,
+    ORA,
+    PHA,
+    PHP,
+    PLA,
+    PLP,
+    ROL,
+    ROR,
+    RTI,
+    RTS,
+    SBC,
+    SEC,
+    SED,
+    SEI,
+    STA,
+    STX,
+    STY,
+    TAX,
+    TAY,
+    TSX,
+    TXA,
+    TXS,
+    TYA,
+
+    // Undocumented NMOS opcodes, named like on masswerk
+    ALR,
+    ANC,
+    ANE,
+    ARR,
+    DCP,
+    ISC,
+    JAM,
+    LAS,
+    LAX,
+    LXA,
+    RLA,
+    RRA,
+    SAX,
+    SBX,
+    SHA,
+    SHX,
+    SHY,
+    SLO,
+    SRE,
+    TAS,
+    USBC,
+
+    // 65C02 additions
+    BRA,
+    PHX,
+    PHY,
+    PLX,
+    PLY,
+    STZ,
+    TRB,
+    TSB,
+
+    Unknown,
+}
+use Operation::*;
+
+impl Operation {
+    pub fn is_branch(&self) -> bool {
+        matches!(self, BCC | BCS | BEQ | BMI | BNE | BPL | BVC | BVS | BRA)
+    }
+
+    pub fn is_jump(&self) -> bool {
+        matches!(self, JMP | JSR)
+    }
+
+    pub fn is_return(&self) -> bool {
+        matches!(self, RTS | RTI)
+    }
+
+    /// Execution never continues to the next instruction
+    pub fn ends_flow(&self) -> bool {
+        matches!(self, JMP | RTS | RTI | BRA | JAM)
+    }
+
+    pub fn is_load(&self) -> bool {
+        matches!(self, LDA | LDX | LDY | LAX | LAS | PLA | PLP | PLX | PLY)
+    }
+
+    pub fn is_store(&self) -> bool {
+        matches!(
+            self,
+            STA | STX | STY | STZ | SAX | SHA | SHX | SHY | TAS | PHA | PHP | PHX | PHY
+        )
+    }
+
+    /// Reads memory and writes the result back to the same address
+    pub fn is_modify(&self) -> bool {
+        matches!(
+            self,
+            ASL | LSR | ROL | ROR | INC | DEC | SLO | RLA | SRE | RRA | DCP | ISC | TSB | TRB
+        )
+    }
+
+    pub fn is_illegal(&self) -> bool {
+        matches!(
+            self,
+            ALR | ANC
+                | ANE
+                | ARR
+                | DCP
+                | ISC
+                | JAM
+                | LAS
+                | LAX
+                | LXA
+                | RLA
+                | RRA
+                | SAX
+                | SBX
+                | SHA
+                | SHX
+                | SHY
+                | SLO
+                | SRE
+                | TAS
+                | USBC
+                | Operation::Unknown
+        )
+    }
+
+    /// Looks up an operation by its mnemonic, case insensitively
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        // Listings look up every line, so the table is only built once
+        static MNEMONICS: OnceLock<HashMap<String, Operation>> = OnceLock::new();
+
+        let mnemonics = MNEMONICS.get_or_init(|| {
+            (0..=255u8)
+                .flat_map(|opcode| {
+                    [Cpu::NmosIllegal, Cpu::Cmos]
+                        .into_iter()
+                        .map(move |cpu| decode_opcode(opcode, cpu).0)
+                })
+                .filter(|operation| *operation != Operation::Unknown)
+                .map(|operation| (operation.to_string(), operation))
+                .collect()
+        });
+        mnemonics.get(&mnemonic.to_ascii_uppercase()).copied()
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if *self == Operation::Unknown {
+            write!(f, "???")
+        } else {
+            write!(f, "{:?}", self)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum AddressMode {
+    Accumulator,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Immediate,
+    Implied,
+    Indirect,
+    XIndirect,
+    IndirectY,
+    Relative,
+    Zeropage,
+    ZeropageX,
+    ZeropageY,
+    // 65C02 only
+    ZeropageIndirect,
+    AbsoluteXIndirect,
+    Unknown,
+}
+use AddressMode::*;
+impl AddressMode {
+    pub(crate) fn format(&self, formatted: &[String], raw: &[u8], offset: usize) -> String {
+        if formatted.len() != self.length() {
+            // Input is faulty and missing bytes
+            return String::from("*Missing operands*");
+        }
+
+        match self {
+            Accumulator => String::from("A"),
+            Absolute => format!("${}{}", formatted[2], formatted[1]),
+            AbsoluteX => format!("${}{},X", formatted[2], formatted[1]),
+            AbsoluteY => format!("${}{},Y", formatted[2], formatted[1]),
+            Immediate => format!("#${}", formatted[1]),
+            Implied => String::new(),
+            Indirect => format!("(${}{})", formatted[2], formatted[1]),
+            AbsoluteXIndirect => format!("(${}{},X)", formatted[2], formatted[1]),
+            XIndirect => format!("(${},X)", formatted[1]),
+            IndirectY => format!("(${}),Y", formatted[1]),
+            Relative => {
+                // MOS-6502 can only handle addresses up to 2^16
+                // For the sake of convenience, this disassembler handles bigger binaries
+                let addr = (offset + 2) as isize + (raw[1] as i8) as isize;
+
+                format!("${:04X}", addr as u16)
+            }
+            Zeropage => format!("${}", formatted[1]),
+            ZeropageX => format!("${},X", formatted[1]),
+            ZeropageY => format!("${},Y", formatted[1]),
+            ZeropageIndirect => format!("(${})", formatted[1]),
+            AddressMode::Unknown => {
+                let is_ascii_symbol = (32..126).contains(&raw[0]);
+                if is_ascii_symbol {
+                    format!(";%{:0>8b} '{}'", raw[0], raw[0] as char)
+                } else {
+                    format!(";%{:0>8b}", raw[0])
+                }
+            }
+        }
+    }
+
+    /// Decorates a symbolic operand the same way `format` decorates a number
+    pub(crate) fn wrap(&self, operand: &str) -> String {
+        match self {
+            Absolute | Relative | Zeropage => operand.to_string(),
+            AbsoluteX | ZeropageX => format!("{},X", operand),
+            AbsoluteY | ZeropageY => format!("{},Y", operand),
+            Immediate => format!("#{}", operand),
+            Indirect | ZeropageIndirect => format!("({})", operand),
+            AbsoluteXIndirect => format!("({},X)", operand),
+            XIndirect => format!("({},X)", operand),
+            IndirectY => format!("({}),Y", operand),
+            Accumulator | Implied | AddressMode::Unknown => unreachable!("mode has no operand"),
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        match self {
+            Accumulator | Implied | AddressMode::Unknown => 1,
+            Immediate | Relative | Zeropage | ZeropageX | ZeropageY | XIndirect | IndirectY
+            | ZeropageIndirect => 2,
+            Absolute | AbsoluteX | AbsoluteY | Indirect | AbsoluteXIndirect => 3,
+        }
+    }
+}
+
+/// Instruction set variants
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize, Enum)]
+pub enum Cpu {
+    /// Documented NMOS 6502 opcodes only
+    #[default]
+    Nmos,
+    /// NMOS 6502 including the undocumented opcodes, as used on the C64
+    NmosIllegal,
+    /// CMOS 65C02 with its additional instructions and address modes
+    Cmos,
+}
+
+pub(crate) fn decode_opcode(value: u8, cpu: Cpu) -> (Operation, AddressMode) {
+    match (decode_documented(value), cpu) {
+        ((Operation::Unknown, _), Cpu::NmosIllegal) => decode_illegal(value),
+        ((Operation::Unknown, _), Cpu::Cmos) => decode_65c02(value),
+        (documented, _) => documented,
+    }
+}
+
+/// Reverse lookup of the opcode tables
+pub(crate) fn encode_opcode(
+    operation: Operation,
+    address_mode: AddressMode,
+    cpu: Cpu,
+) -> Option<u8> {
+    (0..=255u8).find(|&opcode| decode_opcode(opcode, cpu) == (operation, address_mode))
+}
+
+fn decode_documented(value: u8) -> (Operation, AddressMode) {
+    match value {
+        // From https://www.masswerk.at/6502/6502_instruction_set.html
+        // Validated with a binary that contains one of each byte as an instruction
+        0x00 => (BRK, Implied),
+        0x01 => (ORA, XIndirect),
+        0x05 => (ORA, Zeropage),
+        0x06 => (ASL, Zeropage),
+        0x08 => (PHP, Implied),
+        0x09 => (ORA, Immediate),
+        0x0a => (ASL, Accumulator),
+        0x0d => (ORA, Absolute),
+        0x0e => (ASL, Absolute),
+
+        0x10 => (BPL, Relative),
+        0x11 => (ORA, IndirectY),
+        0x15 => (ORA, ZeropageX),
+        0x16 => (ASL, ZeropageX),
+        0x18 => (CLC, Implied),
+        0x19 => (ORA, AbsoluteY),
+        0x1d => (ORA, AbsoluteX),
+        0x1e => (ASL, AbsoluteX),
+
+        0x20 => (JSR, Absolute),
+        0x21 => (AND, XIndirect),
+        0x24 => (BIT, Zeropage),
+        0x25 => (AND, Zeropage),
+        0x26 => (ROL, Zeropage),
+        0x28 => (PLP, Implied),
+        0x29 => (AND, Immediate),
+        0x2a => (ROL, Accumulator),
+        0x2c => (BIT, Absolute),
+        0x2d => (AND, Absolute),
+        0x2e => (ROL, Absolute),
+
+        0x30 => (BMI, Relative),
+        0x31 => (AND, IndirectY),
+        0x35 => (AND, ZeropageX),
+        0x36 => (ROL, ZeropageX),
+        0x38 => (SEC, Implied),
+        0x39 => (AND, AbsoluteY),
+        0x3d => (AND, AbsoluteX),
+        0x3e => (ROL, AbsoluteX),
+
+        0x40 => (RTI, Implied),
+        0x41 => (EOR, XIndirect),
+        0x45 => (EOR, Zeropage),
+        0x46 => (LSR, Zeropage),
+        0x48 => (PHA, Implied),
+        0x49 => (EOR, Immediate),
+        0x4a => (LSR, Accumulator),
+        0x4c => (JMP, Absolute),
+        0x4d => (EOR, Absolute),
+        0x4e => (LSR, Absolute),
+
+        0x50 => (BVC, Relative),
+        0x51 => (EOR, IndirectY),
+        0x55 => (EOR, ZeropageX),
+        0x56 => (LSR, ZeropageX),
+        0x58 => (CLI, Implied),
+        0x59 => (EOR, AbsoluteY),
+        0x5d => (EOR, AbsoluteX),
+        0x5e => (LSR, AbsoluteX),
+
+        0x60 => (RTS, Implied),
+        0x61 => (ADC, XIndirect),
+        0x65 => (ADC, Zeropage),
+        0x66 => (ROR, Zeropage),
+        0x68 => (PLA, Implied),
+        0x69 => (ADC, Immediate),
+        0x6a => (ROR, Accumulator),
+        0x6c => (JMP, Indirect),
+        0x6d => (ADC, Absolute),
+        0x6e => (ROR, Absolute),
+
+        0x70 => (BVS, Relative),
+        0x71 => (ADC, IndirectY),
+        0x75 => (ADC, ZeropageX),
+        0x76 => (ROR, ZeropageX),
+        0x78 => (SEI, Implied),
+        0x79 => (ADC, AbsoluteY),
+        0x7d => (ADC, AbsoluteX),
+        0x7e => (ROR, AbsoluteX),
+
+        0x81 => (STA, XIndirect),
+        0x84 => (STY, Zeropage),
+        0x85 => (STA, Zeropage),
+        0x86 => (STX, Zeropage),
+        0x88 => (DEY, Implied),
+        0x8a => (TXA, Implied),
+        0x8c => (STY, Absolute),
+        0x8d => (STA, Absolute),
+        0x8e => (STX, Absolute),
+
+        0x90 => (BCC, Relative),
+        0x91 => (STA, IndirectY),
+        0x94 => (STY, ZeropageX),
+        0x95 => (STA, ZeropageX),
+        0x96 => (STX, ZeropageY),
+        0x98 => (TYA, Implied),
+        0x99 => (STA, AbsoluteY),
+        0x9a => (TXS, Implied),
+        0x9d => (STA, AbsoluteX),
+
+        0xa0 => (LDY, Immediate),
+        0xa1 => (LDA, XIndirect),
+        0xa2 => (LDX, Immediate),
+        0xa4 => (LDY, Zeropage),
+        0xa5 => (LDA, Zeropage),
+        0xa6 => (LDX, Zeropage),
+        0xa8 => (TAY, Implied),
+        0xa9 => (LDA, Immediate),
+        0xaa => (TAX, Implied),
+        0xac => (LDY, Absolute),
+        0xad => (LDA, Absolute),
+        0xae => (LDX, Absolute),
+
+        0xb0 => (BCS, Relative),
+        0xb1 => (LDA, IndirectY),
+        0xb4 => (LDY, ZeropageX),
+        0xb5 => (LDA, ZeropageX),
+        0xb6 => (LDX, ZeropageY),
+        0xb8 => (CLV, Implied),
+        0xb9 => (LDA, AbsoluteY),
+        0xba => (TSX, Implied),
+        0xbc => (LDY, AbsoluteX),
+        0xbd => (LDA, AbsoluteX),
+        0xbe => (LDX, AbsoluteY),
+
+        0xc0 => (CPY, Immediate),
+        0xc1 => (CMP, XIndirect),
+        0xc4 => (CPY, Zeropage),
+        0xc5 => (CMP, Zeropage),
+        0xc6 => (DEC, Zeropage),
+        0xc8 => (INY, Implied),
+        0xc9 => (CMP, Immediate),
+        0xca => (DEX, Implied),
+        0xcc => (CPY, Absolute),
+        0xcd => (CMP, Absolute),
+        0xce => (DEC, Absolute),
+
+        0xd0 => (BNE, Relative),
+        0xd1 => (CMP, IndirectY),
+        0xd5 => (CMP, ZeropageX),
+        0xd6 => (DEC, ZeropageX),
+        0xd8 => (CLD, Implied),
+        0xd9 => (CMP, AbsoluteY),
+        0xdd => (CMP, AbsoluteX),
+        0xde => (DEC, AbsoluteX),
+
+        0xe0 => (CPX, Immediate),
+        0xe1 => (SBC, XIndirect),
+        0xe4 => (CPX, Zeropage),
+        0xe5 => (SBC, Zeropage),
+        0xe6 => (INC, Zeropage),
+        0xe8 => (INX, Implied),
+        0xe9 => (SBC, Immediate),
+        0xea => (NOP, Implied),
+        0xec => (CPX, Absolute),
+        0xed => (SBC, Absolute),
+        0xee => (INC, Absolute),
+
+        0xf0 => (BEQ, Relative),
+        0xf1 => (SBC, IndirectY),
+        0xf5 => (SBC, ZeropageX),
+        0xf6 => (INC, ZeropageX),
+        0xf8 => (SED, Implied),
+        0xf9 => (SBC, AbsoluteY),
+        0xfd => (SBC, AbsoluteX),
+        0xfe => (INC, AbsoluteX),
+
+        _ => (Operation::Unknown, AddressMode::Unknown),
+    }
+}
+
+fn decode_illegal(value: u8) -> (Operation, AddressMode) {
+    match value {
+        // From https://www.masswerk.at/6502/6502_instruction_set.html#illegals
+        0x03 => (SLO, XIndirect),
+        0x07 => (SLO, Zeropage),
+        0x0b => (ANC, Immediate),
+        0x0f => (SLO, Absolute),
+        0x13 => (SLO, IndirectY),
+        0x17 => (SLO, ZeropageX),
+        0x1b => (SLO, AbsoluteY),
+        0x1f => (SLO, AbsoluteX),
+
+        0x23 => (RLA, XIndirect),
+        0x27 => (RLA, Zeropage),
+        0x2b => (ANC, Immediate),
+        0x2f => (RLA, Absolute),
+        0x33 => (RLA, IndirectY),
+        0x37 => (RLA, ZeropageX),
+        0x3b => (RLA, AbsoluteY),
+        0x3f => (RLA, AbsoluteX),
+
+        0x43 => (SRE, XIndirect),
+        0x47 => (SRE, Zeropage),
+        0x4b => (ALR, Immediate),
+        0x4f => (SRE, Absolute),
+        0x53 => (SRE, IndirectY),
+        0x57 => (SRE, ZeropageX),
+        0x5b => (SRE, AbsoluteY),
+        0x5f => (SRE, AbsoluteX),
+
+        0x63 => (RRA, XIndirect),
+        0x67 => (RRA, Zeropage),
+        0x6b => (ARR, Immediate),
+        0x6f => (RRA, Absolute),
+        0x73 => (RRA, IndirectY),
+        0x77 => (RRA, ZeropageX),
+        0x7b => (RRA, AbsoluteY),
+        0x7f => (RRA, AbsoluteX),
+
+        0x83 => (SAX, XIndirect),
+        0x87 => (SAX, Zeropage),
+        0x8b => (ANE, Immediate),
+        0x8f => (SAX, Absolute),
+        0x93 => (SHA, IndirectY),
+        0x97 => (SAX, ZeropageY),
+        0x9b => (TAS, AbsoluteY),
+        0x9c => (SHY, AbsoluteX),
+        0x9e => (SHX, AbsoluteY),
+        0x9f => (SHA, AbsoluteY),
+
+        0xa3 => (LAX, XIndirect),
+        0xa7 => (LAX, Zeropage),
+        0xab => (LXA, Immediate),
+        0xaf => (LAX, Absolute),
+        0xb3 => (LAX, IndirectY),
+        0xb7 => (LAX, ZeropageY),
+        0xbb => (LAS, AbsoluteY),
+        0xbf => (LAX, AbsoluteY),
+
+        0xc3 => (DCP, XIndirect),
+        0xc7 => (DCP, Zeropage),
+        0xcb => (SBX, Immediate),
+        0xcf => (DCP, Absolute),
+        0xd3 => (DCP, IndirectY),
+        0xd7 => (DCP, ZeropageX),
+        0xdb => (DCP, AbsoluteY),
+        0xdf => (DCP, AbsoluteX),
+
+        0xe3 => (ISC, XIndirect),
+        0xe7 => (ISC, Zeropage),
+        0xeb => (USBC, Immediate),
+        0xef => (ISC, Absolute),
+        0xf3 => (ISC, IndirectY),
+        0xf7 => (ISC, ZeropageX),
+        0xfb => (ISC, AbsoluteY),
+        0xff => (ISC, AbsoluteX),
+
+        0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => (NOP, Implied),
+        0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => (NOP, Immediate),
+        0x04 | 0x44 | 0x64 => (NOP, Zeropage),
+        0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => (NOP, ZeropageX),
+        0x0c => (NOP, Absolute),
+        0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => (NOP, AbsoluteX),
+
+        0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+            (JAM, Implied)
+        }
+
+        _ => (Operation::Unknown, AddressMode::Unknown),
+    }
+}
+
+fn decode_65c02(value: u8) -> (Operation, AddressMode) {
+    match value {
+        // From http://www.6502.org/tutorials/65c02opcodes.html
+        0x04 => (TSB, Zeropage),
+        0x0c => (TSB, Absolute),
+        0x12 => (ORA, ZeropageIndirect),
+        0x14 => (TRB, Zeropage),
+        0x1a => (INC, Accumulator),
+        0x1c => (TRB, Absolute),
+
+        0x32 => (AND, ZeropageIndirect),
+        0x34 => (BIT, ZeropageX),
+        0x3a => (DEC, Accumulator),
+        0x3c => (BIT, AbsoluteX),
+
+        0x52 => (EOR, ZeropageIndirect),
+        0x5a => (PHY, Implied),
+
+        0x64 => (STZ, Zeropage),
+        0x72 => (ADC, ZeropageIndirect),
+        0x74 => (STZ, ZeropageX),
+        0x7a => (PLY, Implied),
+        0x7c => (JMP, AbsoluteXIndirect),
+
+        0x80 => (BRA, Relative),
+        0x89 => (BIT, Immediate),
+        0x92 => (STA, ZeropageIndirect),
+        0x9c => (STZ, Absolute),
+        0x9e => (STZ, AbsoluteX),
+
+        0xb2 => (LDA, ZeropageIndirect),
+        0xd2 => (CMP, ZeropageIndirect),
+        0xda => (PHX, Implied),
+        0xf2 => (SBC, ZeropageIndirect),
+        0xfa => (PLX, Implied),
+
+        _ => (Operation::Unknown, AddressMode::Unknown),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_variants_only_add_opcodes() {
+        for opcode in 0..=255u8 {
+            let documented = decode_opcode(opcode, Cpu::Nmos);
+            if documented.0 == Operation::Unknown {
+                continue;
+            }
+
+            assert_eq!(documented, decode_opcode(opcode, Cpu::NmosIllegal));
+            assert_eq!(documented, decode_opcode(opcode, Cpu::Cmos));
+        }
+    }
+
+    #[test]
+    fn test_illegal_opcodes_fill_the_table() {
+        let unknown = (0..=255u8)
+            .filter(|&opcode| decode_opcode(opcode, Cpu::NmosIllegal).0 == Operation::Unknown)
+            .count();
+
+        assert_eq!(unknown, 0);
+    }
+
+    #[test]
+    fn test_mnemonic_lookup() {
+        assert_eq!(Operation::from_mnemonic("lda"), Some(LDA));
+        assert_eq!(Operation::from_mnemonic("STZ"), Some(STZ));
+        assert_eq!(Operation::from_mnemonic("???"), None);
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Cpu, MemoryImage};
+
+/// Machines with a known memory map. A preset picks the cpu and names the
+/// hardware registers and ROM routines that programs for the machine use.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Platform {
+    C64,
+    Nes,
+    AppleII,
+    Atari8,
+}
+
+const C64_LABELS: &[(usize, &str)] = &[
+    (0x0314, "CINV"),
+    (0x0318, "NMINV"),
+    (0xd000, "VIC"),
+    (0xd011, "VIC_CONTROL1"),
+    (0xd012, "RASTER"),
+    (0xd015, "SPRITE_ENABLE"),
+    (0xd016, "VIC_CONTROL2"),
+    (0xd018, "VIC_MEMORY"),
+    (0xd019, "VIC_IRQ"),
+    (0xd01a, "VIC_IRQ_ENABLE"),
+    (0xd020, "BORDER"),
+    (0xd021, "BACKGROUND"),
+    (0xd400, "SID"),
+    (0xd418, "SID_VOLUME"),
+    (0xdc00, "CIA1"),
+    (0xdc0d, "CIA1_ICR"),
+    (0xdd00, "CIA2"),
+    (0xdd0d, "CIA2_ICR"),
+    (0xe544, "CLRSCR"),
+    (0xffba, "SETLFS"),
+    (0xffbd, "SETNAM"),
+    (0xffc0, "OPEN"),
+    (0xffc3, "CLOSE"),
+    (0xffc6, "CHKIN"),
+    (0xffc9, "CHKOUT"),
+    (0xffcc, "CLRCHN"),
+    (0xffcf, "CHRIN"),
+    (0xffd2, "CHROUT"),
+    (0xffd5, "LOAD"),
+    (0xffd8, "SAVE"),
+    (0xffe4, "GETIN"),
+    (0xfff0, "PLOT"),
+];
+
+const NES_LABELS: &[(usize, &str)] = &[
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4010, "DMC_FREQ"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+const APPLE_II_LABELS: &[(usize, &str)] = &[
+    (0xc000, "KBD"),
+    (0xc010, "KBDSTRB"),
+    (0xc030, "SPKR"),
+    (0xc050, "TXTCLR"),
+    (0xc051, "TXTSET"),
+    (0xc054, "LOWSCR"),
+    (0xc055, "HISCR"),
+    (0xc057, "HIRES"),
+    (0xfc58, "HOME"),
+    (0xfca8, "WAIT"),
+    (0xfd0c, "RDKEY"),
+    (0xfd8e, "CROUT"),
+    (0xfdda, "PRBYTE"),
+    (0xfded, "COUT"),
+];
+
+const ATARI_8_LABELS: &[(usize, &str)] = &[
+    (0xd01a, "COLBK"),
+    (0xd01f, "CONSOL"),
+    (0xd200, "AUDF1"),
+    (0xd201, "AUDC1"),
+    (0xd20a, "RANDOM"),
+    (0xd300, "PORTA"),
+    (0xd400, "DMACTL"),
+    (0xd40a, "WSYNC"),
+    (0xd40b, "VCOUNT"),
+    (0xd40e, "NMIEN"),
+    (0xe456, "CIOV"),
+    (0xe459, "SIOV"),
+    (0xe45c, "SETVBV"),
+    (0xe462, "XITVBV"),
+];
+
+impl Platform {
+    pub fn cpu(self) -> Cpu {
+        match self {
+            // Plenty of C64 code uses the undocumented opcodes
+            Platform::C64 => Cpu::NmosIllegal,
+            Platform::Nes | Platform::AppleII | Platform::Atari8 => Cpu::Nmos,
+        }
+    }
+
+    pub fn labels(self) -> &'static [(usize, &'static str)] {
+        match self {
+            Platform::C64 => C64_LABELS,
+            Platform::Nes => NES_LABELS,
+            Platform::AppleII => APPLE_II_LABELS,
+            Platform::Atari8 => ATARI_8_LABELS,
+        }
+    }
+
+    /// Sets the cpu and names the well known addresses that have no label
+    /// yet, so labels from symbol files win over the preset
+    pub fn apply(self, image: &mut MemoryImage) {
+        image.set_cpu(self.cpu());
+        for &(address, name) in self.labels() {
+            if !image.labels().contains_key(&address) {
+                image.add_label(address, name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_keeps_labels() {
+        let mut image = MemoryImage::new().with_label(0xffd2, "print");
+        Platform::C64.apply(&mut image);
+
+        assert_eq!(image.cpu(), Cpu::NmosIllegal);
+        assert_eq!(image.label(0xffd2), Some("print"));
+        assert_eq!(image.label(0xd020), Some("BORDER"));
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt::Display,
+    fs, io,
+    ops::Range,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    load_at, parse_symbols, Cpu, EntryPoint, FormatError, MemoryImage, Platform, SymbolError,
+};
+
+/// Annotations made on top of an input file, so that the work survives
+/// between sessions and can be versioned next to the binary. Stored as JSON,
+/// paths are relative to the directory of the project file.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct Project {
+    /// Path of the file that the annotations are for
+    pub input: String,
+    /// SHA-256 of the input and symbol files by path, to notice when a file
+    /// no longer is the one that was annotated
+    #[serde(default)]
+    pub hashes: BTreeMap<String, String>,
+    /// Load address of a flat binary
+    #[serde(default)]
+    pub origin: usize,
+    #[serde(default)]
+    pub cpu: Option<Cpu>,
+    /// Machine whose registers and ROM routines get named
+    #[serde(default)]
+    pub platform: Option<Platform>,
+    /// VICE label or `name = $1234` style symbol files
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<usize, String>,
+    #[serde(default)]
+    pub comments: BTreeMap<usize, String>,
+    #[serde(default)]
+    pub data_ranges: Vec<Range<usize>>,
+    #[serde(default)]
+    pub code_starts: BTreeSet<usize>,
+    #[serde(default)]
+    pub entry_points: Vec<EntryPoint>,
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    Json {
+        path: String,
+        error: serde_json::Error,
+    },
+    Format(FormatError),
+    Symbols {
+        path: String,
+        error: SymbolError,
+    },
+    /// The file is not the one the project was made for
+    Changed {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Display for ProjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectError::Io { path, error } => write!(f, "{}: {}", path, error),
+            ProjectError::Json { path, error } => write!(f, "{}: {}", path, error),
+            ProjectError::Format(error) => write!(f, "{}", error),
+            ProjectError::Symbols { path, error } => write!(f, "{}: {}", path, error),
+            ProjectError::Changed {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has changed since the project was saved, its SHA-256 was {} and is now {}",
+                path, expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for ProjectError {}
+
+impl From<FormatError> for ProjectError {
+    fn from(error: FormatError) -> Self {
+        ProjectError::Format(error)
+    }
+}
+
+/// Lowercase hex SHA-256 of the bytes
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl Project {
+    pub fn new(input: impl Into<String>) -> Self {
+        Project {
+            input: input.into(),
+            ..Self::default()
+        }
+    }
+
+    /// New project that remembers the hash of the input
+    pub fn for_input(input: impl Into<String>, bytes: &[u8]) -> Self {
+        let mut project = Self::new(input);
+        project.record(&project.input.clone(), bytes);
+        project
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("projects always serialize")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ProjectError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(io_error(path))?;
+        Self::from_json(&text).map_err(|error| ProjectError::Json {
+            path: path.display().to_string(),
+            error,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ProjectError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json() + "\n").map_err(io_error(path))
+    }
+
+    /// Remembers the hash of a file so that later changes to it are noticed
+    pub fn record(&mut self, path: &str, bytes: &[u8]) {
+        self.hashes.insert(path.to_string(), sha256(bytes));
+    }
+
+    /// Checks a file against its recorded hash. Files without one pass.
+    pub fn check(&self, path: &str, bytes: &[u8]) -> Result<(), ProjectError> {
+        match self.hashes.get(path) {
+            Some(expected) => {
+                let actual = sha256(bytes);
+                if *expected == actual {
+                    Ok(())
+                } else {
+                    Err(ProjectError::Changed {
+                        path: path.to_string(),
+                        expected: expected.clone(),
+                        actual,
+                    })
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Annotated image of the input bytes. Symbol files are not read, see
+    /// `open` and `add_symbols` for that.
+    pub fn image(&self, bytes: &[u8]) -> Result<MemoryImage, ProjectError> {
+        self.check(&self.input, bytes)?;
+        let mut image = load_at(bytes, self.origin)?;
+        self.apply(&mut image);
+        Ok(image)
+    }
+
+    /// Reads the input and symbol files from the directory the project is in
+    /// and returns the annotated image
+    pub fn open(&self, directory: impl AsRef<Path>) -> Result<MemoryImage, ProjectError> {
+        let directory = directory.as_ref();
+        let path = directory.join(&self.input);
+        let bytes = fs::read(&path).map_err(io_error(&path))?;
+        self.check(&self.input, &bytes)?;
+
+        let mut image = load_at(&bytes, self.origin)?;
+        self.add_symbols(directory, &mut image)?;
+        self.apply(&mut image);
+        Ok(image)
+    }
+
+    /// Labels the image from the symbol files of the project
+    pub fn add_symbols(
+        &self,
+        directory: impl AsRef<Path>,
+        image: &mut MemoryImage,
+    ) -> Result<(), ProjectError> {
+        for path in &self.symbols {
+            let file = directory.as_ref().join(path);
+            let text = fs::read_to_string(&file).map_err(io_error(&file))?;
+            self.check(path, text.as_bytes())?;
+            let symbols = parse_symbols(&text).map_err(|error| ProjectError::Symbols {
+                path: path.clone(),
+                error,
+            })?;
+            for (address, name) in symbols {
+                image.add_label(address, name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the annotations to an image that was loaded from the input
+    pub fn apply(&self, image: &mut MemoryImage) {
+        if let Some(platform) = self.platform {
+            platform.apply(image);
+        }
+        if let Some(cpu) = self.cpu {
+            image.set_cpu(cpu);
+        }
+        for (&address, label) in &self.labels {
+            image.add_label(address, label);
+        }
+        for (&address, comment) in &self.comments {
+            image.add_comment(address, comment);
+        }
+        for range in &self.data_ranges {
+            image.add_data_range(range.clone());
+        }
+        for &start in &self.code_starts {
+            image.add_code_range(start..start + 1);
+        }
+        for entry in &self.entry_points {
+            if !image.entry_points().contains(entry) {
+                image.add_entry_point(&entry.name, entry.address);
+            }
+        }
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> ProjectError + '_ {
+    move |error| ProjectError::Io {
+        path: path.display().to_string(),
+        error,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Segment;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut project = Project::new("game.prg");
+        project.origin = 0x0801;
+        project.cpu = Some(Cpu::NmosIllegal);
+        project.labels.insert(0x080d, "start".into());
+        project.comments.insert(0x080d, "clear the screen".into());
+        project.data_ranges.push(0x0900..0x0a00);
+
+        assert_eq!(Project::from_json(&project.to_json()).unwrap(), project);
+        assert_eq!(
+            Project::from_json(r#"{"input": "game.prg"}"#).unwrap(),
+            Project::new("game.prg")
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut project = Project::new("game.prg");
+        project.labels.insert(0x1000, "start".into());
+        project.data_ranges.push(0x1002..0x1004);
+
+        let mut image = MemoryImage::new().with_segment(Segment::new("main", 0x1000, vec![0; 8]));
+        project.apply(&mut image);
+
+        assert_eq!(image.label(0x1000), Some("start"));
+        assert!(image.is_data(0x1003));
+    }
+
+    #[test]
+    fn test_hashes() {
+        let project = Project::for_input("game.prg", &[]);
+        assert_eq!(
+            project.hashes["game.prg"],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        assert!(project.image(&[]).is_ok());
+        assert!(matches!(
+            project.image(&[0xea]),
+            Err(ProjectError::Changed { .. })
+        ));
+        // Files without a hash are not checked
+        assert!(project.check("game.sym", &[0xea]).is_ok());
+    }
+
+    #[test]
+    fn test_open() {
+        let directory = std::env::temp_dir().join(format!("project-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("game.bin"), [0x20, 0xd2, 0xff, 0x60]).unwrap();
+        fs::write(directory.join("game.sym"), "al C:c003 .done\n").unwrap();
+
+        let mut project = Project::for_input("game.bin", &[0x20, 0xd2, 0xff, 0x60]);
+        project.origin = 0xc000;
+        project.platform = Some(Platform::C64);
+        project.symbols.push("game.sym".into());
+        project.entry_points.push(EntryPoint {
+            name: "start".into(),
+            address: 0xc000,
+        });
+        project.save(directory.join("game.json")).unwrap();
+
+        let project = Project::load(directory.join("game.json")).unwrap();
+        let image = project.open(&directory).unwrap();
+        assert_eq!(image.cpu(), Cpu::NmosIllegal);
+        assert_eq!(image.label(0xc000), Some("start"));
+        assert_eq!(image.label(0xc003), Some("done"));
+        assert_eq!(image.label(0xffd2), Some("CHROUT"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{disassemble_image, Instruction, MemoryImage};
+
+/// Parses space separated hex bytes where `??` matches any byte, for example
+/// `A9 ?? 8D 20 D0`
+pub fn parse_pattern(text: &str) -> Result<Vec<Option<u8>>, String> {
+    let pattern = text
+        .split_whitespace()
+        .map(|token| match token {
+            "??" | "?" => Ok(None),
+            _ => u8::from_str_radix(token.trim_start_matches('$'), 16)
+                .map(Some)
+                .map_err(|_| format!("'{}' is not a hex byte", token)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if pattern.is_empty() {
+        return Err("pattern is empty".into());
+    }
+    Ok(pattern)
+}
+
+/// Addresses where the byte pattern occurs. Matches don't continue over gaps
+/// between segments.
+pub fn search_bytes(image: &MemoryImage, pattern: &[Option<u8>]) -> Vec<usize> {
+    if pattern.is_empty() {
+        return vec![];
+    }
+
+    image
+        .runs()
+        .into_iter()
+        .flat_map(|(origin, bytes)| {
+            bytes
+                .windows(pattern.len())
+                .enumerate()
+                .filter(|(_, window)| {
+                    window
+                        .iter()
+                        .zip(pattern)
+                        .all(|(byte, expected)| expected.is_none_or(|expected| expected == *byte))
+                })
+                .map(|(index, _)| origin + index)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Instructions whose `OPERATION ADDRESS` text matches the glob, case
+/// insensitively. `*` matches any run of characters and `?` a single one, so
+/// `STA $D0??` finds stores to the VIC-II registers.
+pub fn search_instructions(image: &MemoryImage, pattern: &str) -> Vec<Instruction> {
+    let pattern: Vec<char> = pattern.trim().to_ascii_uppercase().chars().collect();
+
+    disassemble_image(image)
+        .into_iter()
+        .filter(|instruction| {
+            let text = format!("{} {}", instruction.operation, instruction.address);
+            let text: Vec<char> = text.trim().to_ascii_uppercase().chars().collect();
+            glob(&pattern, &text)
+        })
+        .collect()
+}
+
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            glob(&pattern[1..], text) || (!text.is_empty() && glob(pattern, &text[1..]))
+        }
+        (Some('?'), Some(_)) => glob(&pattern[1..], &text[1..]),
+        (Some(expected), Some(character)) if expected == character => {
+            glob(&pattern[1..], &text[1..])
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Segment;
+
+    use super::*;
+
+    fn image() -> MemoryImage {
+        MemoryImage::new()
+            .with_segment(Segment::new(
+                "main",
+                0xc000,
+                vec![0xa9, 0x00, 0x8d, 0x20, 0xd0, 0xa9, 0x01, 0x8d, 0x21, 0xd0],
+            ))
+            .with_segment(Segment::new("other", 0xc100, vec![0xd0, 0xa9, 0x00]))
+    }
+
+    #[test]
+    fn test_bytes() {
+        let pattern = parse_pattern("A9 ?? 8D").unwrap();
+        assert_eq!(search_bytes(&image(), &pattern), vec![0xc000, 0xc005]);
+
+        let pattern = parse_pattern("d0 a9").unwrap();
+        assert_eq!(search_bytes(&image(), &pattern), vec![0xc004, 0xc100]);
+
+        // Only occurs across the gap
+        let pattern = parse_pattern("21 d0 d0").unwrap();
+        assert!(search_bytes(&image(), &pattern).is_empty());
+
+        assert!(parse_pattern("A9 XY").is_err());
+    }
+
+    #[test]
+    fn test_instructions() {
+        let offsets: Vec<usize> = search_instructions(&image(), "sta $d02?")
+            .into_iter()
+            .map(|instruction| instruction.offset)
+            .collect();
+
+        assert_eq!(offsets, vec![0xc002, 0xc007]);
+        assert_eq!(search_instructions(&image(), "LDA *").len(), 2);
+    }
+}
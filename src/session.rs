@@ -0,0 +1,709 @@
+use std::{ops::Range, sync::Arc};
+
+use futures_util::{SinkExt, StreamExt};
+use poem::{
+    http::StatusCode,
+    web::websocket::{CloseCode, Message, WebSocket, WebSocketConfig, WebSocketStream},
+    Endpoint, FromRequest, IntoResponse, Request, Response, Result,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{event, Level};
+
+use crate::{
+    api::{annotated_image, blocking, Failure},
+    decode_image, decode_image_from,
+    disassemble::annotate,
+    limits::error_response,
+    Cpu, Decoded, ErrorBody, Format, Instruction, Limits, MemoryImage, Store,
+};
+
+/// Instructions sent around the cursor when the client doesn't say
+const VIEW_SIZE: usize = 64;
+const MAX_VIEW_SIZE: usize = 1000;
+
+/// Change a client of a live session makes, sent as JSON with a `type`
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Edit {
+    /// Overwrites bytes from the address on
+    Patch {
+        address: usize,
+        bytes: Vec<u8>,
+    },
+    /// Names the address, an empty name removes the label
+    Label {
+        address: usize,
+        name: String,
+    },
+    /// An empty text removes the comment
+    Comment {
+        address: usize,
+        text: String,
+    },
+    Data {
+        start: usize,
+        end: usize,
+    },
+    Code {
+        start: usize,
+        end: usize,
+    },
+    /// Moves the cursor, `count` instructions around it are sent back
+    Cursor {
+        address: usize,
+        #[serde(default)]
+        count: Option<usize>,
+    },
+}
+
+/// What the server sends back, one for every edit
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Update {
+    /// Instructions around the cursor, sent when the session starts and
+    /// whenever the cursor moves
+    View {
+        cursor: usize,
+        instructions: Vec<Instruction>,
+    },
+    /// The instructions that start from `start` up to `end` are replaced by
+    /// these. Everything outside the region is unchanged.
+    Region {
+        start: usize,
+        end: usize,
+        instructions: Vec<Instruction>,
+    },
+    Error(ErrorBody),
+}
+
+/// Disassembly of an image that is edited in place. An edit only decodes
+/// again from the instruction it touches up to where decoding is back in
+/// step with the listing from before, so that editing a big binary costs
+/// about as much as editing a small one.
+#[derive(Debug)]
+pub struct Session {
+    image: MemoryImage,
+    decoded: Vec<Decoded>,
+    /// Indices of the instructions that start below the end of the one
+    /// before, as where a bank ends and the next one starts. Offsets only go
+    /// up between them.
+    breaks: Vec<usize>,
+    cursor: usize,
+    count: usize,
+}
+
+impl Session {
+    pub fn new(image: MemoryImage) -> Self {
+        let decoded = decode_image(&image);
+        Session {
+            cursor: decoded.first().map_or(0, |decoded| decoded.offset),
+            breaks: (1..decoded.len())
+                .filter(|&index| is_break(&decoded, index))
+                .collect(),
+            image,
+            decoded,
+            count: VIEW_SIZE,
+        }
+    }
+
+    pub fn image(&self) -> &MemoryImage {
+        &self.image
+    }
+
+    /// The whole listing as it is after the edits
+    pub fn instructions(&self) -> Vec<Instruction> {
+        self.annotate(&self.decoded)
+    }
+
+    pub fn view(&self) -> Update {
+        let index = self.index_at(self.cursor).saturating_sub(self.count / 4);
+        let end = (index + self.count).min(self.decoded.len());
+        Update::View {
+            cursor: self.cursor,
+            instructions: self.annotate(&self.decoded[index..end]),
+        }
+    }
+
+    pub fn apply(&mut self, edit: Edit) -> Update {
+        match edit {
+            Edit::Patch { address, bytes } => {
+                let outside = address.checked_add(bytes.len()).is_none_or(|end| {
+                    (address..end).any(|address| self.image.read(address).is_none())
+                });
+                if outside {
+                    return Update::Error(
+                        Failure::Unprocessable(format!(
+                            "The patch at {:04X} goes outside of the image",
+                            address
+                        ))
+                        .body(),
+                    );
+                }
+                let range = address..address + bytes.len();
+                for (address, byte) in range.clone().zip(bytes) {
+                    self.image.write(address, byte);
+                }
+                self.decode_again(range)
+            }
+            Edit::Label { address, name } => {
+                match name.is_empty() {
+                    true => self.image.remove_label(address),
+                    false => self.image.add_label(address, name),
+                }
+                self.annotate_everywhere(address)
+            }
+            Edit::Comment { address, text } => {
+                match text.is_empty() {
+                    true => self.image.remove_comment(address),
+                    false => self.image.add_comment(address, text),
+                }
+                self.annotate_everywhere(address)
+            }
+            Edit::Data { start, end } | Edit::Code { start, end } if start >= end => Update::Error(
+                Failure::BadRequest(format!("The range {:04X}-{:04X} is empty", start, end)).body(),
+            ),
+            Edit::Data { start, end } => {
+                self.image.add_data_range(start..end);
+                self.decode_everywhere(start..end)
+            }
+            Edit::Code { start, end } => {
+                self.image.add_code_range(start..end);
+                self.decode_everywhere(start..end)
+            }
+            Edit::Cursor { address, count } => {
+                self.cursor = address;
+                self.count = count.unwrap_or(VIEW_SIZE).clamp(1, MAX_VIEW_SIZE);
+                self.view()
+            }
+        }
+    }
+
+    /// Index of the instruction the address is a part of, or of the first
+    /// one after it, in the first stretch between breaks that has one
+    fn index_at(&self, address: usize) -> usize {
+        let mut start = 0;
+        for end in self.breaks.iter().copied().chain([self.decoded.len()]) {
+            let stretch = &self.decoded[start..end];
+            let index = stretch.partition_point(|decoded| decoded.end() <= address);
+            if index < stretch.len() {
+                return start + index;
+            }
+            start = end;
+        }
+        self.decoded.len()
+    }
+
+    /// Ranges, labels and comments go with the address in every bank, and
+    /// an update only says which addresses it replaces. With more than one
+    /// bank the whole image is decoded again and the view sent instead.
+    fn decode_everywhere(&mut self, changed: Range<usize>) -> Update {
+        if self.breaks.is_empty() {
+            return self.decode_again(changed);
+        }
+        *self = Session {
+            cursor: self.cursor,
+            count: self.count,
+            ..Session::new(std::mem::take(&mut self.image))
+        };
+        self.view()
+    }
+
+    fn annotate_everywhere(&self, address: usize) -> Update {
+        match self.breaks.is_empty() {
+            true => self.annotate_again(address),
+            false => self.view(),
+        }
+    }
+
+    fn decode_again(&mut self, changed: Range<usize>) -> Update {
+        // The instruction before the change may have been cut short by a
+        // boundary that the change removed
+        let first = self.index_at(changed.start).saturating_sub(1);
+        let start = self
+            .decoded
+            .get(first)
+            .map_or(changed.start, |decoded| decoded.offset);
+
+        let mut last = first;
+        let mut in_step = false;
+        let mut instructions = vec![];
+        for decoded in decode_image_from(&self.image, start) {
+            // Past the change, decoding is back in step as soon as it comes
+            // to where an instruction of the old listing started
+            if decoded.offset >= changed.end {
+                while self
+                    .decoded
+                    .get(last)
+                    .is_some_and(|old| old.offset < decoded.offset)
+                {
+                    last += 1;
+                }
+                if self
+                    .decoded
+                    .get(last)
+                    .is_some_and(|old| old.offset == decoded.offset)
+                {
+                    in_step = true;
+                    break;
+                }
+            }
+            instructions.push(decoded);
+        }
+        if !in_step {
+            last = self.decoded.len();
+        }
+
+        let end = match self.decoded.get(last) {
+            Some(decoded) => decoded.offset,
+            None => self.decoded[first..]
+                .iter()
+                .chain(&instructions)
+                .map(Decoded::end)
+                .max()
+                .unwrap_or(start),
+        };
+        let update = Update::Region {
+            start,
+            end,
+            instructions: self.annotate(&instructions),
+        };
+        let added = instructions.len();
+        self.decoded.splice(first..last, instructions);
+
+        // Only the breaks next to and inside of the spliced instructions
+        // can have changed, the ones after them just moved
+        let mut breaks: Vec<usize> = self
+            .breaks
+            .iter()
+            .filter(|&&index| index < first)
+            .copied()
+            .collect();
+        breaks.extend(
+            (first.max(1)..=first + added)
+                .filter(|&index| index < self.decoded.len() && is_break(&self.decoded, index)),
+        );
+        breaks.extend(
+            self.breaks
+                .iter()
+                .filter(|&&index| index > last)
+                .map(|&index| index + added - (last - first)),
+        );
+        self.breaks = breaks;
+        update
+    }
+
+    fn annotate_again(&self, address: usize) -> Update {
+        let instructions: Vec<Decoded> = self
+            .decoded
+            .get(self.index_at(address))
+            .filter(|decoded| decoded.offset == address)
+            .cloned()
+            .into_iter()
+            .collect();
+        Update::Region {
+            start: address,
+            end: instructions.first().map_or(address, Decoded::end),
+            instructions: self.annotate(&instructions),
+        }
+    }
+
+    fn annotate(&self, decoded: &[Decoded]) -> Vec<Instruction> {
+        decoded
+            .iter()
+            .map(|decoded| annotate(decoded.clone(), &self.image))
+            .collect()
+    }
+}
+
+/// Whether the instruction at the index starts below the end of the one
+/// before it
+fn is_break(decoded: &[Decoded], index: usize) -> bool {
+    decoded[index].offset < decoded[index - 1].end()
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionParams {
+    /// ID of a stored binary, its project is applied
+    id: String,
+    origin: Option<usize>,
+    cpu: Option<Cpu>,
+    input_format: Option<Format>,
+}
+
+/// WebSocket endpoint of live sessions on stored binaries. The client sends
+/// `Edit`s as JSON text messages and gets an `Update` back for each, so that
+/// only the instructions an edit changed travel over the wire.
+///
+/// Clones share the sessions, so the server can keep one to close them when
+/// it shuts down.
+#[derive(Debug, Clone, Default)]
+pub struct Sessions {
+    limits: Limits,
+    store: Arc<Store>,
+    /// Every live session holds a receiver, and closes once this is true
+    closing: Arc<watch::Sender<bool>>,
+}
+
+impl Sessions {
+    pub fn new(limits: Limits) -> Self {
+        Sessions {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Shares the store with the API, so that binaries uploaded there can
+    /// be edited
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Closes the live sessions, and the ones that start from now on, and
+    /// waits until they have ended
+    pub async fn close(&self) {
+        self.closing.send_replace(true);
+        self.closing.closed().await;
+    }
+
+    /// Loads the binary and decodes it off the async threads
+    async fn session(&self, req: &Request) -> Result<Session, Failure> {
+        let params = req
+            .params::<SessionParams>()
+            .map_err(|err| Failure::BadRequest(err.to_string()))?;
+        let bytes = self.store.get(&params.id).ok_or_else(|| {
+            Failure::NotFound(format!("There is no binary with the ID {}", params.id))
+        })?;
+        let project = self.store.project(&params.id);
+        blocking(move || {
+            let image = annotated_image(
+                &bytes,
+                params.origin,
+                params.cpu,
+                params.input_format,
+                project.as_ref(),
+            )?;
+            Ok(Session::new(image))
+        })
+        .await
+    }
+}
+
+impl Endpoint for Sessions {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        event!(Level::INFO, "Starting session");
+
+        let Ok(websocket) = WebSocket::from_request_without_body(&req).await else {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                Failure::BadRequest("Sessions are WebSocket connections".into()).body(),
+            ));
+        };
+        let session = match self.session(&req).await {
+            Ok(session) => session,
+            Err(failure) => return Ok(error_response(failure.status(), failure.body())),
+        };
+
+        let max_message = Some(self.limits.max_payload);
+        let config = WebSocketConfig::default()
+            .max_message_size(max_message)
+            .max_frame_size(max_message);
+        let closing = self.closing.subscribe();
+        Ok(websocket
+            .config(config)
+            .on_upgrade(move |socket| serve(socket, session, closing))
+            .into_response())
+    }
+}
+
+async fn serve(
+    mut socket: WebSocketStream,
+    mut session: Session,
+    mut closing: watch::Receiver<bool>,
+) {
+    if send(&mut socket, &session.view()).await.is_err() {
+        return;
+    }
+
+    loop {
+        let message = tokio::select! {
+            message = socket.next() => message,
+            // Without the drop the guard of the value is held across the
+            // close, which would keep the future from being `Send`
+            _ = async { drop(closing.wait_for(|closing| *closing).await) } => {
+                let close = Message::close_with(CloseCode::Away, "The server is shutting down");
+                let _ = socket.send(close).await;
+                return;
+            }
+        };
+        let Some(message) = message else {
+            return;
+        };
+        let update = match message {
+            Ok(Message::Text(text)) => match serde_json::from_str::<Edit>(&text) {
+                // Decoding again can take as long as decoding the whole
+                // image, so it takes a permit like every other disassembly
+                Ok(edit) => {
+                    let applied = blocking(move || {
+                        let update = session.apply(edit);
+                        Ok((session, update))
+                    })
+                    .await;
+                    match applied {
+                        Ok((applied, update)) => {
+                            session = applied;
+                            update
+                        }
+                        Err(failure) => {
+                            let _ = send(&mut socket, &Update::Error(failure.body())).await;
+                            return;
+                        }
+                    }
+                }
+                Err(err) => Update::Error(Failure::BadRequest(err.to_string()).body()),
+            },
+            Ok(Message::Binary(_)) => {
+                Update::Error(Failure::BadRequest("Edits are sent as text".into()).body())
+            }
+            // Answered by the WebSocket itself, a close ends the messages
+            Ok(Message::Ping(_) | Message::Pong(_) | Message::Close(_)) => continue,
+            Err(err) => {
+                event!(Level::INFO, "Closing session: {}", err);
+                return;
+            }
+        };
+        if send(&mut socket, &update).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocketStream, update: &Update) -> std::io::Result<()> {
+    let text = serde_json::to_string(update).expect("updates always serialize");
+    socket.send(Message::Text(text)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disassemble_image, load_at, Segment};
+
+    /// Every edit has to leave the session with the listing that decoding
+    /// the edited image from scratch gives
+    fn check(session: &Session) {
+        assert_eq!(session.instructions(), disassemble_image(session.image()),);
+    }
+
+    #[test]
+    fn test_edits() {
+        // LDA $1234 and NOP, over and over
+        let bytes = [0xad, 0x34, 0x12, 0xea].repeat(64);
+        let mut session = Session::new(load_at(&bytes, 0x1000).unwrap());
+
+        // NOP over the opcode, the operand bytes are decoded on their own
+        let update = session.apply(Edit::Patch {
+            address: 0x1004,
+            bytes: vec![0xea],
+        });
+        check(&session);
+        let Update::Region {
+            start,
+            end,
+            instructions,
+        } = update
+        else {
+            panic!("{:?}", update);
+        };
+        assert_eq!((start, end), (0x1003, 0x1007));
+        let operations: Vec<&str> = instructions
+            .iter()
+            .map(|instruction| instruction.operation.as_str())
+            .collect();
+        assert_eq!(operations, ["NOP", "NOP", "???", "???"]);
+
+        session.apply(Edit::Data {
+            start: 0x1010,
+            end: 0x1013,
+        });
+        check(&session);
+        session.apply(Edit::Code {
+            start: 0x1011,
+            end: 0x1012,
+        });
+        check(&session);
+        session.apply(Edit::Patch {
+            address: 0x10fe,
+            bytes: vec![0x20, 0x00],
+        });
+        check(&session);
+
+        let update = session.apply(Edit::Label {
+            address: 0x1008,
+            name: "loop".into(),
+        });
+        check(&session);
+        assert!(matches!(update, Update::Region { instructions, .. }
+            if instructions[0].label.as_deref() == Some("loop")));
+
+        let update = session.apply(Edit::Patch {
+            address: 0x10ff,
+            bytes: vec![1, 2],
+        });
+        assert!(matches!(update, Update::Error(error) if error.code == "unprocessable"));
+        let update = session.apply(Edit::Patch {
+            address: usize::MAX,
+            bytes: vec![1, 2],
+        });
+        assert!(matches!(update, Update::Error(error) if error.code == "unprocessable"));
+    }
+
+    #[test]
+    fn test_banks() {
+        // Two banks at the same addresses, so offsets go back down halfway
+        // through the listing
+        let bytes = [0xad, 0x34, 0x12, 0xea].repeat(8);
+        let image = MemoryImage::new()
+            .with_segment(Segment::new("bank 0", 0x8000, bytes.clone()).with_bank(0))
+            .with_segment(Segment::new("bank 1", 0x8000, bytes).with_bank(1));
+        let mut session = Session::new(image);
+        assert_eq!(session.breaks, [16]);
+        assert_eq!(session.index_at(0x8005), 2);
+        assert_eq!(session.index_at(0x8020), session.decoded.len());
+
+        let update = session.apply(Edit::Patch {
+            address: 0x8004,
+            bytes: vec![0xea],
+        });
+        check(&session);
+        assert!(matches!(update, Update::Region { start: 0x8003, .. }));
+
+        // The range is in both banks, so the whole view comes back
+        let update = session.apply(Edit::Data {
+            start: 0x8010,
+            end: 0x8013,
+        });
+        check(&session);
+        assert!(matches!(update, Update::View { .. }));
+        assert_eq!(session.breaks, [session.index_at(0x801f) + 1]);
+
+        let update = session.apply(Edit::Label {
+            address: 0x8008,
+            name: "loop".into(),
+        });
+        check(&session);
+        let Update::View { instructions, .. } = update else {
+            panic!("{:?}", update);
+        };
+        let labelled = instructions
+            .iter()
+            .filter(|instruction| instruction.label.as_deref() == Some("loop"));
+        assert_eq!(labelled.count(), 2);
+    }
+
+    #[test]
+    fn test_view() {
+        let mut session = Session::new(load_at(&[0xea; 100], 0x2000).unwrap());
+        let update = session.apply(Edit::Cursor {
+            address: 0x2040,
+            count: Some(8),
+        });
+        let Update::View { instructions, .. } = update else {
+            panic!("{:?}", update);
+        };
+        assert_eq!(instructions.len(), 8);
+        assert_eq!(instructions[0].offset, 0x203e);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let sessions = Sessions::default();
+        let mut live = sessions.closing.subscribe();
+        let close = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { sessions.close().await }
+        });
+
+        assert!(*live.wait_for(|closing| *closing).await.unwrap());
+        assert!(!close.is_finished());
+        drop(live);
+        close.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+        let client = reqwest::Client::new();
+        let id = client
+            .post("http://localhost:9999/json/binaries")
+            .header("Content-Type", "application/octet-stream")
+            .body(vec![0xa9, 0x01, 0x60])
+            .send()
+            .await
+            .unwrap()
+            .json::<crate::api::StoredBinary>()
+            .await
+            .unwrap()
+            .id;
+
+        let url = format!("ws://localhost:9999/json/session?id={}&origin=49152", id);
+        let (mut socket, response) = connect_async(url).await.unwrap();
+        assert_eq!(response.status(), 101);
+
+        async fn receive<S>(socket: &mut S) -> Update
+        where
+            S: futures_util::Stream<Item = tokio_tungstenite::tungstenite::Result<Message>> + Unpin,
+        {
+            match socket.next().await.unwrap().unwrap() {
+                Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                message => panic!("{:?}", message),
+            }
+        }
+        let edit = |edit: &Edit| Message::text(serde_json::to_string(edit).unwrap());
+
+        let view = receive(&mut socket).await;
+        assert!(matches!(view, Update::View { cursor: 0xc000, instructions }
+            if instructions.len() == 2));
+
+        socket
+            .send(edit(&Edit::Patch {
+                address: 0xc001,
+                bytes: vec![0x02],
+            }))
+            .await
+            .unwrap();
+        let region = receive(&mut socket).await;
+        assert!(
+            matches!(region, Update::Region { start: 0xc000, end: 0xc002, instructions }
+            if instructions[0].address == "#$02")
+        );
+
+        // The end of the patch is past the largest address
+        socket
+            .send(edit(&Edit::Patch {
+                address: usize::MAX,
+                bytes: vec![0xea, 0xea],
+            }))
+            .await
+            .unwrap();
+        let error = receive(&mut socket).await;
+        assert!(matches!(error, Update::Error(error) if error.code == "unprocessable"));
+
+        socket
+            .send(Message::text("{\"type\": \"jump\"}"))
+            .await
+            .unwrap();
+        let error = receive(&mut socket).await;
+        assert!(matches!(error, Update::Error(error) if error.code == "bad_request"));
+
+        let response = client
+            .get(format!("http://localhost:9999/json/session?id={}", id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+}
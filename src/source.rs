@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use poem_openapi::Enum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decode_image,
+    opcodes::{encode_opcode, AddressMode},
+    Cpu, Decoded, MemoryImage, Operation,
+};
+
+/// Assembler syntax that source output is written in
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize, Enum)]
+pub enum Dialect {
+    #[default]
+    Ca65,
+    Acme,
+}
+
+impl Dialect {
+    fn cpu(&self, cpu: Cpu) -> String {
+        match (self, cpu) {
+            (Dialect::Ca65, Cpu::Nmos) => ".setcpu \"6502\"".into(),
+            (Dialect::Ca65, Cpu::NmosIllegal) => ".setcpu \"6502X\"".into(),
+            (Dialect::Ca65, Cpu::Cmos) => ".setcpu \"65C02\"".into(),
+            (Dialect::Acme, Cpu::Nmos) => "!cpu 6502".into(),
+            (Dialect::Acme, Cpu::NmosIllegal) => "!cpu 6510".into(),
+            (Dialect::Acme, Cpu::Cmos) => "!cpu 65c02".into(),
+        }
+    }
+
+    fn origin(&self, address: usize) -> String {
+        match self {
+            Dialect::Ca65 => format!(".org ${:04X}", address),
+            Dialect::Acme => format!("* = ${:04X}", address),
+        }
+    }
+
+    fn label(&self, name: &str) -> String {
+        match self {
+            Dialect::Ca65 => format!("{}:", name),
+            Dialect::Acme => name.to_string(),
+        }
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> String {
+        let values: Vec<String> = bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+        match self {
+            Dialect::Ca65 => format!(".byte {}", values.join(", ")),
+            Dialect::Acme => format!("!byte {}", values.join(", ")),
+        }
+    }
+
+    /// Absolute addresses that would fit in the zero page have to be forced,
+    /// or the assembler would pick the shorter encoding
+    fn instruction(&self, operation: Operation, operand: &str, is_forced: bool) -> String {
+        match (self, is_forced) {
+            (_, false) if operand.is_empty() => operation.to_string(),
+            (_, false) => format!("{} {}", operation, operand),
+            (Dialect::Ca65, true) => match operand.strip_prefix('(') {
+                Some(rest) => format!("{} (a:{}", operation, rest),
+                None => format!("{} a:{}", operation, operand),
+            },
+            (Dialect::Acme, true) => format!("{}+2 {}", operation, operand),
+        }
+    }
+}
+
+/// Writes the image as assembler source that reassembles into the same bytes.
+/// Branch and jump targets inside the image get `Lxxxx` labels, and the labels
+/// of the image are used where they fit. Bytes that don't decode into a
+/// documented instruction are written as data with the mnemonic in a comment.
+pub fn assembly_source(image: &MemoryImage, dialect: Dialect) -> String {
+    let decoded = decode_image(image);
+    let names = label_names(image, &decoded);
+    let starts: BTreeSet<usize> = decoded.iter().map(|decoded| decoded.offset).collect();
+
+    let mut lines = vec![];
+    for property in image.metadata() {
+        lines.push(format!("; {}: {}", property.name, property.value));
+    }
+    lines.push(format!("        {}", dialect.cpu(image.cpu())));
+
+    // Labels that don't land on a line are defined up front, so they are also
+    // known in the first pass
+    let constants: BTreeSet<usize> = names
+        .keys()
+        .copied()
+        .filter(|address| !starts.contains(address))
+        .collect();
+    if !constants.is_empty() {
+        lines.push(String::new());
+    }
+    for address in &constants {
+        lines.push(format!("{} = ${:04X}", names[address], address));
+    }
+
+    let mut next = None;
+    for decoded in &decoded {
+        if next != Some(decoded.offset) {
+            lines.push(String::new());
+            lines.push(format!("        {}", dialect.origin(decoded.offset)));
+        }
+        next = Some(decoded.end());
+
+        if let Some(name) = names.get(&decoded.offset) {
+            lines.push(dialect.label(name));
+        }
+
+        let mut line = match render(decoded, image.cpu(), &names, &constants, dialect) {
+            Some(instruction) => format!("        {}", instruction),
+            // Marked data needs no explanation
+            None if image.is_data(decoded.offset) => {
+                format!("        {}", dialect.bytes(&decoded.raw_bytes))
+            }
+            None => {
+                let mnemonic = match decoded.operation {
+                    Operation::Unknown => "???".to_string(),
+                    operation => operation.to_string(),
+                };
+                format!(
+                    "        {} ; {}",
+                    dialect.bytes(&decoded.raw_bytes),
+                    mnemonic
+                )
+            }
+        };
+
+        if let Some(comment) = image.comment(decoded.offset) {
+            line = format!("{: <40}; {}", line, comment);
+        }
+        lines.push(line);
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Names for every address that is labelled in the image, and `Lxxxx` for the
+/// instructions that are jumped or branched to
+fn label_names(image: &MemoryImage, decoded: &[Decoded]) -> BTreeMap<usize, String> {
+    let mut addresses: BTreeSet<usize> = image.labels().keys().copied().collect();
+    addresses.extend(image.entry_points().iter().map(|entry| entry.address));
+
+    let starts: BTreeSet<usize> = decoded.iter().map(|decoded| decoded.offset).collect();
+    addresses.extend(
+        decoded
+            .iter()
+            .filter(|decoded| decoded.operation.is_branch() || decoded.operation.is_jump())
+            .filter(|decoded| decoded.address_mode != AddressMode::Indirect)
+            .filter_map(Decoded::target)
+            .filter(|target| starts.contains(target)),
+    );
+
+    let mut used = BTreeSet::new();
+    addresses
+        .into_iter()
+        .map(|address| {
+            let mut name = match image.label(address) {
+                Some(label) => sanitize(label),
+                None => format!("L{:04X}", address),
+            };
+            if !used.insert(name.clone()) {
+                name = format!("{}_{:04X}", name, address);
+                used.insert(name.clone());
+            }
+            (address, name)
+        })
+        .collect()
+}
+
+fn render(
+    decoded: &Decoded,
+    cpu: Cpu,
+    names: &BTreeMap<usize, String>,
+    constants: &BTreeSet<usize>,
+    dialect: Dialect,
+) -> Option<String> {
+    // Only instructions that assemble back into the same opcode are written
+    // as such, which leaves out the undocumented duplicates
+    let is_documented = !decoded.operation.is_illegal() && decoded.operation != Operation::Unknown;
+    let opcode = encode_opcode(decoded.operation, decoded.address_mode, cpu);
+    if !decoded.is_satisfied() || !is_documented || opcode != Some(decoded.raw_bytes[0]) {
+        return None;
+    }
+
+    let mode = decoded.address_mode;
+    let operand = match (mode, decoded.target()) {
+        (AddressMode::Implied, _) => String::new(),
+        (AddressMode::Accumulator, _) => "A".into(),
+        (AddressMode::Immediate, _) => format!("#${:02X}", decoded.operand()?),
+        (_, Some(target)) => match names.get(&target) {
+            // Labels defined later are unknown in the first pass of the
+            // assembler, so zero page operands only use the constants
+            Some(name) if mode.length() == 3 || mode == AddressMode::Relative => mode.wrap(name),
+            Some(name) if constants.contains(&target) => mode.wrap(name),
+            _ if mode.length() == 3 || mode == AddressMode::Relative => {
+                mode.wrap(&format!("${:04X}", target))
+            }
+            _ => mode.wrap(&format!("${:02X}", target)),
+        },
+        (_, None) => return None,
+    };
+
+    let is_forced = mode.length() == 3 && decoded.operand()? < 0x100;
+    Some(dialect.instruction(decoded.operation, &operand, is_forced))
+}
+
+/// Labels from containers can have spaces and other characters that the
+/// assemblers don't accept
+fn sanitize(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assemble, Segment};
+
+    use super::*;
+
+    fn image() -> MemoryImage {
+        MemoryImage::new()
+            .with_segment(Segment::new(
+                "main",
+                0xc000,
+                vec![
+                    0xa2, 0x00, // LDX #$00
+                    0xbd, 0x0f, 0x00, // LDA $000F,X
+                    0x20, 0xd2, 0xff, // JSR CHROUT
+                    0xe8, // INX
+                    0xd0, 0xf7, // BNE $C002
+                    0x60, // RTS
+                    0xa7, 0x10, // LAX $10
+                ],
+            ))
+            .with_entry_point("cold start", 0xc000)
+            .with_label(0xffd2, "CHROUT")
+    }
+
+    #[test]
+    fn test_ca65() {
+        let source = assembly_source(&image(), Dialect::Ca65);
+
+        assert!(source.contains("CHROUT = $FFD2"));
+        assert!(source.contains("        .org $C000\ncold_start:\n        LDX #$00"));
+        assert!(source.contains("LC002:"));
+        assert!(source.contains("LDA a:$000F,X"));
+        assert!(source.contains("JSR CHROUT"));
+        assert!(source.contains("BNE LC002"));
+        assert!(source.contains(".byte $A7 ; ???"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for dialect in [Dialect::Ca65, Dialect::Acme] {
+            for cpu in [Cpu::Nmos, Cpu::NmosIllegal, Cpu::Cmos] {
+                let image = image().with_cpu(cpu);
+                let source = assembly_source(&image, dialect);
+                let assembled = assemble(&source, Cpu::Nmos).unwrap();
+
+                assert_eq!(assembled.origin, 0xc000);
+                assert_eq!(assembled.bytes, image.segments()[0].bytes, "{}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mega_round_trip() {
+        let bytes = std::fs::read("test-bin/mega.bin").unwrap();
+        let image = MemoryImage::from(&bytes[..]).with_cpu(Cpu::Cmos);
+        let source = assembly_source(&image, Dialect::Acme);
+
+        assert_eq!(assemble(&source, Cpu::Nmos).unwrap().bytes, bytes);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{sha256, Project};
+
+/// Binaries that clients uploaded, kept by their SHA-256 so that later
+/// requests can refer to them by ID instead of sending them again, along with
+/// the project of annotations for each.
+///
+/// Without a directory everything is kept in memory and the oldest binaries
+/// are dropped once the total size passes the limit. With one, binaries and
+/// projects are files under it and survive restarts, and memory only caches
+/// the binaries.
+#[derive(Debug)]
+pub struct Store {
+    max_bytes: usize,
+    directory: Option<PathBuf>,
+    cache: Mutex<Cache>,
+    projects: Mutex<BTreeMap<String, Project>>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    binaries: HashMap<String, Arc<Vec<u8>>>,
+    /// IDs from the oldest to the newest
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new(256 * 1024 * 1024)
+    }
+}
+
+impl Store {
+    pub fn new(max_bytes: usize) -> Self {
+        Store {
+            max_bytes,
+            directory: None,
+            cache: Mutex::default(),
+            projects: Mutex::default(),
+        }
+    }
+
+    /// Store that keeps its files in the directory, which is created when
+    /// missing. Projects that were saved there before are loaded.
+    pub fn open(directory: impl AsRef<Path>, max_bytes: usize) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(directory.join("binaries"))?;
+        fs::create_dir_all(directory.join("projects"))?;
+
+        let mut projects = BTreeMap::new();
+        for entry in fs::read_dir(directory.join("projects"))? {
+            let path = entry?.path();
+            let Some(id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+                .filter(|id| is_id(id))
+            else {
+                continue;
+            };
+            let project = Project::load(&path)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            projects.insert(id.to_string(), project);
+        }
+
+        Ok(Store {
+            projects: Mutex::new(projects),
+            directory: Some(directory),
+            ..Self::new(max_bytes)
+        })
+    }
+
+    /// Keeps the binary and returns its ID. Storing the same bytes again
+    /// returns the same ID.
+    pub fn insert(&self, bytes: Vec<u8>) -> io::Result<String> {
+        let id = sha256(&bytes);
+        if let Some(path) = self.binary_path(&id) {
+            if !path.exists() {
+                // Written under another name first, so that a crash can't
+                // leave half a binary behind under its ID
+                let partial = path.with_extension("partial");
+                fs::write(&partial, &bytes)?;
+                fs::rename(partial, path)?;
+            }
+        }
+
+        self.cache(&id, Arc::new(bytes));
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Vec<u8>>> {
+        if !is_id(id) {
+            return None;
+        }
+        if let Some(binary) = self.lock_cache().binaries.get(id) {
+            return Some(binary.clone());
+        }
+
+        let binary = Arc::new(fs::read(self.binary_path(id)?).ok()?);
+        self.cache(id, binary.clone());
+        Some(binary)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        match self.binary_path(id) {
+            Some(path) => path.exists(),
+            None => is_id(id) && self.lock_cache().binaries.contains_key(id),
+        }
+    }
+
+    /// Removes the binary along with its project, returns whether there was
+    /// a binary
+    pub fn remove(&self, id: &str) -> io::Result<bool> {
+        let mut removed = false;
+        if let Some(path) = self.binary_path(id) {
+            match fs::remove_file(path) {
+                Ok(()) => removed = true,
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                Err(_) => {}
+            }
+        }
+
+        let mut cache = self.lock_cache();
+        if let Some(binary) = cache.binaries.remove(id) {
+            cache.bytes -= binary.len();
+            cache.order.retain(|cached| cached != id);
+            removed = true;
+        }
+        drop(cache);
+
+        self.remove_project(id)?;
+        Ok(removed)
+    }
+
+    /// Projects by the ID of their binary
+    pub fn projects(&self) -> BTreeMap<String, Project> {
+        self.lock_projects().clone()
+    }
+
+    pub fn project(&self, id: &str) -> Option<Project> {
+        self.lock_projects().get(id).cloned()
+    }
+
+    /// Saves the project of a binary, replacing the one it had
+    pub fn set_project(&self, id: &str, project: Project) -> io::Result<()> {
+        if let Some(path) = self.project_path(id) {
+            let partial = path.with_extension("partial");
+            fs::write(&partial, project.to_json() + "\n")?;
+            fs::rename(partial, path)?;
+        }
+        self.lock_projects().insert(id.to_string(), project);
+        Ok(())
+    }
+
+    /// Removes the project of a binary, returns whether there was one
+    pub fn remove_project(&self, id: &str) -> io::Result<bool> {
+        if let Some(path) = self.project_path(id) {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(self.lock_projects().remove(id).is_some())
+    }
+
+    /// Whether binaries can be kept, which in memory they always can but the
+    /// directory can go missing, say when a volume is unmounted
+    pub fn is_available(&self) -> bool {
+        self.directory.as_ref().is_none_or(|directory| {
+            ["binaries", "projects"]
+                .iter()
+                .all(|subdirectory| directory.join(subdirectory).is_dir())
+        })
+    }
+
+    fn cache(&self, id: &str, binary: Arc<Vec<u8>>) {
+        let mut cache = self.lock_cache();
+        if cache.binaries.contains_key(id) {
+            return;
+        }
+
+        cache.bytes += binary.len();
+        cache.binaries.insert(id.to_string(), binary);
+        cache.order.push_back(id.to_string());
+
+        // The binary that was just added is kept even when it alone is over
+        let mut evicted = vec![];
+        while cache.bytes > self.max_bytes && cache.order.len() > 1 {
+            let oldest = cache.order.pop_front().expect("checked above");
+            if let Some(binary) = cache.binaries.remove(&oldest) {
+                cache.bytes -= binary.len();
+            }
+            evicted.push(oldest);
+        }
+        drop(cache);
+
+        // Without a directory the binary is gone for good, and a project
+        // without its binary can't be loaded anymore
+        if self.directory.is_none() {
+            let mut projects = self.lock_projects();
+            for id in evicted {
+                projects.remove(&id);
+            }
+        }
+    }
+
+    fn binary_path(&self, id: &str) -> Option<PathBuf> {
+        let directory = self.directory.as_ref().filter(|_| is_id(id))?;
+        Some(directory.join("binaries").join(id))
+    }
+
+    fn project_path(&self, id: &str) -> Option<PathBuf> {
+        let directory = self.directory.as_ref().filter(|_| is_id(id))?;
+        Some(directory.join("projects").join(format!("{}.json", id)))
+    }
+
+    fn lock_cache(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache.lock().expect("store lock is never poisoned")
+    }
+
+    fn lock_projects(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Project>> {
+        self.projects.lock().expect("store lock is never poisoned")
+    }
+}
+
+/// IDs are lowercase SHA-256 hex, which also keeps them from naming paths
+/// outside the directory
+fn is_id(id: &str) -> bool {
+    id.len() == 64
+        && id
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_oldest_are_dropped() {
+        let store = Store::new(4);
+        let first = store.insert(vec![1, 2]).unwrap();
+        assert_eq!(store.insert(vec![1, 2]).unwrap(), first);
+        let second = store.insert(vec![3, 4]).unwrap();
+        assert_eq!(store.get(&first).unwrap().as_slice(), [1, 2]);
+
+        let third = store.insert(vec![5]).unwrap();
+        assert_eq!(store.get(&first), None);
+        assert!(store.get(&second).is_some() && store.get(&third).is_some());
+
+        assert!(store.remove(&second).unwrap());
+        assert!(!store.remove(&second).unwrap());
+        assert_eq!(store.get(&second), None);
+        let fourth = store.insert(vec![6, 7, 8]).unwrap();
+        assert!(store.get(&third).is_some() && store.get(&fourth).is_some());
+
+        // Projects go with their binaries
+        store
+            .set_project(&third, Project::for_input(&third, &[5]))
+            .unwrap();
+        store.insert(vec![9, 10]).unwrap();
+        assert_eq!(store.get(&third), None);
+        assert_eq!(store.project(&third), None);
+        assert!(store.projects().is_empty());
+    }
+
+    #[test]
+    fn test_directory() {
+        let directory = std::env::temp_dir().join(format!("store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let store = Store::open(&directory, 0).unwrap();
+        let id = store.insert(vec![0xea, 0x60]).unwrap();
+        let mut project = Project::for_input(&id, &[0xea, 0x60]);
+        project.labels.insert(1, "done".into());
+        store.set_project(&id, project.clone()).unwrap();
+        assert!(store.get("../../etc/passwd").is_none());
+
+        let reopened = Store::open(&directory, 0).unwrap();
+        assert_eq!(reopened.get(&id).unwrap().as_slice(), [0xea, 0x60]);
+        assert_eq!(reopened.project(&id), Some(project.clone()));
+
+        assert!(reopened.remove_project(&id).unwrap());
+        assert!(Store::open(&directory, 0).unwrap().projects().is_empty());
+
+        reopened.set_project(&id, project).unwrap();
+        assert!(reopened.remove(&id).unwrap());
+        let reopened = Store::open(&directory, 0).unwrap();
+        assert!(!reopened.contains(&id) && reopened.projects().is_empty());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
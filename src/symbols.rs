@@ -0,0 +1,102 @@
+use std::{error::Error, fmt::Display};
+
+use crate::parse_number;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SymbolError {
+    /// One-indexed line of the symbol file
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for SymbolError {}
+
+/// Reads (address, name) pairs from a symbol file. Understands VICE label
+/// files (`al C:1000 .name`, which ld65 also writes) and assembler style
+/// `name = $1000` or `name equ $1000` lines. Lines starting with `;` or `#`
+/// are comments.
+pub fn parse_symbols(text: &str) -> Result<Vec<(usize, String)>, SymbolError> {
+    let mut symbols = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |message: String| SymbolError {
+            line: index + 1,
+            message,
+        };
+
+        let (name, value) = if let Some(rest) = line.strip_prefix("al ") {
+            let (address, name) = rest
+                .trim()
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error("expected an address and a name".into()))?;
+            // Memory space prefix, such as C: for the computer
+            let address = address
+                .split_once(':')
+                .map_or(address, |(_, address)| address);
+            (name.trim().trim_start_matches('.'), format!("${}", address))
+        } else if let Some((name, value)) = line
+            .split_once('=')
+            .or_else(|| line.split_once(" equ "))
+            .or_else(|| line.split_once(" EQU "))
+        {
+            (name.trim(), value.trim().to_string())
+        } else {
+            return Err(error(format!("unrecognised symbol '{}'", line)));
+        };
+
+        let address =
+            parse_number(&value).ok_or_else(|| error(format!("invalid address '{}'", value)))?;
+        if name.is_empty() {
+            return Err(error("missing name".into()));
+        }
+
+        symbols.push((address, name.to_string()));
+    }
+
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let text = "
+            ; VICE
+            al C:ffd2 .CHROUT
+            al 00C000 .start
+            # Assembler
+            border = $D020
+            ptr equ 251
+        ";
+
+        assert_eq!(
+            parse_symbols(text),
+            Ok(vec![
+                (0xffd2, "CHROUT".into()),
+                (0xc000, "start".into()),
+                (0xd020, "border".into()),
+                (0xfb, "ptr".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_error_line() {
+        let error = parse_symbols("a = $10\nnonsense\n").unwrap_err();
+
+        assert_eq!(error.line, 2);
+    }
+}